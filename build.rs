@@ -1,3 +1,87 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX_HTML: &str = "src/http_ws_server_page.html";
+// Elements whose content is copied as-is: code, and text where whitespace matters
+const VERBATIM_TAGS: [&str; 4] = ["script", "style", "pre", "textarea"];
+
 fn main() {
     embuild::espidf::sysenv::output();
+
+    // Build metadata served on GET /version
+    let git_hash = command_output("git", &["rev-parse", "--short", "HEAD"])
+        .unwrap_or_else(|| "unknown".to_string());
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    let build_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    println!("cargo:rustc-env=GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=RUSTC_VERSION={rustc_version}");
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={build_timestamp}");
+    // Pick up new commits and checkouts
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    // Minified index page, embedded by config.rs
+    let page = std::fs::read_to_string(INDEX_HTML).expect("index page is readable");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    std::fs::write(
+        Path::new(&out_dir).join("index_min.html"),
+        minify_html(&page),
+    )
+    .expect("minified index page is writable");
+    println!("cargo:rerun-if-changed={INDEX_HTML}");
+}
+
+/// Strip comments and collapse whitespace runs to a single space, leaving the
+/// content of `VERBATIM_TAGS` elements untouched
+fn minify_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html.trim();
+    while let Some(c) = rest.chars().next() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            // An unterminated comment runs to the end of the page
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+        } else if let Some(tag) = verbatim_tag(rest) {
+            // Copy up to the closing tag, which is then handled like any other
+            let close = format!("</{tag}");
+            let end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if c.is_ascii_whitespace() {
+            if !out.ends_with(' ') {
+                out.push(' ');
+            }
+            rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+/// The verbatim tag `html` starts with, if it opens one
+fn verbatim_tag(html: &str) -> Option<&'static str> {
+    let name = html.strip_prefix('<')?;
+    VERBATIM_TAGS.into_iter().find(|tag| {
+        name.get(..tag.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(tag))
+            && name[tag.len()..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace())
+    })
+}
+
+/// Trimmed stdout of a command, `None` if it could not be run or failed
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_string())
 }
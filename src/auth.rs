@@ -0,0 +1,189 @@
+//! HTTP Basic Authentication for admin endpoints
+
+use embedded_svc::io::Write;
+use esp_idf_svc::{
+    http::server::{EspHttpConnection, Request},
+    sys::EspError,
+};
+use log::*;
+
+use crate::config::{ADMIN_PASS, ADMIN_TOKEN, ADMIN_USER};
+use crate::error::ServerError;
+use crate::request_log;
+use crate::server::start_response;
+use crate::utils::get_request_header;
+
+// Realm shown by browsers in the login prompt
+const REALM: &str = "esp32-admin";
+
+/// Check the `Authorization: Basic ...` header of a request
+pub fn http_basic_auth(
+    req: &Request<&mut EspHttpConnection<'_>>,
+    username: &str,
+    password: &str,
+) -> bool {
+    get_request_header(req, "Authorization")
+        .is_some_and(|header| check_credentials(header, username, password))
+}
+
+/// Wrap a handler so it only runs for requests carrying the admin credentials
+/// Other requests get 401 with a `WWW-Authenticate` challenge
+pub fn require_auth<F>(
+    handler: F,
+) -> impl for<'r> Fn(Request<&mut EspHttpConnection<'r>>) -> Result<(), EspError> + Send + 'static
+where
+    F: for<'r> Fn(Request<&mut EspHttpConnection<'r>>) -> Result<(), EspError> + Send + 'static,
+{
+    move |req| {
+        if http_basic_auth(&req, ADMIN_USER, ADMIN_PASS) {
+            return handler(req);
+        }
+        warn!("Rejecting unauthenticated request to {}", req.uri());
+        let err = ServerError::Unauthorized;
+        let challenge = format!("Basic realm=\"{}\"", REALM);
+        let body = format!(
+            r#"{{"error":"{}","request_id":"{}"}}"#,
+            err,
+            request_log::request_id()
+        );
+        start_response(
+            req,
+            err.status(),
+            Some(err.reason()),
+            &[
+                ("Content-Type", "application/json"),
+                ("WWW-Authenticate", challenge.as_str()),
+            ],
+        )?
+        .write_all(body.as_bytes())
+        .map_err(|e| ServerError::from(e).into_esp_error())
+    }
+}
+
+/// Check a token sent in a request body against `ADMIN_TOKEN`
+pub fn check_admin_token(token: &str) -> bool {
+    constant_time_eq(token.as_bytes(), ADMIN_TOKEN.as_bytes())
+}
+
+/// Check an `Authorization` header value against the expected credentials
+fn check_credentials(header: &str, username: &str, password: &str) -> bool {
+    let Some((scheme, encoded)) = header.trim().split_once(' ') else {
+        return false;
+    };
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return false;
+    }
+    let Some(decoded) = base64_decode(encoded.trim()) else {
+        return false;
+    };
+    let Some(colon) = decoded.iter().position(|&b| b == b':') else {
+        return false;
+    };
+    // Compare both halves so the timing doesn't reveal which one was wrong
+    let user_ok = constant_time_eq(&decoded[..colon], username.as_bytes());
+    let pass_ok = constant_time_eq(&decoded[colon + 1..], password.as_bytes());
+    user_ok & pass_ok
+}
+
+/// Compare two byte strings in time that depends only on their lengths
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+    for i in 0..a.len().max(b.len()) {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= (x ^ y) as usize;
+    }
+    diff == 0
+}
+
+/// Decode standard base64 with padding, as used by Basic Authentication
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let bytes = input.as_bytes();
+    if bytes.len() % 4 != 0 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len() / 4 * 3);
+    for (i, chunk) in bytes.chunks_exact(4).enumerate() {
+        let last = i == bytes.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &c in &chunk[..4 - padding] {
+            n = (n << 6) | value(c)?;
+        }
+        n <<= 6 * padding as u32;
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert_eq!(base64_decode("YQ==").unwrap(), b"a");
+        assert_eq!(base64_decode("YWI=").unwrap(), b"ab");
+        assert_eq!(base64_decode("YWJj").unwrap(), b"abc");
+        assert_eq!(base64_decode("YWRtaW46c2VjcmV0").unwrap(), b"admin:secret");
+    }
+
+    #[test]
+    fn test_base64_decode_rejects_invalid() {
+        assert!(base64_decode("YWJ").is_none());
+        assert!(base64_decode("YW!j").is_none());
+        assert!(base64_decode("YQ==YWJj").is_none());
+        assert!(base64_decode("Y===").is_none());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"x"));
+    }
+
+    #[test]
+    fn test_check_credentials() {
+        // admin:secret
+        const HEADER: &str = "Basic YWRtaW46c2VjcmV0";
+        assert!(check_credentials(HEADER, "admin", "secret"));
+        assert!(check_credentials(
+            " basic  YWRtaW46c2VjcmV0 ",
+            "admin",
+            "secret"
+        ));
+        assert!(!check_credentials(HEADER, "admin", "other"));
+        assert!(!check_credentials(HEADER, "root", "secret"));
+        assert!(!check_credentials(
+            "Bearer YWRtaW46c2VjcmV0",
+            "admin",
+            "secret"
+        ));
+        // admin (no colon)
+        assert!(!check_credentials("Basic YWRtaW4=", "admin", ""));
+    }
+
+    #[test]
+    fn test_check_admin_token() {
+        assert!(check_admin_token(ADMIN_TOKEN));
+        assert!(!check_admin_token(""));
+        assert!(!check_admin_token(&format!("{}x", ADMIN_TOKEN)));
+    }
+}
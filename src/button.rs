@@ -0,0 +1,238 @@
+//! BOOT button input on GPIO0
+//!
+//! Pressing the button while guessing games are running sends every player
+//! the range their secret is still in. With no games running it cycles the
+//! OLED through server stats instead. Holding it for `FACTORY_RESET_HOLD_MS`
+//! wipes the provisioned Wi-Fi credentials and reboots into setup.
+//! Without the `game` or `oled` feature the respective action is skipped.
+//!
+//! On the ESP32-C3 DevKit the on-board BOOT button is wired to GPIO9, so
+//! there a push button from GPIO0 to GND is needed.
+
+use anyhow::Result;
+use core::num::NonZeroU32;
+#[cfg(feature = "game")]
+use embedded_svc::ws::FrameType;
+use esp_idf_svc::{
+    hal::{
+        delay::{FreeRtos, BLOCK},
+        gpio::{Gpio0, InterruptType, PinDriver, Pull},
+        reset::restart,
+        task::notification::Notification,
+    },
+    nvs::EspDefaultNvsPartition,
+};
+use log::*;
+#[cfg(feature = "game")]
+use std::collections::BTreeMap;
+#[cfg(any(feature = "game", feature = "oled"))]
+use std::sync::Arc;
+
+use crate::config::{BUTTON_DEBOUNCE_MS, FACTORY_RESET_HOLD_MS};
+#[cfg(feature = "game")]
+use crate::guessing_game::bounds_hint;
+#[cfg(feature = "game")]
+use crate::heartbeat::Heartbeat;
+#[cfg(feature = "oled")]
+use crate::oled::OledDisplay;
+use crate::server::factory_reset;
+#[cfg(any(feature = "game", feature = "oled"))]
+use crate::state::SharedState;
+#[cfg(feature = "oled")]
+use crate::utils::format_duration;
+use crate::utils::now_ms;
+
+const BUTTON_STACK_SIZE: usize = 4096;
+// Number of pages `stats_page` cycles through
+const STATS_PAGES: usize = 3;
+
+/// State the button needs to act on a press
+pub struct ButtonContext {
+    /// Running games and their range, and the open WebSocket count
+    #[cfg(any(feature = "game", feature = "oled"))]
+    pub state: SharedState,
+    #[cfg(feature = "game")]
+    pub heartbeat: Arc<Heartbeat>,
+    #[cfg(feature = "oled")]
+    pub oled: Option<Arc<OledDisplay>>,
+    /// Partition holding the provisioned credentials, for the factory reset
+    pub nvs: EspDefaultNvsPartition,
+}
+
+/// Server stats shown on the OLED
+#[cfg(feature = "oled")]
+struct Stats {
+    sessions: u32,
+    free_heap: u32,
+    uptime_ms: u64,
+}
+
+/// Spawn the task waiting for button presses
+pub fn spawn(pin: Gpio0, context: ButtonContext) -> Result<()> {
+    let mut button = PinDriver::input(pin)?;
+    // The button pulls the pin to GND when pressed
+    button.set_pull(Pull::Up)?;
+    button.set_interrupt_type(InterruptType::NegEdge)?;
+
+    std::thread::Builder::new()
+        .name("button".into())
+        .stack_size(BUTTON_STACK_SIZE)
+        .spawn(move || {
+            // The notification targets the task that creates it, so this
+            // has to happen on the button thread
+            let notification = Notification::new();
+            let notifier = notification.notifier();
+            // SAFETY: the notification lives as long as this thread, which never exits
+            let subscribed = unsafe {
+                button.subscribe(move || {
+                    notifier.notify_and_yield(NonZeroU32::MIN);
+                })
+            };
+            if let Err(e) = subscribed {
+                error!("Failed to subscribe to button interrupt: {:?}", e);
+                return;
+            }
+
+            let mut page = 0;
+            loop {
+                // Interrupts are disabled again after each trigger
+                if let Err(e) = button.enable_interrupt() {
+                    error!("Failed to enable button interrupt: {:?}", e);
+                    return;
+                }
+                notification.wait(BLOCK);
+
+                // Ignore bounces and glitches that are gone after the debounce delay
+                FreeRtos::delay_ms(BUTTON_DEBOUNCE_MS);
+                if button.is_high() {
+                    continue;
+                }
+
+                // Wait for the release, a long enough hold resets instead
+                let pressed_at = now_ms();
+                while button.is_low() && now_ms() - pressed_at < FACTORY_RESET_HOLD_MS as u64 {
+                    FreeRtos::delay_ms(BUTTON_DEBOUNCE_MS);
+                }
+                if button.is_low() {
+                    reset_and_restart(&context);
+                    continue;
+                }
+
+                debug!("Button pressed");
+                if !send_hints(&context) {
+                    show_stats(&context, page);
+                    page = (page + 1) % STATS_PAGES;
+                }
+            }
+        })?;
+
+    info!("Button handler started on GPIO0");
+    Ok(())
+}
+
+/// Send each running game its secret range
+/// Returns false if no game is running
+#[cfg(feature = "game")]
+fn send_hints(context: &ButtonContext) -> bool {
+    let state = context.state.lock();
+    let hints: BTreeMap<i32, String> = state
+        .sessions
+        .iter()
+        .filter(|(_, session)| !session.game.is_done())
+        .map(|(&id, session)| {
+            let (low, high) = session
+                .game
+                .secret_bounds(state.game_config.min, state.game_config.max);
+            (id, bounds_hint(low, high))
+        })
+        .collect();
+    drop(state);
+    if hints.is_empty() {
+        return false;
+    }
+
+    // senders() hands out clones, so no lock is held while sending
+    for (session, mut sender) in context.heartbeat.senders() {
+        let Some(hint) = hints.get(&session) else {
+            continue;
+        };
+        match sender.send(FrameType::Text(false), hint.as_bytes()) {
+            Ok(()) => info!("Sent hint `{}` to session {}", hint, session),
+            Err(e) => warn!("Failed to send hint to session {}: {:?}", session, e),
+        }
+    }
+    true
+}
+
+#[cfg(not(feature = "game"))]
+fn send_hints(_context: &ButtonContext) -> bool {
+    false
+}
+
+/// Show one page of server stats on the OLED
+#[cfg(feature = "oled")]
+fn show_stats(context: &ButtonContext, page: usize) {
+    let Some(oled) = &context.oled else {
+        info!("Button pressed with no game running and no OLED attached");
+        return;
+    };
+    let stats = Stats {
+        sessions: context.state.open_ws_sessions(),
+        free_heap: unsafe { esp_idf_svc::sys::esp_get_free_heap_size() },
+        uptime_ms: now_ms(),
+    };
+    if let Err(e) = oled.display_message(&stats_page(page, &stats)) {
+        warn!("Failed to show stats on OLED: {:?}", e);
+    }
+}
+
+#[cfg(not(feature = "oled"))]
+fn show_stats(_context: &ButtonContext, _page: usize) {
+    info!("Button pressed with no game running and no OLED support");
+}
+
+/// Wipe the provisioned credentials and reboot into the setup AP
+fn reset_and_restart(context: &ButtonContext) {
+    warn!(
+        "Button held for {} ms, factory reset",
+        FACTORY_RESET_HOLD_MS
+    );
+    if let Err(e) = factory_reset(context.nvs.clone()) {
+        error!("Factory reset failed: {:?}", e);
+        return;
+    }
+    #[cfg(feature = "oled")]
+    if let Some(oled) = &context.oled {
+        if let Err(e) = oled.display_message("Factory reset, rebooting") {
+            warn!("Failed to show factory reset on OLED: {:?}", e);
+        }
+    }
+    restart();
+}
+
+#[cfg(feature = "oled")]
+fn stats_page(page: usize, stats: &Stats) -> String {
+    match page % STATS_PAGES {
+        0 => format!("Sessions: {}", stats.sessions),
+        1 => format!("Heap: {} KB", stats.free_heap / 1024),
+        _ => format!("Up: {}", format_duration(stats.uptime_ms / 1000)),
+    }
+}
+
+#[cfg(all(test, feature = "oled"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_pages_cycle() {
+        let stats = Stats {
+            sessions: 3,
+            free_heap: 150 * 1024,
+            uptime_ms: 90_500,
+        };
+        assert_eq!(stats_page(0, &stats), "Sessions: 3");
+        assert_eq!(stats_page(1, &stats), "Heap: 150 KB");
+        assert_eq!(stats_page(2, &stats), "Up: 1m 30s");
+        assert_eq!(stats_page(STATS_PAGES, &stats), "Sessions: 3");
+    }
+}
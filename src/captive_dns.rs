@@ -0,0 +1,157 @@
+//! Captive portal DNS server
+//!
+//! Answers every A query with the access point's address, so a device that
+//! joins the AP and looks up its connectivity check host ends up talking to
+//! this server. The DHCP server on the AP hands out its own address as the
+//! DNS server, so no client configuration is needed.
+
+use anyhow::Result;
+use log::*;
+use std::net::{Ipv4Addr, UdpSocket};
+
+const DNS_PORT: u16 = 53;
+const DNS_STACK_SIZE: usize = 4096;
+// Plain DNS over UDP never exceeds this without EDNS
+const MAX_PACKET_LEN: usize = 512;
+// Short TTL so clients forget the fake answers soon after leaving the AP
+const ANSWER_TTL_S: u32 = 60;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+/// Spawn the task answering DNS queries with `ip`
+pub fn spawn(ip: Ipv4Addr) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DNS_PORT))?;
+
+    std::thread::Builder::new()
+        .name("captive_dns".into())
+        .stack_size(DNS_STACK_SIZE)
+        .spawn(move || {
+            let mut buf = [0u8; MAX_PACKET_LEN];
+            loop {
+                let (len, peer) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("DNS receive failed: {:?}", e);
+                        continue;
+                    }
+                };
+                let Some(response) = build_response(&buf[..len], ip) else {
+                    debug!("Ignoring malformed DNS packet from {}", peer);
+                    continue;
+                };
+                if let Err(e) = socket.send_to(&response, peer) {
+                    warn!("DNS reply to {} failed: {:?}", peer, e);
+                }
+            }
+        })?;
+
+    info!("Captive portal DNS answering every query with {}", ip);
+    Ok(())
+}
+
+/// Build the answer to a DNS query, pointing every A record at `ip`
+/// Returns `None` for packets that are not a standard query
+fn build_response(query: &[u8], ip: Ipv4Addr) -> Option<Vec<u8>> {
+    if query.len() < HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let is_response = flags & 0x8000 != 0;
+    let opcode = (flags >> 11) & 0xf;
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    if is_response || opcode != 0 || questions == 0 {
+        return None;
+    }
+
+    // Only the first question is answered, clients send one anyway
+    let mut pos = HEADER_LEN;
+    loop {
+        let label_len = *query.get(pos)? as usize;
+        pos += 1;
+        if label_len == 0 {
+            break;
+        }
+        // Compression pointers are not valid in a question name
+        if label_len & 0xc0 != 0 {
+            return None;
+        }
+        pos += label_len;
+    }
+    let question_end = pos + 4;
+    let question = query.get(HEADER_LEN..question_end)?;
+    let qtype = u16::from_be_bytes([query[pos], query[pos + 1]]);
+    let qclass = u16::from_be_bytes([query[pos + 2], query[pos + 3]]);
+    let answer = qclass == CLASS_IN && (qtype == TYPE_A || qtype == TYPE_ANY);
+
+    let mut response = Vec::with_capacity(question_end + 16);
+    response.extend_from_slice(&query[..2]);
+    // Response, authoritative, recursion desired copied from the query, recursion available
+    response.extend_from_slice(&(0x8480 | (flags & 0x0100)).to_be_bytes());
+    response.extend_from_slice(&1u16.to_be_bytes());
+    response.extend_from_slice(&(answer as u16).to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(question);
+    if answer {
+        // Name is a pointer to the question right after the header
+        response.extend_from_slice(&[0xc0, HEADER_LEN as u8]);
+        response.extend_from_slice(&TYPE_A.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&ANSWER_TTL_S.to_be_bytes());
+        response.extend_from_slice(&4u16.to_be_bytes());
+        response.extend_from_slice(&ip.octets());
+    }
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AP_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 1);
+
+    /// Standard query for `example.com` with the given type
+    fn query(qtype: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(b"\x07example\x03com\x00");
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_a_query_answered_with_ap_ip() {
+        let request = query(TYPE_A);
+        let response = build_response(&request, AP_IP).unwrap();
+        // ID kept, QR/AA/RD/RA set, one question and one answer
+        assert_eq!(
+            &response[..12],
+            &[0x12, 0x34, 0x85, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(&response[12..request.len()], &request[12..]);
+        assert_eq!(&response[response.len() - 4..], &[192, 168, 71, 1]);
+    }
+
+    #[test]
+    fn test_other_types_get_empty_answer() {
+        // AAAA
+        let request = query(28);
+        let response = build_response(&request, AP_IP).unwrap();
+        assert_eq!(&response[6..8], &[0, 0]);
+        assert_eq!(response.len(), request.len());
+    }
+
+    #[test]
+    fn test_rejects_malformed() {
+        assert!(build_response(&[0; 5], AP_IP).is_none());
+        // Truncated question
+        let request = query(TYPE_A);
+        assert!(build_response(&request[..request.len() - 2], AP_IP).is_none());
+        // Responses are not answered
+        let mut response = query(TYPE_A);
+        response[2] |= 0x80;
+        assert!(build_response(&response, AP_IP).is_none());
+    }
+}
@@ -0,0 +1,126 @@
+//! Wi-Fi channel auto-selection for the access point
+
+use core::time::Duration;
+use embedded_svc::wifi::{ClientConfiguration, Configuration};
+use esp_idf_svc::{
+    sys::EspError,
+    wifi::{
+        config::{ScanConfig, ScanType},
+        BlockingWifi, EspWifi,
+    },
+};
+use log::*;
+
+use crate::config::{CHANNEL, CHANNEL_SCAN_DWELL_MS, MAX_AUTO_CHANNEL};
+
+/// Scan for nearby networks and return the channel with the fewest of them
+/// Falls back to `CHANNEL` if the scan fails or finds nothing
+/// Leaves the Wi-Fi driver stopped so it can be reconfigured as an AP
+pub fn pick_least_congested_channel(wifi: &mut BlockingWifi<EspWifi<'static>>) -> u8 {
+    info!(
+        "Scanning channels 1-{} for other networks...",
+        MAX_AUTO_CHANNEL
+    );
+    let scan = scan_channels(wifi);
+    if let Err(e) = wifi.stop() {
+        warn!("Failed to stop Wi-Fi after channel scan: {:?}", e);
+    }
+
+    match scan {
+        Ok(channels) if !channels.is_empty() => {
+            let counts = count_networks(&channels);
+            let channel = least_congested(&counts, CHANNEL);
+            info!(
+                "Found {} networks, per channel: {:?}, picked channel {}",
+                channels.len(),
+                counts,
+                channel
+            );
+            channel
+        }
+        Ok(_) => {
+            info!("No other networks found, using channel {}", CHANNEL);
+            CHANNEL
+        }
+        Err(e) => {
+            warn!("Channel scan failed, using channel {}: {:?}", CHANNEL, e);
+            CHANNEL
+        }
+    }
+}
+
+/// Passive scan in STA mode, returning the channel of every network seen
+fn scan_channels(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<Vec<u8>, EspError> {
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+    wifi.start()?;
+    let scan_config = ScanConfig {
+        scan_type: ScanType::Passive(Duration::from_millis(CHANNEL_SCAN_DWELL_MS)),
+        show_hidden: true,
+        ..Default::default()
+    };
+    wifi.start_scan(&scan_config, true)?;
+    Ok(wifi
+        .get_scan_result()?
+        .iter()
+        .map(|ap| ap.channel)
+        .collect())
+}
+
+/// Number of networks on each channel, index 0 being channel 1
+fn count_networks(channels: &[u8]) -> [u32; MAX_AUTO_CHANNEL as usize] {
+    let mut counts = [0; MAX_AUTO_CHANNEL as usize];
+    for &channel in channels {
+        if (1..=MAX_AUTO_CHANNEL).contains(&channel) {
+            counts[channel as usize - 1] += 1;
+        }
+    }
+    counts
+}
+
+/// Channel with the lowest count, preferring `fallback` and then lower channels on ties
+fn least_congested(counts: &[u32], fallback: u8) -> u8 {
+    let fallback_count = counts
+        .get(fallback as usize - 1)
+        .copied()
+        .unwrap_or(u32::MAX);
+    let (index, &min) = counts
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, count)| count)
+        .expect("at least one channel");
+    if fallback_count == min {
+        fallback
+    } else {
+        index as u8 + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_networks_ignores_out_of_range() {
+        let counts = count_networks(&[1, 1, 6, 0, 14, MAX_AUTO_CHANNEL]);
+        assert_eq!(counts[0], 2);
+        assert_eq!(counts[5], 1);
+        assert_eq!(counts[MAX_AUTO_CHANNEL as usize - 1], 1);
+        assert_eq!(counts.iter().sum::<u32>(), 4);
+    }
+
+    #[test]
+    fn test_least_congested_picks_emptiest() {
+        let mut counts = [3; 13];
+        counts[4] = 1;
+        assert_eq!(least_congested(&counts, 11), 5);
+    }
+
+    #[test]
+    fn test_least_congested_prefers_fallback_on_tie() {
+        let mut counts = [0; 13];
+        counts[0] = 2;
+        assert_eq!(least_congested(&counts, 11), 11);
+        counts[10] = 1;
+        assert_eq!(least_congested(&counts, 11), 2);
+    }
+}
@@ -1,4 +1,19 @@
 //! Configuration constants and environment variable handling
+//!
+//! Every station joining the access point can open WebSocket sessions, so
+//! `MAX_AP_STATIONS` is kept at or below `MAX_WS_SESSIONS`: with one game
+//! per station, a full AP still leaves every player a session.
+
+#[cfg(feature = "oled")]
+use ssd1306::prelude::DisplayRotation;
+#[cfg(feature = "game")]
+use std::{collections::BTreeMap, ffi::CStr};
+
+#[cfg(feature = "game")]
+use crate::guessing_game::Difficulty;
+#[cfg(feature = "game")]
+use crate::utils::{extract_json_string, extract_json_u32};
+use crate::utils::fnv1a_str;
 
 macro_rules! get_env_or_default {
     ($env:literal, $default:literal) => {
@@ -10,18 +25,462 @@ macro_rules! get_env_or_default {
 }
 
 pub const SSID: &str = get_env_or_default!("WIFI_SSID", "ESP32-Game");
+// Build-time Wi-Fi credentials, used on boards nothing was provisioned on yet
 pub const PASSWORD: &str = get_env_or_default!("WIFI_PASS", "password123");
+// Optional upstream network to join while hosting the AP, empty to run AP only
+pub const UPSTREAM_SSID: &str = get_env_or_default!("UPSTREAM_SSID", "");
+pub const UPSTREAM_PASS: &str = get_env_or_default!("UPSTREAM_PASS", "");
+// Without WIFI_PASS or UPSTREAM_SSID set at build time, unprovisioned boards
+// serve the setup access point instead
+pub const BUILD_CREDENTIALS: bool =
+    option_env!("WIFI_PASS").is_some() || option_env!("UPSTREAM_SSID").is_some();
+// Append the last 3 bytes of the AP MAC to `SSID` (`ESP32-Game-AABBCC`), so
+// boards running the same firmware can be told apart
+pub const MAC_SUFFIX_SSID: bool = true;
+// Open access point served until Wi-Fi credentials are provisioned
+pub const SETUP_SSID: &str = "ESP32-Setup";
+// HTTP Basic Authentication credentials for admin endpoints (POST /ota, /admin/*)
+pub const ADMIN_USER: &str = get_env_or_default!("ADMIN_USER", "admin");
+pub const ADMIN_PASS: &str = get_env_or_default!("ADMIN_PASS", "change-me");
+// Token accepted in the body of POST /reboot, the admin password that also guards /ota
+pub const ADMIN_TOKEN: &str = ADMIN_PASS;
+// Hostname advertised over mDNS, reachable as `<MDNS_HOSTNAME>.local`
+pub const MDNS_HOSTNAME: &str = get_env_or_default!("MDNS_HOSTNAME", "esp32-game");
 
-pub static INDEX_HTML: &str = include_str!("http_ws_server_page.html");
+// http_ws_server_page.html without comments and redundant whitespace, see build.rs
+const INDEX_HTML_MIN: &str = include_str!(concat!(env!("OUT_DIR"), "/index_min.html"));
+pub static INDEX_HTML: &str = INDEX_HTML_MIN;
+// Quoted FNV-1a hash of the index page, changes whenever the page does
+pub const INDEX_HTML_ETAG: &str =
+    match core::str::from_utf8(&etag_bytes(fnv1a_str(INDEX_HTML_MIN))) {
+        Ok(etag) => etag,
+        Err(_) => panic!("ETag is not valid UTF-8"),
+    };
+// Build metadata for GET /version, the values come from build.rs
+pub static VERSION_JSON: &str = concat!(
+    r#"{"version":""#,
+    env!("CARGO_PKG_VERSION"),
+    r#"","git_hash":""#,
+    env!("GIT_HASH"),
+    r#"","build_timestamp":"#,
+    env!("BUILD_TIMESTAMP"),
+    r#","rustc":""#,
+    env!("RUSTC_VERSION"),
+    r#""}"#
+);
+// Served for unknown paths; the index page is too big to reuse here
+pub const NOT_FOUND_HTML: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>404 Not Found</title></head>\
+<body style=\"font-family: sans-serif; text-align: center\"><h1>404 Not Found</h1>\
+<p>Nothing lives here. <a href=\"/\">Back to the game</a></p></body></html>";
 
-// Max payload length for guessing game
-pub const MAX_LEN: usize = 8;
+// Max payload length for guessing game (room for a `{"guess": 100}` JSON message)
+pub const MAX_LEN: usize = 32;
 // Max payload length for OLED display messages (longer to allow full messages)
+#[cfg(feature = "oled")]
 pub const MAX_DISPLAY_LEN: usize = 256;
+// Second I2C address probed for the OLED, used by some SSD1306 modules instead of 0x3C
+#[cfg(feature = "oled")]
+pub const SSD1306_FALLBACK_ADDRESS: u8 = 0x3D;
+// How the OLED is mounted, `OLED_ROTATION=180` for upside down; text is laid
+// out for the rotated width and height
+#[cfg(feature = "oled")]
+pub const OLED_ROTATION: DisplayRotation =
+    parse_rotation(get_env_or_default!("OLED_ROTATION", "0"));
+// Delay between lines when scrolling long messages on the OLED
+#[cfg(feature = "oled")]
+pub const OLED_SCROLL_DELAY_MS: u32 = 800;
+// Top row of the guess progress bar, the last two rows of the 72x40 display
+#[cfg(all(feature = "game", feature = "oled"))]
+pub const OLED_PROGRESS_BAR_Y: i32 = 38;
+// Boot splash shown instead of the ready message: 1 bit per pixel in row-major
+// order, MSB first, e.g. `Some(include_bytes!("../splash.bin"))` for a 72x40 logo
+#[cfg(feature = "oled")]
+pub const SPLASH_BMP: Option<&[u8]> = None;
+#[cfg(feature = "oled")]
+pub const SPLASH_WIDTH: u8 = 72;
+#[cfg(feature = "oled")]
+pub const SPLASH_HEIGHT: u8 = 40;
 
 // Need lots of stack to parse JSON
 pub const STACK_SIZE: usize = 10240;
+// Routes the HTTP server can hold, ESP-IDF's default of 32 is too few with every feature on
+pub const MAX_URI_HANDLERS: usize = 48;
+// Stack for the short-lived thread delivering admin broadcasts
+#[cfg(feature = "game")]
+pub const BROADCAST_STACK_SIZE: usize = 4096;
+// Stack for the thread that waits out a reboot countdown
+pub const REBOOT_STACK_SIZE: usize = 4096;
 
+// Stations the access point accepts, ESP-IDF allows 10 which the C3's heap can't serve
+pub const MAX_AP_STATIONS: u8 = 4;
 // Wi-Fi channel, between 1 and 11
+// Used when the channel scan at startup fails or finds no other networks
 pub const CHANNEL: u8 = 11;
+// Highest channel considered by auto-selection, use 11 where 12-13 are not allowed (e.g. US)
+pub const MAX_AUTO_CHANNEL: u8 = 13;
+// Time spent listening for beacons on each channel during the scan
+pub const CHANNEL_SCAN_DWELL_MS: u64 = 120;
+// Networks listed by GET /wifi/scan, the strongest first
+#[cfg(feature = "scan")]
+pub const MAX_SCAN_RESULTS: usize = 20;
+// Access point beacon interval, in TUs of 1.024 ms, ESP-IDF allows 100-60000
+// Longer intervals let power-saving clients sleep longer between beacons, but
+// new clients take longer to find the AP and buffered frames wait longer
+pub const BEACON_INTERVAL_MS: u16 = 100;
+
+// TCP keepalive on WebSocket sockets, so NAT gateways don't drop idle connections:
+// probe after TCP_KEEPALIVE_IDLE_S of silence, every TCP_KEEPALIVE_INTVL_S, and close
+// the connection after TCP_KEEPALIVE_COUNT unanswered probes
+pub const TCP_KEEPALIVE_IDLE_S: i32 = 60;
+pub const TCP_KEEPALIVE_INTVL_S: i32 = 10;
+pub const TCP_KEEPALIVE_COUNT: i32 = 3;
+
+// Attempts and delay between them for ESP-IDF calls that fail transiently
+pub const ESP_RETRY_ATTEMPTS: u32 = 3;
+pub const ESP_RETRY_DELAY_MS: u32 = 500;
+
+// A task subscribed to the task watchdog resets the chip if it doesn't check
+// in for this long, see `WatchdogCheckpoint`
+pub const WATCHDOG_TIMEOUT_S: u32 = 30;
+
+// Interval between RSSI readings pushed on /events/rssi
+pub const RSSI_POLL_INTERVAL_MS: u64 = 1000;
+// Number of readings kept per station for GET /rssi/history
+pub const RSSI_HISTORY_LEN: usize = 60;
+// Stations whose readings are kept, one per station the AP accepts
+pub const MAX_HISTORY_STATIONS: usize = MAX_AP_STATIONS as usize;
+// Time window of GET /rssi/export.csv without a `duration_s`, and the max allowed
+pub const RSSI_EXPORT_DEFAULT_S: u64 = 60;
+pub const MAX_RSSI_EXPORT_S: u64 = 3600;
+// Kalman filter tuning for smoothed distance readings (variances in m^2)
+// Higher process noise follows movement faster, higher measurement noise smooths more
+pub const KALMAN_PROCESS_NOISE: f32 = 0.05;
+pub const KALMAN_MEASUREMENT_NOISE: f32 = 4.0;
+pub const KALMAN_INITIAL_ERROR: f32 = 1.0;
+// Weight of the newest distance in the moving average reported as `filtered_distance`
+pub const DISTANCE_EMA_ALPHA: f32 = 0.2;
+
+// Time the BOOT button has to stay pressed to count as a press
+pub const BUTTON_DEBOUNCE_MS: u32 = 50;
+// Holding the BOOT button this long wipes the provisioned Wi-Fi credentials
+pub const FACTORY_RESET_HOLD_MS: u32 = 5000;
+// Delay between answering POST /reset and rebooting into the factory defaults
+pub const RESET_REBOOT_DELAY_MS: u32 = 2000;
+// Status LED, GPIO8 has an on-board LED on many ESP32-C3 boards
+// Must not be the button (GPIO0) or one of the OLED's I2C pins (GPIO5, GPIO6)
+pub const LED_GPIO: i32 = 8;
+
+// Max concurrent guessing game sessions, each one costs heap
+pub const MAX_WS_SESSIONS: usize = 8;
+
+// Interval between WebSocket heartbeat pings
+#[cfg(feature = "game")]
+pub const WS_PING_INTERVAL_MS: u64 = 30_000;
+// Time a session has to answer a ping before it is considered stale
+#[cfg(feature = "game")]
+pub const WS_PONG_TIMEOUT_MS: u64 = 10_000;
+// WebSocket subprotocol /ws/guess clients ask for to get every reply as JSON
+#[cfg(feature = "game")]
+pub const GUESS_JSON_SUBPROTOCOL: &CStr = c"guess-json-v1";
+// Messages a /ws/guess session may send per second before it is closed
+#[cfg(feature = "game")]
+pub const WS_MAX_MSG_PER_SEC: u32 = 10;
+// Guessing game sessions without a message for this long are closed
+#[cfg(feature = "game")]
+pub const WS_IDLE_TIMEOUT_S: u64 = 120;
+
+// Guesses allowed per game unless changed through POST /config/game
+#[cfg(feature = "game")]
+pub const DEFAULT_MAX_GUESSES: u32 = 10;
+// Most rounds a /ws/guess session can be set to play, see `GameConfig::rounds`
+#[cfg(feature = "game")]
+pub const MAX_ROUNDS: u8 = 5;
+// Questions per /ws/quiz session
+pub const QUIZ_QUESTIONS: u32 = 10;
+// Letters in every /ws/wordguess word
+pub const WORD_LEN: usize = 5;
+const WORDS_SRC: &str = include_str!("words.txt");
+// Secret words for /ws/wordguess, one per line in words.txt
+pub static WORDS: &[&str] = &split_lines::<{ count_lines(WORDS_SRC) }>(WORDS_SRC);
+// Players per tournament round on /ws/tournament
+#[allow(dead_code)] // Available for the `tournament` feature
+pub const MAX_TOURNAMENT_PLAYERS: usize = 8;
+
+// Number of best scores kept on the leaderboard
+#[cfg(feature = "game")]
+pub const LEADERBOARD_LEN: usize = 10;
+
+// Per-IP HTTP rate limit: at most RATE_LIMIT_REQUESTS per RATE_LIMIT_WINDOW_MS
+pub const RATE_LIMIT_REQUESTS: u32 = 20;
+pub const RATE_LIMIT_WINDOW_MS: u64 = 1000;
+// Lower limit for GET /wifi/stations, which queries the Wi-Fi driver
+pub const STATIONS_RATE_LIMIT_REQUESTS: u32 = 10;
+// Lower still for GET /wifi/scan, every scan interrupts the access point briefly
+#[cfg(feature = "scan")]
+pub const SCAN_RATE_LIMIT_REQUESTS: u32 = 1;
+
+// Max request body length for POST /config/game and POST /rssi/calibrate
+pub const MAX_CONFIG_BODY_LEN: usize = 128;
+// Max body length for POST /provision, fits the longest SSID and passwords
+pub const MAX_PROVISION_BODY_LEN: usize = 256;
+// Delay before POST /reboot restarts when the request doesn't give one, and the max allowed
+pub const REBOOT_DEFAULT_DELAY_S: u32 = 5;
+pub const MAX_REBOOT_DELAY_S: u32 = 60;
+// Max request body length for POST /admin/broadcast
+#[cfg(feature = "game")]
+pub const MAX_BROADCAST_BODY_LEN: usize = 256;
+
+// Size of the buffer used to stream firmware images to flash
+pub const OTA_CHUNK_LEN: usize = 4096;
+// Bytes collected before a chunk of a streamed response is sent
+pub const HTTP_CHUNK_LEN: usize = 512;
+// Recent log lines kept in RAM for GET /logs/stream, and the max length of each
+pub const LOG_BUFFER_LINES: usize = 64;
+pub const LOG_LINE_MAX_LEN: usize = 160;
+
+// Number of HTTP requests kept for GET /admin/log
+pub const REQUEST_LOG_LEN: usize = 32;
+
+// Catch bad values at build time instead of with a panic deep inside esp-idf-svc
+const _: () = assert!(SSID.len() <= 32, "WIFI_SSID must be at most 32 bytes");
+const _: () = assert!(
+    PASSWORD.len() >= 8 && PASSWORD.len() <= 63,
+    "WIFI_PASS must be 8-63 bytes"
+);
+const _: () = assert!(UPSTREAM_SSID.len() <= 32, "UPSTREAM_SSID must be at most 32 bytes");
+const _: () = assert!(
+    UPSTREAM_PASS.is_empty() || (UPSTREAM_PASS.len() >= 8 && UPSTREAM_PASS.len() <= 63),
+    "UPSTREAM_PASS must be empty or 8-63 bytes"
+);
+const _: () = assert!(
+    !WORDS.is_empty() && words_valid(WORDS),
+    "words.txt must hold WORD_LEN lowercase letters per line"
+);
+const _: () = assert!(CHANNEL >= 1 && CHANNEL <= 13, "CHANNEL must be 1-13");
+const _: () = assert!(
+    MAX_AUTO_CHANNEL >= 1 && MAX_AUTO_CHANNEL <= 13,
+    "MAX_AUTO_CHANNEL must be 1-13"
+);
+const _: () = assert!(
+    BEACON_INTERVAL_MS >= 100 && BEACON_INTERVAL_MS <= 60000,
+    "BEACON_INTERVAL_MS must be 100-60000"
+);
+const _: () = assert!(MAX_LEN >= 4, "MAX_LEN must be at least 4");
+const _: () = assert!(
+    MAX_AP_STATIONS >= 1 && MAX_AP_STATIONS as usize <= MAX_WS_SESSIONS,
+    "MAX_AP_STATIONS must be 1 to MAX_WS_SESSIONS"
+);
+const _: () = assert!(
+    LED_GPIO != 0 && LED_GPIO != 5 && LED_GPIO != 6,
+    "LED_GPIO is taken by the button or the OLED"
+);
+const _: () = assert!(STACK_SIZE >= 4096, "STACK_SIZE must be at least 4096");
+#[cfg(feature = "oled")]
+const _: () = assert!(
+    match SPLASH_BMP {
+        Some(splash) => {
+            splash.len() == (SPLASH_WIDTH as usize * SPLASH_HEIGHT as usize).div_ceil(8)
+                && SPLASH_WIDTH % 8 == 0
+        }
+        None => true,
+    },
+    "SPLASH_BMP must hold SPLASH_WIDTH x SPLASH_HEIGHT bits, with a width divisible by 8"
+);
+
+/// Runtime-configurable guessing game settings
+#[cfg(feature = "game")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameConfig {
+    pub min: u32,
+    pub max: u32,
+    pub difficulty: Difficulty,
+    pub max_guesses: u32,
+    /// Rounds played per session, best-of-N when more than 1
+    pub rounds: u8,
+}
+
+#[cfg(feature = "game")]
+impl Default for GameConfig {
+    fn default() -> Self {
+        let difficulty = Difficulty::default();
+        let (min, max) = difficulty.range();
+        Self {
+            min,
+            max,
+            difficulty,
+            max_guesses: DEFAULT_MAX_GUESSES,
+            rounds: 1,
+        }
+    }
+}
 
+#[cfg(feature = "game")]
+impl GameConfig {
+    /// Parse and validate a JSON body like
+    /// `{"min":1,"max":500,"difficulty":"hard","max_guesses":12,"rounds":3}`
+    /// Missing fields keep their current value, except that changing the
+    /// difficulty resets the range to the difficulty's default
+    pub fn updated_from_json(&self, body: &str) -> Result<Self, &'static str> {
+        let difficulty = extract_json_string(body, "difficulty");
+        self.updated_from_fields(difficulty.as_deref(), |key| extract_json_u32(body, key))
+    }
+
+    /// Same as `updated_from_json`, for the fields of a form encoded body like
+    /// `min=1&max=500&difficulty=hard`
+    pub fn updated_from_form(
+        &self,
+        fields: &BTreeMap<String, String>,
+    ) -> Result<Self, &'static str> {
+        self.updated_from_fields(fields.get("difficulty").map(String::as_str), |key| {
+            fields.get(key).map(|value| value.parse().map_err(|_| ()))
+        })
+    }
+
+    fn updated_from_fields(
+        &self,
+        difficulty: Option<&str>,
+        number: impl Fn(&str) -> Option<Result<u32, ()>>,
+    ) -> Result<Self, &'static str> {
+        let (difficulty, (default_min, default_max)) = match difficulty {
+            Some(name) => match Difficulty::from_name(name) {
+                Some(difficulty) => (difficulty, difficulty.range()),
+                None => return Err("difficulty must be easy, medium or hard"),
+            },
+            None => (self.difficulty, (self.min, self.max)),
+        };
+        let min = match number("min") {
+            Some(Ok(min)) => min,
+            Some(Err(())) => return Err("min must be a non-negative integer"),
+            None => default_min,
+        };
+        let max = match number("max") {
+            Some(Ok(max)) => max,
+            Some(Err(())) => return Err("max must be a non-negative integer"),
+            None => default_max,
+        };
+        if min >= max {
+            return Err("min must be less than max");
+        }
+        let max_guesses = match number("max_guesses") {
+            Some(Ok(0)) => return Err("max_guesses must be at least 1"),
+            Some(Ok(max_guesses)) => max_guesses,
+            Some(Err(())) => return Err("max_guesses must be a non-negative integer"),
+            None => self.max_guesses,
+        };
+        let rounds = match number("rounds") {
+            Some(Ok(rounds)) if (1..=MAX_ROUNDS as u32).contains(&rounds) => rounds as u8,
+            Some(_) => return Err("rounds must be 1-5"),
+            None => self.rounds,
+        };
+        Ok(Self {
+            min,
+            max,
+            difficulty,
+            max_guesses,
+            rounds,
+        })
+    }
+
+    /// Whether a guess or secret lies within the configured range
+    pub fn contains(&self, n: u32) -> bool {
+        (self.min..=self.max).contains(&n)
+    }
+
+    /// Map a random number onto the configured range
+    pub fn secret_from(&self, random: u32) -> u32 {
+        let span = self.max as u64 - self.min as u64 + 1;
+        self.min + (random as u64 % span) as u32
+    }
+
+    pub fn to_json(self) -> String {
+        format!(
+            r#"{{"min":{},"max":{},"difficulty":"{}","max_guesses":{},"rounds":{}}}"#,
+            self.min,
+            self.max,
+            self.difficulty.name(),
+            self.max_guesses,
+            self.rounds
+        )
+    }
+}
+
+/// Number of non-empty lines in `text` at compile time
+const fn count_lines(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut count = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\n' && (i + 1 == bytes.len() || bytes[i + 1] == b'\n') {
+            count += 1;
+        }
+        i += 1;
+    }
+    count
+}
+
+/// Split `text` into its `N` non-empty lines at compile time
+const fn split_lines<const N: usize>(text: &str) -> [&str; N] {
+    let mut lines = [""; N];
+    let mut rest = text.as_bytes();
+    let mut n = 0;
+    while n < N {
+        let mut end = 0;
+        while end < rest.len() && rest[end] != b'\n' {
+            end += 1;
+        }
+        let (line, tail) = rest.split_at(end);
+        if !line.is_empty() {
+            lines[n] = match core::str::from_utf8(line) {
+                Ok(line) => line,
+                Err(_) => panic!("Line is not valid UTF-8"),
+            };
+            n += 1;
+        }
+        rest = if tail.is_empty() { tail } else { tail.split_at(1).1 };
+    }
+    lines
+}
+
+/// Whether every word is `WORD_LEN` lowercase ASCII letters
+const fn words_valid(words: &[&str]) -> bool {
+    let mut i = 0;
+    while i < words.len() {
+        let bytes = words[i].as_bytes();
+        if bytes.len() != WORD_LEN {
+            return false;
+        }
+        let mut j = 0;
+        while j < bytes.len() {
+            if !bytes[j].is_ascii_lowercase() {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Map an `OLED_ROTATION` value in degrees to the display rotation
+#[cfg(feature = "oled")]
+pub const fn parse_rotation(degrees: &str) -> DisplayRotation {
+    match degrees.as_bytes() {
+        b"0" => DisplayRotation::Rotate0,
+        b"90" => DisplayRotation::Rotate90,
+        b"180" => DisplayRotation::Rotate180,
+        b"270" => DisplayRotation::Rotate270,
+        _ => panic!("OLED_ROTATION must be 0, 90, 180 or 270"),
+    }
+}
+
+/// Format a hash as a quoted hex ETag at compile time
+const fn etag_bytes(hash: u32) -> [u8; 10] {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut out = [b'"'; 10];
+    let mut i = 0;
+    while i < 8 {
+        out[1 + i] = HEX[((hash >> (28 - 4 * i)) & 0xf) as usize];
+        i += 1;
+    }
+    out
+}
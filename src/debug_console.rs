@@ -0,0 +1,232 @@
+//! Line-based debug console on TCP port 2323
+//!
+//! Connect with `nc <ip> 2323` or telnet. The first line must be
+//! `ADMIN_TOKEN`, after that each line is a command:
+//!
+//! - `heap`: free heap in bytes
+//! - `sessions`: the open /ws/guess sessions
+//! - `rssi`: RSSI and distance of the connected station
+//! - `game <id> secret`: the secret of a session's game
+//! - `reboot`: restart the device
+//!
+//! Only one client is served at a time, others wait until it disconnects.
+
+use anyhow::Result;
+use esp_idf_svc::hal::reset::restart;
+use log::*;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+use crate::auth::check_admin_token;
+use crate::rssi::{calculate_distance_from_rssi, get_station_rssi};
+use crate::state::SharedState;
+use crate::utils::{now_ms, parse_mac_address};
+
+const CONSOLE_PORT: u16 = 2323;
+const CONSOLE_STACK_SIZE: usize = 6144;
+// Idle clients are dropped so they can't keep the console to themselves
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+// Longer lines are rejected, no command comes close
+const MAX_LINE_LEN: usize = 128;
+const PROMPT: &[u8] = b"> ";
+
+/// A parsed console command
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Heap,
+    Sessions,
+    Rssi,
+    GameSecret(i32),
+    Reboot,
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["heap"] => Ok(Self::Heap),
+            ["sessions"] => Ok(Self::Sessions),
+            ["rssi"] => Ok(Self::Rssi),
+            ["reboot"] => Ok(Self::Reboot),
+            ["game", id, "secret"] => id
+                .parse()
+                .map(Self::GameSecret)
+                .map_err(|_| format!("Invalid session ID `{}`", id)),
+            [] => Err("Empty command".to_string()),
+            _ => Err(format!(
+                "Unknown command `{}`, try heap, sessions, rssi, game <id> secret or reboot",
+                line.trim()
+            )),
+        }
+    }
+}
+
+/// Spawn the task serving the console
+pub fn spawn(state: SharedState) -> Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, CONSOLE_PORT))?;
+
+    std::thread::Builder::new()
+        .name("debug_console".into())
+        .stack_size(CONSOLE_STACK_SIZE)
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Debug console accept failed: {:?}", e);
+                        continue;
+                    }
+                };
+                let peer = stream.peer_addr().ok();
+                info!("Debug console client {:?} connected", peer);
+                if let Err(e) = serve(stream, &state) {
+                    warn!("Debug console client {:?}: {:?}", peer, e);
+                }
+                info!("Debug console client {:?} disconnected", peer);
+            }
+        })?;
+
+    info!("Debug console listening on port {}", CONSOLE_PORT);
+    Ok(())
+}
+
+/// Authenticate a client, then run its commands until it disconnects
+fn serve(stream: TcpStream, state: &SharedState) -> Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut buf = Vec::with_capacity(MAX_LINE_LEN);
+
+    writer.write_all(b"Token: ")?;
+    match read_line(&mut reader, &mut buf)? {
+        Some(token) if check_admin_token(token.trim()) => {}
+        Some(_) => {
+            warn!("Debug console client sent a wrong token");
+            writer.write_all(b"Unauthorized\n")?;
+            return Ok(());
+        }
+        None => return Ok(()),
+    }
+
+    loop {
+        writer.write_all(PROMPT)?;
+        let Some(line) = read_line(&mut reader, &mut buf)? else {
+            return Ok(());
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match Command::parse(&line) {
+            Ok(Command::Reboot) => {
+                info!("Reboot requested from the debug console");
+                writer.write_all(b"Rebooting...\n")?;
+                writer.flush()?;
+                restart();
+            }
+            Ok(command) => run(command, state),
+            Err(e) => e,
+        };
+        writer.write_all(reply.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+}
+
+/// Read a line with `read_until`, `None` once the client disconnected
+fn read_line<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>) -> Result<Option<String>> {
+    buf.clear();
+    // Bounded so a client can't fill the heap with one endless line
+    let len = reader
+        .by_ref()
+        .take(MAX_LINE_LEN as u64)
+        .read_until(b'\n', buf)?;
+    if len == 0 {
+        return Ok(None);
+    }
+    if buf.last() != Some(&b'\n') && len == MAX_LINE_LEN {
+        anyhow::bail!("Line longer than {} bytes", MAX_LINE_LEN);
+    }
+    Ok(Some(String::from_utf8_lossy(buf).into_owned()))
+}
+
+fn run(command: Command, state: &SharedState) -> String {
+    match command {
+        Command::Heap => {
+            let free = unsafe { esp_idf_svc::sys::esp_get_free_heap_size() };
+            format!("Free heap: {} bytes", free)
+        }
+        Command::Sessions => {
+            let sessions = state.sessions();
+            if sessions.is_empty() {
+                return "No open sessions".to_string();
+            }
+            let now = now_ms();
+            sessions
+                .iter()
+                .map(|(&id, session)| session.to_summary_json(id, now))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Command::Rssi => match get_station_rssi() {
+            Some((rssi, mac)) => format!(
+                "{}: {} dBm, {:.2} m",
+                parse_mac_address(&mac),
+                rssi,
+                calculate_distance_from_rssi(rssi)
+            ),
+            None => "No connected station".to_string(),
+        },
+        Command::GameSecret(id) => match state.sessions().get(&id) {
+            Some(session) => format!("Session {} secret: {}", id, session.game.secret()),
+            None => format!("No session {}", id),
+        },
+        // Handled by `serve`, which owns the connection
+        Command::Reboot => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse("heap\n"), Ok(Command::Heap));
+        assert_eq!(Command::parse("sessions\r\n"), Ok(Command::Sessions));
+        assert_eq!(Command::parse(" rssi "), Ok(Command::Rssi));
+        assert_eq!(Command::parse("reboot"), Ok(Command::Reboot));
+        assert_eq!(
+            Command::parse("game 54 secret"),
+            Ok(Command::GameSecret(54))
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid() {
+        assert!(Command::parse("").is_err());
+        assert!(Command::parse("game x secret").is_err());
+        assert!(Command::parse("game 54").is_err());
+        assert!(Command::parse("heap now").is_err());
+        assert!(Command::parse("shutdown").unwrap_err().contains("shutdown"));
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut reader: &[u8] = b"token\nheap";
+        let mut buf = Vec::new();
+        assert_eq!(
+            read_line(&mut reader, &mut buf).unwrap().as_deref(),
+            Some("token\n")
+        );
+        assert_eq!(
+            read_line(&mut reader, &mut buf).unwrap().as_deref(),
+            Some("heap")
+        );
+        assert_eq!(read_line(&mut reader, &mut buf).unwrap(), None);
+
+        let long = [b'a'; MAX_LINE_LEN + 1];
+        assert!(read_line(&mut &long[..], &mut buf).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+//! Typed errors for HTTP and WebSocket handlers
+
+use core::fmt;
+use embedded_svc::io::Write;
+use esp_idf_svc::{
+    http::server::{EspHttpConnection, Request},
+    io::EspIOError,
+    sys::{
+        EspError, ESP_ERR_INVALID_ARG, ESP_ERR_INVALID_SIZE, ESP_ERR_INVALID_STATE,
+        ESP_ERR_NOT_FOUND,
+    },
+};
+use log::*;
+
+use crate::request_log;
+use crate::server::start_response;
+use crate::utils::json_escape;
+
+/// Everything that can go wrong while handling a request
+#[derive(Debug)]
+pub enum ServerError {
+    /// Underlying ESP-IDF call failed (socket, HTTP server, ...)
+    Io(EspError),
+    /// Payload was not valid UTF-8
+    Encoding,
+    /// Payload exceeded the endpoint's size limit
+    PayloadTooLarge,
+    /// No game state exists for the session
+    GameNotFound,
+    /// Client exceeded the request rate limit
+    RateLimit,
+    /// Missing or wrong credentials
+    Unauthorized,
+    /// Request was well-formed but semantically invalid
+    BadRequest(String),
+    /// Feature can't be used in the current state of the server
+    #[cfg(feature = "scan")]
+    Unavailable(String),
+}
+
+impl ServerError {
+    /// HTTP status code matching the error
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Io(_) => 500,
+            Self::Encoding | Self::BadRequest(_) => 400,
+            Self::PayloadTooLarge => 413,
+            Self::GameNotFound => 404,
+            Self::RateLimit => 429,
+            Self::Unauthorized => 401,
+            #[cfg(feature = "scan")]
+            Self::Unavailable(_) => 503,
+        }
+    }
+
+    /// HTTP reason phrase matching `status()`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "Internal Server Error",
+            Self::Encoding | Self::BadRequest(_) => "Bad Request",
+            Self::PayloadTooLarge => "Payload Too Large",
+            Self::GameNotFound => "Not Found",
+            Self::RateLimit => "Too Many Requests",
+            Self::Unauthorized => "Unauthorized",
+            #[cfg(feature = "scan")]
+            Self::Unavailable(_) => "Service Unavailable",
+        }
+    }
+
+    /// Log the error and convert it into the closest ESP-IDF error code
+    /// Needed wherever a handler has to return `EspError`
+    pub fn into_esp_error(self) -> EspError {
+        error!("Handler error: {}", self);
+        match self {
+            Self::Io(e) => e,
+            Self::Encoding | Self::BadRequest(_) => {
+                EspError::from_infallible::<ESP_ERR_INVALID_ARG>()
+            }
+            Self::PayloadTooLarge => EspError::from_infallible::<ESP_ERR_INVALID_SIZE>(),
+            Self::GameNotFound => EspError::from_infallible::<ESP_ERR_NOT_FOUND>(),
+            Self::RateLimit | Self::Unauthorized => {
+                EspError::from_infallible::<ESP_ERR_INVALID_STATE>()
+            }
+            #[cfg(feature = "scan")]
+            Self::Unavailable(_) => EspError::from_infallible::<ESP_ERR_INVALID_STATE>(),
+        }
+    }
+
+    /// Error for a failed `utils::read_json_body`
+    /// Maps its size and encoding error codes back to the matching variants
+    pub fn from_body_error(e: EspError) -> Self {
+        match e.code() {
+            ESP_ERR_INVALID_SIZE => Self::PayloadTooLarge,
+            ESP_ERR_INVALID_ARG => Self::Encoding,
+            _ => Self::Io(e),
+        }
+    }
+
+    /// Send the error to the HTTP client as a JSON body with the matching status,
+    /// the request ID and the response time
+    pub fn respond(self, req: Request<&mut EspHttpConnection<'_>>) -> Result<(), EspError> {
+        warn!("Rejecting request to {}: {}", req.uri(), self);
+        let body = format!(
+            r#"{{"error":"{}","request_id":"{}"}}"#,
+            json_escape(&self.to_string()),
+            request_log::request_id()
+        );
+        let mut resp = start_response(
+            req,
+            self.status(),
+            Some(self.reason()),
+            &[("Content-Type", "application/json")],
+        )?;
+        resp.write_all(body.as_bytes())
+            .map_err(|e| ServerError::from(e).into_esp_error())?;
+        Ok(())
+    }
+}
+
+impl From<EspError> for ServerError {
+    fn from(e: EspError) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<EspIOError> for ServerError {
+    fn from(e: EspIOError) -> Self {
+        Self::Io(e.0)
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Encoding => write!(f, "payload is not valid UTF-8"),
+            Self::PayloadTooLarge => write!(f, "payload too large"),
+            Self::GameNotFound => write!(f, "game session not found"),
+            Self::RateLimit => write!(f, "rate limit exceeded"),
+            Self::Unauthorized => write!(f, "unauthorized"),
+            Self::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            #[cfg(feature = "scan")]
+            Self::Unavailable(reason) => write!(f, "unavailable: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
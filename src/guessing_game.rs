@@ -3,56 +3,565 @@
 use core::cmp::Ordering;
 use log::*;
 
+use crate::config::{GameConfig, DEFAULT_MAX_GUESSES};
+use crate::utils::{json_escape, nth};
+
+// Easy mode calls a guess "warm" when it is at most this far from the secret
+const WARM_DISTANCE: u32 = 5;
+
+// Layout of `GuessingGame::serialize`: magic (3 bytes), secret (u32 LE),
+// guesses (u32 LE), done (1 byte), difficulty (1 byte), max_guesses (u16 LE),
+// 1 reserved byte. The guess history is not kept.
+const SERIALIZED_MAGIC: &[u8; 3] = b"GG1";
+pub const SERIALIZED_LEN: usize = 16;
+
+/// Difficulty level, controlling the number range and how helpful hints are
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Difficulty {
+    /// 1-50, hints also say whether the guess is warm or cold
+    Easy,
+    /// 1-100, hints say too high/too low with the guess count
+    #[default]
+    Medium,
+    /// 1-200, hints only say higher/lower
+    Hard,
+}
+
+impl Difficulty {
+    /// Default number range for the difficulty
+    pub fn range(self) -> (u32, u32) {
+        match self {
+            Self::Easy => (1, 50),
+            Self::Medium => (1, 100),
+            Self::Hard => (1, 200),
+        }
+    }
+
+    /// Name used in the JSON config API
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Easy => "easy",
+            Self::Medium => "medium",
+            Self::Hard => "hard",
+        }
+    }
+
+    /// Parse a difficulty from its API name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "easy" => Some(Self::Easy),
+            "medium" => Some(Self::Medium),
+            "hard" => Some(Self::Hard),
+            _ => None,
+        }
+    }
+
+    /// Human-readable hint for a guess that was `distance` away from the secret
+    pub fn hint(self, ordering: Ordering, attempt: u32, distance: u32) -> String {
+        let direction = match ordering {
+            Ordering::Greater => "too high",
+            Ordering::Less => "too low",
+            Ordering::Equal => "correct",
+        };
+        match (self, ordering) {
+            (_, Ordering::Equal) => format!("Your {} guess was correct", nth(attempt)),
+            (Self::Easy, _) => {
+                let temperature = if distance <= WARM_DISTANCE {
+                    "warm"
+                } else {
+                    "cold"
+                };
+                format!(
+                    "Your {} guess was {}, but you're {}!",
+                    nth(attempt),
+                    direction,
+                    temperature
+                )
+            }
+            (Self::Medium, _) => format!("Your {} guess was {}", nth(attempt), direction),
+            (Self::Hard, Ordering::Greater) => "Lower".to_string(),
+            (Self::Hard, Ordering::Less) => "Higher".to_string(),
+        }
+    }
+}
+
+/// Messages exchanged over the guessing game WebSocket
+///
+/// Clients may speak either the legacy plain-text protocol (`"42"`) or JSON
+/// (`{"guess": 42}`); replies should be rendered in the same format.
+#[derive(Debug, PartialEq)]
+pub enum WsMessage {
+    Guess(u32),
+    Welcome {
+        min: u32,
+        max: u32,
+    },
+    Result {
+        ordering: Ordering,
+        attempt: u32,
+        remaining: u32,
+        hint: String,
+    },
+    Win {
+        secret: u32,
+        attempts: u32,
+        history: Vec<u32>,
+    },
+    GaveUp {
+        secret: u32,
+        history: Vec<u32>,
+    },
+    GameOver {
+        secret: u32,
+    },
+    /// A round of a multi-round session ended and the next one started
+    RoundComplete {
+        round: u8,
+        rounds: u8,
+    },
+    /// The last round of a multi-round session ended
+    MatchOver {
+        rounds_won: u8,
+        rounds: u8,
+        total_guesses: u32,
+    },
+    Error(String),
+    Pong,
+}
+
+impl WsMessage {
+    /// Build a `Result` message with the hint matching the difficulty
+    pub fn result(
+        difficulty: Difficulty,
+        ordering: Ordering,
+        attempt: u32,
+        remaining: u32,
+        distance: u32,
+    ) -> Self {
+        Self::Result {
+            ordering,
+            attempt,
+            remaining,
+            hint: difficulty.hint(ordering, attempt, distance),
+        }
+    }
+
+    /// Check whether a raw client payload uses the JSON protocol
+    pub fn is_json(input: &str) -> bool {
+        input
+            .trim_matches(|c: char| c.is_ascii_control() || c.is_whitespace())
+            .starts_with('{')
+    }
+
+    /// Check whether a raw client payload asks to give up the current game
+    pub fn is_give_up(input: &str) -> bool {
+        input
+            .trim_matches(|c: char| c.is_ascii_control() || c.is_whitespace())
+            .eq_ignore_ascii_case("give up")
+    }
+
+    /// Check whether a raw client payload is a `ping` liveness check
+    pub fn is_ping(input: &str) -> bool {
+        input
+            .trim_matches(|c: char| c.is_ascii_control() || c.is_whitespace())
+            .eq_ignore_ascii_case("ping")
+    }
+
+    /// Parse a JSON client message, currently only `{"guess": <number>}`
+    pub fn from_json(input: &str) -> Option<Self> {
+        let body = input
+            .trim_matches(|c: char| c.is_ascii_control() || c.is_whitespace())
+            .strip_prefix('{')?
+            .strip_suffix('}')?;
+        let (key, value) = body.split_once(':')?;
+        if key.trim() != "\"guess\"" {
+            warn!("Unknown JSON message: `{input}`");
+            return None;
+        }
+        let number = value.trim().trim_matches('"').parse::<u32>().ok()?;
+        Some(Self::Guess(number))
+    }
+
+    /// Render the message as a JSON object
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Guess(n) => format!(r#"{{"guess":{}}}"#, n),
+            Self::Welcome { min, max } => format!(
+                r#"{{"result":"welcome","min":{},"max":{},"hint":"{}"}}"#,
+                min,
+                max,
+                json_escape(&self.to_text())
+            ),
+            Self::Result {
+                ordering,
+                attempt,
+                remaining,
+                hint,
+            } => {
+                let result = match ordering {
+                    Ordering::Greater => "too_high",
+                    Ordering::Less => "too_low",
+                    Ordering::Equal => "correct",
+                };
+                format!(
+                    r#"{{"result":"{}","attempt":{},"remaining":{},"hint":"{}"}}"#,
+                    result,
+                    attempt,
+                    remaining,
+                    json_escape(hint)
+                )
+            }
+            Self::Win {
+                secret,
+                attempts,
+                history,
+            } => format!(
+                r#"{{"result":"win","secret":{},"attempts":{},"history":{},"hint":"{}"}}"#,
+                secret,
+                attempts,
+                json_array(history),
+                json_escape(&self.to_text())
+            ),
+            Self::GaveUp { secret, history } => format!(
+                r#"{{"result":"give_up","secret":{},"history":{},"hint":"{}"}}"#,
+                secret,
+                json_array(history),
+                json_escape(&self.to_text())
+            ),
+            Self::GameOver { secret } => format!(
+                r#"{{"result":"game_over","secret":{},"hint":"{}"}}"#,
+                secret,
+                json_escape(&self.to_text())
+            ),
+            Self::RoundComplete { round, rounds } => format!(
+                r#"{{"result":"round_complete","round":{},"rounds":{},"hint":"{}"}}"#,
+                round,
+                rounds,
+                json_escape(&self.to_text())
+            ),
+            Self::MatchOver {
+                rounds_won,
+                rounds,
+                total_guesses,
+            } => format!(
+                r#"{{"result":"match_over","rounds_won":{},"rounds":{},"total_guesses":{},"hint":"{}"}}"#,
+                rounds_won,
+                rounds,
+                total_guesses,
+                json_escape(&self.to_text())
+            ),
+            Self::Error(msg) => format!(r#"{{"result":"error","error":"{}"}}"#, json_escape(msg)),
+            Self::Pong => r#"{"result":"pong"}"#.to_string(),
+        }
+    }
+
+    /// Render the message in the legacy plain-text format
+    pub fn to_text(&self) -> String {
+        match self {
+            Self::Guess(n) => n.to_string(),
+            Self::Welcome { min, max } => format!(
+                "Welcome to the guessing game! Enter a number between {} and {}",
+                min, max
+            ),
+            Self::Result { hint, .. } => hint.clone(),
+            Self::Win {
+                secret, attempts, ..
+            } => format!(
+                "You guessed {} on your {} try! Game over. Enter a new number to play again!",
+                secret,
+                nth(*attempts)
+            ),
+            Self::GaveUp { secret, .. } => {
+                format!("The secret was {}. Better luck next time!", secret)
+            }
+            Self::GameOver { secret } => format!("Game over! The secret was {}", secret),
+            Self::RoundComplete { round, rounds } => format!(
+                "Round {} of {} complete! Starting round {}...",
+                round,
+                rounds,
+                round + 1
+            ),
+            Self::MatchOver {
+                rounds_won,
+                rounds,
+                total_guesses,
+            } => format!(
+                "You won {} of {} rounds, total guesses: {}",
+                rounds_won, rounds, total_guesses
+            ),
+            Self::Error(msg) => msg.clone(),
+            Self::Pong => "pong".to_string(),
+        }
+    }
+
+    /// Render the message in the requested protocol
+    pub fn render(&self, json: bool) -> String {
+        if json {
+            self.to_json()
+        } else {
+            self.to_text()
+        }
+    }
+}
+
+/// A client message on the guessing game WebSocket, in either protocol
+#[derive(Debug, PartialEq, Eq)]
+pub enum WsIncoming {
+    Guess(u32),
+    GiveUp,
+    Ping,
+}
+
+impl WsIncoming {
+    /// Parse a raw client payload, or return the error to reply with
+    pub fn parse(input: &str, config: &GameConfig) -> Result<Self, WsMessage> {
+        if WsMessage::is_give_up(input) {
+            return Ok(Self::GiveUp);
+        }
+        if WsMessage::is_ping(input) {
+            return Ok(Self::Ping);
+        }
+        GuessingGame::parse_guess(input, config)
+            .map(Self::Guess)
+            .ok_or_else(|| {
+                WsMessage::Error(format!(
+                    "Please enter a number between {} and {}",
+                    config.min, config.max
+                ))
+            })
+    }
+}
+
+/// Hint with the range the secret is still in, see `GuessingGame::secret_bounds`
+pub fn bounds_hint(low: u32, high: u32) -> String {
+    format!("The secret is between {} and {}", low, high)
+}
+
+/// JSON body of GET /game/hint
+pub fn bounds_hint_json(low: u32, high: u32) -> String {
+    format!(
+        r#"{{"hint":"{}","low_bound":{},"high_bound":{}}}"#,
+        bounds_hint(low, high),
+        low,
+        high
+    )
+}
+
+/// Render a list of numbers as a JSON array
+fn json_array(values: &[u32]) -> String {
+    let items: Vec<String> = values.iter().map(u32::to_string).collect();
+    format!("[{}]", items.join(","))
+}
+
 /// Represents a single guessing game session
 pub struct GuessingGame {
     guesses: u32,
     secret: u32,
     done: bool,
+    difficulty: Difficulty,
+    max_guesses: u32,
+    history: Vec<u32>,
 }
 
 impl GuessingGame {
     /// Create a new guessing game with a secret number
+    #[allow(dead_code)] // Available for callers that don't care about difficulty
     pub fn new(secret: u32) -> Self {
-        info!("Creating new guessing game with secret: {}", secret);
+        Self::new_with_difficulty(secret, Difficulty::default())
+    }
+
+    /// Create a new guessing game with a secret number and difficulty level
+    pub fn new_with_difficulty(secret: u32, difficulty: Difficulty) -> Self {
+        info!(
+            "Creating new {} guessing game with secret: {}",
+            difficulty.name(),
+            secret
+        );
         Self {
             guesses: 0,
             secret,
             done: false,
+            difficulty,
+            max_guesses: DEFAULT_MAX_GUESSES,
+            history: Vec::new(),
         }
     }
 
-    /// Make a guess and return the comparison result and guess count
-    pub fn guess(&mut self, guess: u32) -> (Ordering, u32) {
+    /// Create a new guessing game using the difficulty and guess limit from the config
+    pub fn from_config(secret: u32, config: &GameConfig) -> Self {
+        Self {
+            max_guesses: config.max_guesses,
+            ..Self::new_with_difficulty(secret, config.difficulty)
+        }
+    }
+
+    /// Start a new game in place, reusing the existing allocation
+    /// The difficulty level and guess limit are kept
+    pub fn reset(&mut self, new_secret: u32) {
+        info!("Resetting guessing game with secret: {}", new_secret);
+        self.guesses = 0;
+        self.secret = new_secret;
+        self.done = false;
+        self.history.clear();
+    }
+
+    /// Make a guess and return the comparison result, guess count and
+    /// whether the game was lost because the guess limit was reached
+    pub fn guess(&mut self, guess: u32) -> (Ordering, u32, bool) {
         if self.done {
             warn!("Attempted guess on completed game");
-            (Ordering::Equal, self.guesses)
+            (Ordering::Equal, self.guesses, false)
         } else {
+            if self.repeats_last_guess(guess) {
+                warn!(
+                    "Guess {} repeated back to back, possible scripted client",
+                    guess
+                );
+            }
+            self.history.push(guess);
             self.guesses += 1;
             let cmp = guess.cmp(&self.secret);
             info!(
                 "Guess #{}: {} (secret: {}, result: {:?})",
                 self.guesses, guess, self.secret, cmp
             );
+            let game_over_no_more_guesses =
+                cmp != Ordering::Equal && self.guesses >= self.max_guesses;
             if cmp == Ordering::Equal {
                 self.done = true;
                 info!("Game completed in {} guesses", self.guesses);
+            } else if game_over_no_more_guesses {
+                self.done = true;
+                info!("Game lost after {} guesses", self.guesses);
             }
-            (cmp, self.guesses)
+            (cmp, self.guesses, game_over_no_more_guesses)
         }
     }
 
-    /// Parse a guess string into a valid number (1-100)
-    pub fn parse_guess(input: &str) -> Option<u32> {
-        // Trim control codes (including null bytes) and/or whitespace
-        let Ok(number) = input
-            .trim_matches(|c: char| c.is_ascii_control() || c.is_whitespace())
-            .parse::<u32>()
-        else {
-            warn!("Not a number: `{input}` (length {})", input.len());
+    /// Every guess made in the current game, oldest first
+    pub fn history(&self) -> &[u32] {
+        &self.history
+    }
+
+    /// Narrowest range still containing the secret, given the guesses so far
+    /// `min` and `max` are the bounds of the game's number range
+    pub fn secret_bounds(&self, min: u32, max: u32) -> (u32, u32) {
+        let low = self
+            .history
+            .iter()
+            .filter(|&&g| g < self.secret)
+            .map(|&g| g + 1)
+            .fold(min, u32::max);
+        let high = self
+            .history
+            .iter()
+            .filter(|&&g| g > self.secret)
+            .map(|&g| g - 1)
+            .fold(max, u32::min);
+        (low, high)
+    }
+
+    /// Whether `guess` is the same as the previous guess
+    fn repeats_last_guess(&self, guess: u32) -> bool {
+        self.history.last() == Some(&guess)
+    }
+
+    /// Number of guesses left before the game is lost
+    pub fn guesses_remaining(&self) -> u32 {
+        self.max_guesses.saturating_sub(self.guesses)
+    }
+
+    /// End the game without a win and reveal the secret
+    pub fn give_up(&mut self) -> u32 {
+        info!(
+            "Giving up after {} guesses (secret: {})",
+            self.guesses, self.secret
+        );
+        self.done = true;
+        self.secret
+    }
+
+    /// Check whether the game has been won or given up
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Pack the game into a fixed-size record for NVS
+    pub fn serialize(&self) -> [u8; SERIALIZED_LEN] {
+        let mut data = [0u8; SERIALIZED_LEN];
+        data[..3].copy_from_slice(SERIALIZED_MAGIC);
+        data[3..7].copy_from_slice(&self.secret.to_le_bytes());
+        data[7..11].copy_from_slice(&self.guesses.to_le_bytes());
+        data[11] = self.done as u8;
+        data[12] = match self.difficulty {
+            Difficulty::Easy => 0,
+            Difficulty::Medium => 1,
+            Difficulty::Hard => 2,
+        };
+        let max_guesses = self.max_guesses.min(u16::MAX as u32) as u16;
+        data[13..15].copy_from_slice(&max_guesses.to_le_bytes());
+        data
+    }
+
+    /// Rebuild a game packed by `serialize`
+    /// Returns `None` if the magic or a field is invalid
+    pub fn deserialize(data: &[u8; SERIALIZED_LEN]) -> Option<Self> {
+        if &data[..3] != SERIALIZED_MAGIC {
             return None;
+        }
+        let done = match data[11] {
+            0 => false,
+            1 => true,
+            _ => return None,
         };
+        let difficulty = match data[12] {
+            0 => Difficulty::Easy,
+            1 => Difficulty::Medium,
+            2 => Difficulty::Hard,
+            _ => return None,
+        };
+        Some(Self {
+            guesses: u32::from_le_bytes(data[7..11].try_into().unwrap()),
+            secret: u32::from_le_bytes(data[3..7].try_into().unwrap()),
+            done,
+            difficulty,
+            max_guesses: u16::from_le_bytes([data[13], data[14]]) as u32,
+            history: Vec::new(),
+        })
+    }
 
-        if !(1..=100).contains(&number) {
+    /// Minimal JSON view of the game for admin listings, without the secret
+    pub fn to_summary_json(&self) -> String {
+        format!(
+            r#"{{"guesses":{},"remaining":{},"done":{}}}"#,
+            self.guesses,
+            self.guesses_remaining(),
+            self.done
+        )
+    }
+
+    /// Parse a guess into a number within the configured range
+    /// Accepts both plain text (`42`) and JSON (`{"guess": 42}`)
+    pub fn parse_guess(input: &str, config: &GameConfig) -> Option<u32> {
+        let number = if WsMessage::is_json(input) {
+            let Some(WsMessage::Guess(number)) = WsMessage::from_json(input) else {
+                warn!("Invalid JSON guess: `{input}`");
+                return None;
+            };
+            number
+        } else {
+            // Trim control codes (including null bytes) and/or whitespace
+            let Ok(number) = input
+                .trim_matches(|c: char| c.is_ascii_control() || c.is_whitespace())
+                .parse::<u32>()
+            else {
+                warn!("Not a number: `{input}` (length {})", input.len());
+                return None;
+            };
+            number
+        };
+
+        if !config.contains(number) {
             warn!("Not in range ({number})");
             return None;
         }
@@ -65,11 +574,17 @@ impl GuessingGame {
     pub fn secret(&self) -> u32 {
         self.secret
     }
+
+    /// Get the difficulty level the game was created with
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
 }
 
 #[cfg(test)]
 mod tests {
-
+    use super::*;
+    use crate::utils::parse_query_string;
 
     #[test]
     fn test_guessing_game_new() {
@@ -81,7 +596,7 @@ mod tests {
     #[test]
     fn test_guessing_game_guess_too_high() {
         let mut game = GuessingGame::new(50);
-        let (cmp, count) = game.guess(75);
+        let (cmp, count, _) = game.guess(75);
         assert_eq!(cmp, Ordering::Greater);
         assert_eq!(count, 1);
     }
@@ -89,7 +604,7 @@ mod tests {
     #[test]
     fn test_guessing_game_guess_too_low() {
         let mut game = GuessingGame::new(50);
-        let (cmp, count) = game.guess(25);
+        let (cmp, count, _) = game.guess(25);
         assert_eq!(cmp, Ordering::Less);
         assert_eq!(count, 1);
     }
@@ -97,23 +612,410 @@ mod tests {
     #[test]
     fn test_guessing_game_guess_correct() {
         let mut game = GuessingGame::new(50);
-        let (cmp, count) = game.guess(50);
+        let (cmp, count, _) = game.guess(50);
         assert_eq!(cmp, Ordering::Equal);
         assert_eq!(count, 1);
     }
 
+    #[test]
+    fn test_guessing_game_reset_matches_new() {
+        let mut game = GuessingGame::new(50);
+        game.guess(25);
+        game.guess(50);
+        game.reset(75);
+
+        let mut fresh = GuessingGame::new(75);
+        assert_eq!(game.guesses, fresh.guesses);
+        assert_eq!(game.secret, fresh.secret);
+        assert_eq!(game.done, fresh.done);
+        assert_eq!(game.guess(80), fresh.guess(80));
+        assert_eq!(game.guess(75), fresh.guess(75));
+    }
+
     #[test]
     fn test_parse_guess_valid() {
-        assert_eq!(GuessingGame::parse_guess("42"), Some(42));
-        assert_eq!(GuessingGame::parse_guess("1"), Some(1));
-        assert_eq!(GuessingGame::parse_guess("100"), Some(100));
+        let range = GameConfig::default();
+        assert_eq!(GuessingGame::parse_guess("42", &range), Some(42));
+        assert_eq!(GuessingGame::parse_guess("1", &range), Some(1));
+        assert_eq!(GuessingGame::parse_guess("100", &range), Some(100));
     }
 
     #[test]
     fn test_parse_guess_invalid() {
-        assert_eq!(GuessingGame::parse_guess("abc"), None);
-        assert_eq!(GuessingGame::parse_guess("0"), None);
-        assert_eq!(GuessingGame::parse_guess("101"), None);
+        let range = GameConfig::default();
+        assert_eq!(GuessingGame::parse_guess("abc", &range), None);
+        assert_eq!(GuessingGame::parse_guess("0", &range), None);
+        assert_eq!(GuessingGame::parse_guess("101", &range), None);
+    }
+
+    #[test]
+    fn test_parse_guess_json() {
+        let range = GameConfig::default();
+        assert_eq!(GuessingGame::parse_guess(r#"{"guess": 42}"#, &range), Some(42));
+        assert_eq!(GuessingGame::parse_guess("{\"guess\":7}\0", &range), Some(7));
+        assert_eq!(GuessingGame::parse_guess(r#"{"guess": 0}"#, &range), None);
+        assert_eq!(GuessingGame::parse_guess(r#"{"number": 42}"#, &range), None);
+    }
+
+    #[test]
+    fn test_ws_message_result_json() {
+        let msg = WsMessage::result(Difficulty::Medium, Ordering::Greater, 3, 7, 10);
+        assert_eq!(msg.to_text(), "Your third guess was too high");
+        assert_eq!(
+            msg.to_json(),
+            r#"{"result":"too_high","attempt":3,"remaining":7,"hint":"Your third guess was too high"}"#
+        );
+    }
+
+    #[test]
+    fn test_ws_message_error_json_escaped() {
+        let msg = WsMessage::Error("bad \"input\"".to_string());
+        assert_eq!(msg.to_json(), r#"{"result":"error","error":"bad \"input\""}"#);
+    }
+
+    #[test]
+    fn test_parse_guess_custom_range() {
+        let range = GameConfig {
+            min: 1,
+            max: 500,
+            ..GameConfig::default()
+        };
+        assert_eq!(GuessingGame::parse_guess("500", &range), Some(500));
+        assert_eq!(GuessingGame::parse_guess("501", &range), None);
+        assert_eq!(range.secret_from(499), 500);
+        assert_eq!(range.secret_from(500), 1);
+    }
+
+    #[test]
+    fn test_game_config_from_json() {
+        let current = GameConfig::default();
+        assert_eq!(
+            current.updated_from_json(r#"{"min":1,"max":500}"#),
+            Ok(GameConfig {
+                min: 1,
+                max: 500,
+                ..current
+            })
+        );
+        assert_eq!(
+            current.updated_from_json(r#"{"max": 50}"#),
+            Ok(GameConfig { max: 50, ..current })
+        );
+        assert!(current.updated_from_json(r#"{"min":5,"max":5}"#).is_err());
+        assert!(current.updated_from_json(r#"{"min":-1}"#).is_err());
+    }
+
+    #[test]
+    fn test_game_config_from_form() {
+        let current = GameConfig::default();
+        let fields = parse_query_string("difficulty=hard&max=300&max_guesses=12");
+        assert_eq!(
+            current.updated_from_form(&fields),
+            Ok(GameConfig {
+                min: 1,
+                max: 300,
+                difficulty: Difficulty::Hard,
+                max_guesses: 12,
+                rounds: 1,
+            })
+        );
+        assert!(current
+            .updated_from_form(&parse_query_string("min=abc"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_game_config_difficulty_sets_range() {
+        let current = GameConfig::default();
+        assert_eq!(
+            current.updated_from_json(r#"{"difficulty":"hard"}"#),
+            Ok(GameConfig {
+                min: 1,
+                max: 200,
+                difficulty: Difficulty::Hard,
+                ..current
+            })
+        );
+        assert_eq!(
+            current.updated_from_json(r#"{"difficulty": "easy", "max": 20}"#),
+            Ok(GameConfig {
+                min: 1,
+                max: 20,
+                difficulty: Difficulty::Easy,
+                ..current
+            })
+        );
+        assert!(current.updated_from_json(r#"{"difficulty":"insane"}"#).is_err());
+    }
+
+    #[test]
+    fn test_new_with_difficulty() {
+        assert_eq!(GuessingGame::new(42).difficulty(), Difficulty::Medium);
+        let mut game = GuessingGame::new_with_difficulty(42, Difficulty::Hard);
+        game.guess(42);
+        game.reset(7);
+        assert_eq!(game.difficulty(), Difficulty::Hard);
+    }
+
+    #[test]
+    fn test_easy_hint_warm_and_cold() {
+        assert_eq!(
+            Difficulty::Easy.hint(Ordering::Greater, 2, WARM_DISTANCE),
+            "Your second guess was too high, but you're warm!"
+        );
+        assert_eq!(
+            Difficulty::Easy.hint(Ordering::Less, 1, WARM_DISTANCE + 1),
+            "Your first guess was too low, but you're cold!"
+        );
+    }
+
+    #[test]
+    fn test_medium_hint_unchanged() {
+        assert_eq!(
+            Difficulty::Medium.hint(Ordering::Less, 4, 1),
+            "Your fourth guess was too low"
+        );
+    }
+
+    #[test]
+    fn test_hard_hint_has_no_count() {
+        assert_eq!(Difficulty::Hard.hint(Ordering::Greater, 3, 1), "Lower");
+        assert_eq!(Difficulty::Hard.hint(Ordering::Less, 3, 100), "Higher");
     }
-}
 
+    #[test]
+    fn test_give_up_fresh_game() {
+        let mut game = GuessingGame::new(42);
+        assert!(!game.is_done());
+        assert_eq!(game.give_up(), 42);
+        assert!(game.is_done());
+        // Further guesses don't count once the game is over
+        assert_eq!(game.guess(10), (Ordering::Equal, 0, false));
+    }
+
+    #[test]
+    fn test_give_up_completed_game() {
+        let mut game = GuessingGame::new(42);
+        assert_eq!(game.guess(42), (Ordering::Equal, 1, false));
+        assert!(game.is_done());
+        assert_eq!(game.give_up(), 42);
+        assert!(game.is_done());
+    }
+
+    #[test]
+    fn test_is_give_up() {
+        assert!(WsMessage::is_give_up("give up"));
+        assert!(WsMessage::is_give_up("  Give Up\n"));
+        assert!(WsMessage::is_give_up("GIVE UP\0"));
+        assert!(!WsMessage::is_give_up("give"));
+        assert!(!WsMessage::is_give_up("42"));
+    }
+
+    #[test]
+    fn test_ws_incoming_parse() {
+        let range = GameConfig::default();
+        assert_eq!(WsIncoming::parse("42", &range), Ok(WsIncoming::Guess(42)));
+        assert_eq!(
+            WsIncoming::parse(r#"{"guess": 7}"#, &range),
+            Ok(WsIncoming::Guess(7))
+        );
+        assert_eq!(WsIncoming::parse("Give up\n", &range), Ok(WsIncoming::GiveUp));
+        assert_eq!(WsIncoming::parse("ping\0", &range), Ok(WsIncoming::Ping));
+        let error = WsMessage::Error("Please enter a number between 1 and 100".to_string());
+        assert_eq!(WsIncoming::parse("abc", &range), Err(error));
+        assert!(WsIncoming::parse("101", &range).is_err());
+        assert!(WsIncoming::parse("pingpong", &range).is_err());
+    }
+
+    #[test]
+    fn test_bounds_hint() {
+        assert_eq!(bounds_hint(31, 69), "The secret is between 31 and 69");
+        assert_eq!(
+            bounds_hint_json(34, 67),
+            r#"{"hint":"The secret is between 34 and 67","low_bound":34,"high_bound":67}"#
+        );
+    }
+
+    #[test]
+    fn test_pong_message() {
+        assert_eq!(WsMessage::Pong.to_text(), "pong");
+        assert_eq!(WsMessage::Pong.to_json(), r#"{"result":"pong"}"#);
+    }
+
+    #[test]
+    fn test_gave_up_message() {
+        let msg = WsMessage::GaveUp {
+            secret: 42,
+            history: vec![50, 25],
+        };
+        assert_eq!(msg.to_text(), "The secret was 42. Better luck next time!");
+        assert_eq!(
+            msg.to_json(),
+            r#"{"result":"give_up","secret":42,"history":[50,25],"hint":"The secret was 42. Better luck next time!"}"#
+        );
+    }
+
+    #[test]
+    fn test_game_config_max_guesses() {
+        let current = GameConfig::default();
+        assert_eq!(current.max_guesses, DEFAULT_MAX_GUESSES);
+        assert_eq!(
+            current.updated_from_json(r#"{"max_guesses":3}"#),
+            Ok(GameConfig {
+                max_guesses: 3,
+                ..current
+            })
+        );
+        assert!(current.updated_from_json(r#"{"max_guesses":0}"#).is_err());
+    }
+
+    #[test]
+    fn test_game_config_rounds() {
+        let current = GameConfig::default();
+        assert_eq!(current.rounds, 1);
+        assert_eq!(
+            current.updated_from_json(r#"{"rounds":5}"#),
+            Ok(GameConfig {
+                rounds: 5,
+                ..current
+            })
+        );
+        assert!(current.updated_from_json(r#"{"rounds":0}"#).is_err());
+        assert!(current.updated_from_json(r#"{"rounds":6}"#).is_err());
+    }
+
+    #[test]
+    fn test_win_on_last_allowed_guess() {
+        let config = GameConfig {
+            max_guesses: 3,
+            ..GameConfig::default()
+        };
+        let mut game = GuessingGame::from_config(42, &config);
+        assert_eq!(game.guess(10), (Ordering::Less, 1, false));
+        assert_eq!(game.guess(90), (Ordering::Greater, 2, false));
+        assert_eq!(game.guesses_remaining(), 1);
+        assert_eq!(game.guess(42), (Ordering::Equal, 3, false));
+        assert_eq!(game.guesses_remaining(), 0);
+    }
+
+    #[test]
+    fn test_game_over_after_max_guesses() {
+        let config = GameConfig {
+            max_guesses: 3,
+            ..GameConfig::default()
+        };
+        let mut game = GuessingGame::from_config(42, &config);
+        game.guess(10);
+        game.guess(90);
+        assert_eq!(game.guess(50), (Ordering::Greater, 3, true));
+        assert!(game.is_done());
+        // One over the limit is not counted
+        assert_eq!(game.guess(42), (Ordering::Equal, 3, false));
+        assert_eq!(game.guesses_remaining(), 0);
+
+        game.reset(7);
+        assert_eq!(game.guesses_remaining(), 3);
+    }
+
+    #[test]
+    fn test_game_over_message() {
+        let msg = WsMessage::GameOver { secret: 42 };
+        assert_eq!(msg.to_text(), "Game over! The secret was 42");
+        assert_eq!(
+            msg.to_json(),
+            r#"{"result":"game_over","secret":42,"hint":"Game over! The secret was 42"}"#
+        );
+    }
+
+    #[test]
+    fn test_history_in_guess_order() {
+        let mut game = GuessingGame::new(42);
+        assert!(game.history().is_empty());
+        game.guess(50);
+        game.guess(25);
+        game.guess(42);
+        assert_eq!(game.history(), &[50, 25, 42]);
+        // Guesses after the game is over are not recorded
+        game.guess(7);
+        assert_eq!(game.history(), &[50, 25, 42]);
+
+        game.reset(7);
+        assert!(game.history().is_empty());
+    }
+
+    #[test]
+    fn test_repeated_guess_detection() {
+        let mut game = GuessingGame::new(42);
+        assert!(!game.repeats_last_guess(50));
+        game.guess(50);
+        assert!(game.repeats_last_guess(50));
+        assert!(!game.repeats_last_guess(25));
+        game.guess(25);
+        assert!(!game.repeats_last_guess(50));
+    }
+
+    #[test]
+    fn test_secret_bounds() {
+        let mut game = GuessingGame::new(42);
+        assert_eq!(game.secret_bounds(1, 100), (1, 100));
+        game.guess(20);
+        game.guess(70);
+        game.guess(30);
+        game.guess(90);
+        assert_eq!(game.secret_bounds(1, 100), (31, 69));
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let mut game = GuessingGame::new(42);
+        game.guess(10);
+        assert_eq!(
+            game.to_summary_json(),
+            r#"{"guesses":1,"remaining":9,"done":false}"#
+        );
+        game.give_up();
+        assert!(game.to_summary_json().ends_with(r#""done":true}"#));
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let mut game = GuessingGame::new_with_difficulty(1234, Difficulty::Hard);
+        game.max_guesses = 7;
+        game.guess(10);
+        game.guess(20);
+        let restored = GuessingGame::deserialize(&game.serialize()).unwrap();
+        assert_eq!(restored.secret, 1234);
+        assert_eq!(restored.guesses, 2);
+        assert!(!restored.done);
+        assert_eq!(restored.difficulty, Difficulty::Hard);
+        assert_eq!(restored.max_guesses, 7);
+        assert!(restored.history.is_empty());
+
+        game.give_up();
+        assert!(GuessingGame::deserialize(&game.serialize()).unwrap().is_done());
+    }
+
+    #[test]
+    fn test_deserialize_rejects_invalid() {
+        let data = GuessingGame::new(42).serialize();
+        assert!(GuessingGame::deserialize(&[0; SERIALIZED_LEN]).is_none());
+        let mut bad_done = data;
+        bad_done[11] = 2;
+        assert!(GuessingGame::deserialize(&bad_done).is_none());
+        let mut bad_difficulty = data;
+        bad_difficulty[12] = 3;
+        assert!(GuessingGame::deserialize(&bad_difficulty).is_none());
+    }
+
+    #[test]
+    fn test_win_message_history_json() {
+        let msg = WsMessage::Win {
+            secret: 42,
+            attempts: 2,
+            history: vec![50, 42],
+        };
+        assert!(msg
+            .to_json()
+            .starts_with(r#"{"result":"win","secret":42,"attempts":2,"history":[50,42],"#));
+    }
+}
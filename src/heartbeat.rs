@@ -0,0 +1,276 @@
+//! WebSocket ping/pong heartbeat for detecting stale sessions
+//!
+//! A background task pings every registered session on a fixed interval.
+//! Sessions whose socket is gone, or which stop answering pings, are dropped
+//! from the game map and the session store so their state does not leak or
+//! get restored after a reboot.
+//!
+//! A second task closes sessions that are still connected but have not sent
+//! a message for `WS_IDLE_TIMEOUT_S`, e.g. a tab left open in the background.
+
+use anyhow::Result;
+use embedded_svc::ws::FrameType;
+use esp_idf_svc::hal::delay::FreeRtos;
+use esp_idf_svc::http::server::ws::EspHttpWsDetachedSender;
+use log::*;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::config::{WS_IDLE_TIMEOUT_S, WS_PING_INTERVAL_MS, WS_PONG_TIMEOUT_MS};
+use crate::session::SessionStore;
+use crate::state::SharedState;
+use crate::utils::now_ms;
+
+const HEARTBEAT_STACK_SIZE: usize = 4096;
+// How often the idle sweeper checks for sessions past the idle timeout
+const IDLE_SWEEP_INTERVAL_MS: u32 = 5000;
+const IDLE_TIMEOUT_MESSAGE: &str = "Session timed out due to inactivity";
+
+struct Peer {
+    sender: EspHttpWsDetachedSender,
+    last_seen_ms: u64,
+    ping_sent_ms: Option<u64>,
+    // ESP-IDF only forwards Pong frames to the handler when control frame
+    // passthrough is enabled, so the pong deadline is only enforced once a
+    // session has proven that its pongs actually reach us.
+    answers_pings: bool,
+}
+
+/// Registry of open WebSocket sessions tracked by the heartbeat task
+#[derive(Default)]
+pub struct Heartbeat {
+    peers: Mutex<BTreeMap<i32, Peer>>,
+    // Time of the last message frame per session, control frames don't count
+    last_activity_ms: Mutex<BTreeMap<i32, u64>>,
+}
+
+impl Heartbeat {
+    /// Start tracking a session
+    pub fn register(&self, session: i32, sender: EspHttpWsDetachedSender) {
+        self.peers.lock().unwrap().insert(
+            session,
+            Peer {
+                sender,
+                last_seen_ms: now_ms(),
+                ping_sent_ms: None,
+                answers_pings: false,
+            },
+        );
+        self.last_activity_ms
+            .lock()
+            .unwrap()
+            .insert(session, now_ms());
+        debug!("Heartbeat tracking session {}", session);
+    }
+
+    /// Stop tracking a session
+    pub fn unregister(&self, session: i32) {
+        self.last_activity_ms.lock().unwrap().remove(&session);
+        if self.peers.lock().unwrap().remove(&session).is_some() {
+            debug!("Heartbeat stopped tracking session {}", session);
+        }
+    }
+
+    /// Record a Pong frame from a session
+    pub fn pong(&self, session: i32) {
+        if let Some(peer) = self.peers.lock().unwrap().get_mut(&session) {
+            peer.last_seen_ms = now_ms();
+            peer.ping_sent_ms = None;
+            peer.answers_pings = true;
+            debug!("Pong from session {}", session);
+        }
+    }
+
+    /// Record any other frame from a session as proof of life
+    pub fn seen(&self, session: i32) {
+        if let Some(peer) = self.peers.lock().unwrap().get_mut(&session) {
+            peer.last_seen_ms = now_ms();
+            peer.ping_sent_ms = None;
+        }
+    }
+
+    /// Record a message frame from a session, resetting its idle timeout
+    pub fn active(&self, session: i32) {
+        if let Some(last) = self.last_activity_ms.lock().unwrap().get_mut(&session) {
+            *last = now_ms();
+        }
+    }
+
+    /// Detached senders for every tracked session
+    pub fn senders(&self) -> Vec<(i32, EspHttpWsDetachedSender)> {
+        self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(session, peer)| (*session, peer.sender.clone()))
+            .collect()
+    }
+
+    /// Ping every session and return the ones that should be dropped
+    fn sweep(&self) -> Vec<i32> {
+        let now = now_ms();
+        let mut stale = Vec::new();
+
+        // Collect senders first: a detached send blocks until the HTTP server
+        // task processes it, and that task may need this lock meanwhile.
+        let targets: Vec<(i32, EspHttpWsDetachedSender)> = {
+            let peers = self.peers.lock().unwrap();
+            for (session, peer) in peers.iter() {
+                if let Some(sent) = peer.ping_sent_ms {
+                    if peer.answers_pings && now.saturating_sub(sent) >= WS_PONG_TIMEOUT_MS {
+                        warn!(
+                            "Session {} did not answer ping within {} ms (last seen {} ms ago)",
+                            session,
+                            WS_PONG_TIMEOUT_MS,
+                            now.saturating_sub(peer.last_seen_ms)
+                        );
+                        stale.push(*session);
+                    }
+                }
+            }
+            peers
+                .iter()
+                .filter(|(session, _)| !stale.contains(session))
+                .map(|(session, peer)| (*session, peer.sender.clone()))
+                .collect()
+        };
+
+        for (session, mut sender) in targets {
+            if sender.is_closed() {
+                warn!("Session {} socket already closed", session);
+                stale.push(session);
+                continue;
+            }
+
+            match sender.send(FrameType::Ping, &[]) {
+                Ok(()) => {
+                    if let Some(peer) = self.peers.lock().unwrap().get_mut(&session) {
+                        peer.ping_sent_ms.get_or_insert(now);
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to ping session {}: {:?}", session, e);
+                    stale.push(session);
+                }
+            }
+        }
+
+        stale
+    }
+
+    /// Close every session idle for longer than `WS_IDLE_TIMEOUT_S`
+    fn sweep_idle(&self, state: &SharedState, store: &Mutex<SessionStore>) {
+        let idle = idle_sessions(&self.last_activity_ms.lock().unwrap(), now_ms());
+        for session in idle {
+            self.last_activity_ms.lock().unwrap().remove(&session);
+            let peer = self.peers.lock().unwrap().remove(&session);
+            let removed = remove_session(session, state, store);
+
+            if let Some(mut peer) = peer {
+                let sent = peer
+                    .sender
+                    .send(FrameType::Text(false), IDLE_TIMEOUT_MESSAGE.as_bytes())
+                    .and_then(|()| peer.sender.send(FrameType::Close, &[]));
+                if let Err(e) = sent {
+                    warn!("Failed to close idle session {}: {:?}", session, e);
+                }
+            }
+
+            info!(
+                "Closed WebSocket session {} after {} s without messages (game state removed: {})",
+                session, WS_IDLE_TIMEOUT_S, removed
+            );
+        }
+    }
+
+    /// Drop a stale session from the registry, the game map and the store
+    fn evict(&self, session: i32, state: &SharedState, store: &Mutex<SessionStore>) {
+        let peer = self.peers.lock().unwrap().remove(&session);
+        let removed = remove_session(session, state, store);
+
+        if let Some(mut peer) = peer {
+            // Best effort, the client is most likely gone already
+            let _ = peer.sender.send(FrameType::Close, &[]);
+        }
+
+        info!(
+            "Dropped stale WebSocket session {} (game state removed: {})",
+            session, removed
+        );
+    }
+}
+
+/// Remove a session's game and save the store if there was one
+/// The close handler finds the session gone afterwards and saves nothing.
+fn remove_session(session: i32, state: &SharedState, store: &Mutex<SessionStore>) -> bool {
+    let mut sessions = state.sessions();
+    let removed = sessions.remove(&session).is_some();
+    if removed {
+        store.lock().unwrap().save(&sessions);
+    }
+    removed
+}
+
+/// Spawn the background task pinging sessions every `WS_PING_INTERVAL_MS`
+pub fn spawn(
+    heartbeat: Arc<Heartbeat>,
+    state: SharedState,
+    store: Arc<Mutex<SessionStore>>,
+) -> Result<()> {
+    std::thread::Builder::new()
+        .name("ws_heartbeat".into())
+        .stack_size(HEARTBEAT_STACK_SIZE)
+        .spawn({
+            let heartbeat = heartbeat.clone();
+            let state = state.clone();
+            let store = store.clone();
+            move || loop {
+                FreeRtos::delay_ms(WS_PING_INTERVAL_MS as u32);
+
+                for session in heartbeat.sweep() {
+                    heartbeat.evict(session, &state, &store);
+                }
+            }
+        })?;
+
+    std::thread::Builder::new()
+        .name("ws_idle".into())
+        .stack_size(HEARTBEAT_STACK_SIZE)
+        .spawn(move || loop {
+            FreeRtos::delay_ms(IDLE_SWEEP_INTERVAL_MS);
+            heartbeat.sweep_idle(&state, &store);
+        })?;
+
+    info!(
+        "WebSocket heartbeat started (interval {} ms, pong timeout {} ms)",
+        WS_PING_INTERVAL_MS, WS_PONG_TIMEOUT_MS
+    );
+    info!("WebSocket idle timeout {} s", WS_IDLE_TIMEOUT_S);
+    Ok(())
+}
+
+/// Sessions whose last message is more than `WS_IDLE_TIMEOUT_S` before `now`
+fn idle_sessions(last_activity_ms: &BTreeMap<i32, u64>, now: u64) -> Vec<i32> {
+    last_activity_ms
+        .iter()
+        .filter(|(_, &last)| now.saturating_sub(last) > WS_IDLE_TIMEOUT_S * 1000)
+        .map(|(&session, _)| session)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_sessions() {
+        let timeout_ms = WS_IDLE_TIMEOUT_S * 1000;
+        let now = timeout_ms + 10_000;
+        let activity = BTreeMap::from([(1, 0), (2, 10_000), (3, now - 1)]);
+        assert_eq!(idle_sessions(&activity, now), vec![1]);
+        // A clock behind the last activity never counts as idle
+        assert!(idle_sessions(&activity, 0).is_empty());
+    }
+}
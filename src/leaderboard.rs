@@ -0,0 +1,261 @@
+//! Leaderboard of the best guessing game scores, persisted to NVS flash
+//!
+//! Entries are kept sorted by ascending guess count, so the best score is
+//! always first. Ties keep the earlier entry ahead of the later one.
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::*;
+
+use crate::config::LEADERBOARD_LEN;
+use crate::utils::{check_crc32, now_ms, write_crc32, CRC32_LEN};
+
+pub const NVS_NAMESPACE: &str = "leaderboard";
+pub const NVS_KEY: &str = "scores";
+
+// Layout: CRC-32 of the rest (u32 LE), version (1 byte), entry count
+// (1 byte), then per entry score (u32 LE) followed by timestamp (u64 LE)
+const FORMAT_VERSION: u8 = 2;
+const HEADER_LEN: usize = CRC32_LEN + 2;
+const ENTRY_LEN: usize = 12;
+const BLOB_LEN: usize = HEADER_LEN + LEADERBOARD_LEN * ENTRY_LEN;
+
+/// A single leaderboard entry
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+    /// Number of guesses needed to win
+    pub score: u32,
+    /// Milliseconds since boot when the score was recorded
+    pub timestamp: u64,
+}
+
+/// Fixed-size top scores table
+pub struct Leaderboard {
+    entries: [Entry; LEADERBOARD_LEN],
+    len: usize,
+    nvs: Option<EspNvs<NvsDefault>>,
+}
+
+impl Leaderboard {
+    /// Create an empty leaderboard that is not backed by flash
+    pub fn in_memory() -> Self {
+        Self {
+            entries: [Entry::default(); LEADERBOARD_LEN],
+            len: 0,
+            nvs: None,
+        }
+    }
+
+    /// Load the leaderboard from NVS
+    /// Falls back to an empty leaderboard if the stored data is missing or corrupt
+    pub fn load(partition: EspDefaultNvsPartition) -> Self {
+        let nvs = match EspNvs::new(partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open leaderboard NVS namespace: {:?}", e);
+                warn!("Continuing with in-memory leaderboard...");
+                return Self::in_memory();
+            }
+        };
+
+        let mut leaderboard = Self::in_memory();
+        let mut buf = [0u8; BLOB_LEN];
+        match nvs.get_blob(NVS_KEY, &mut buf) {
+            Ok(Some(data)) => match Self::decode(data) {
+                Some(loaded) => {
+                    leaderboard = loaded;
+                    info!("Loaded {} leaderboard entries from NVS", leaderboard.len);
+                }
+                None => warn!("Leaderboard data in NVS is corrupt, starting empty"),
+            },
+            Ok(None) => info!("No leaderboard stored in NVS yet"),
+            Err(e) => warn!("Failed to read leaderboard from NVS: {:?}", e),
+        }
+
+        leaderboard.nvs = Some(nvs);
+        leaderboard
+    }
+
+    /// Record a finished game and persist the table if it changed
+    /// Returns the 1-based rank of the new entry, or `None` if it did not qualify
+    pub fn record(&mut self, attempts: u32) -> Option<usize> {
+        let rank = self.insert(Entry {
+            score: attempts,
+            timestamp: now_ms(),
+        })?;
+        info!(
+            "New leaderboard entry: {} guesses at rank {}",
+            attempts, rank
+        );
+        self.save();
+        Some(rank)
+    }
+
+    /// The best `n` entries, best first
+    pub fn top_n(&self, n: usize) -> &[Entry] {
+        &self.entries[..n.min(self.len)]
+    }
+
+    /// Remove all entries, both in memory and in flash
+    #[allow(dead_code)] // Available for future admin/reset endpoints
+    pub fn clear(&mut self) {
+        self.entries = [Entry::default(); LEADERBOARD_LEN];
+        self.len = 0;
+        if let Some(nvs) = self.nvs.as_mut() {
+            if let Err(e) = nvs.remove(NVS_KEY) {
+                warn!("Failed to clear leaderboard in NVS: {:?}", e);
+            }
+        }
+        info!("Leaderboard cleared");
+    }
+
+    /// Serialize the full table as a JSON array
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .top_n(LEADERBOARD_LEN)
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                format!(
+                    r#"{{"rank":{},"attempts":{},"timestamp_ms":{}}}"#,
+                    i + 1,
+                    entry.score,
+                    entry.timestamp
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(","))
+    }
+
+    fn insert(&mut self, entry: Entry) -> Option<usize> {
+        let pos = self.entries[..self.len]
+            .iter()
+            .position(|e| entry.score < e.score)
+            .unwrap_or(self.len);
+        if pos >= LEADERBOARD_LEN {
+            return None;
+        }
+
+        // Shift worse entries down, dropping the last one if the table is full
+        let end = self.len.min(LEADERBOARD_LEN - 1);
+        self.entries.copy_within(pos..end, pos + 1);
+        self.entries[pos] = entry;
+        self.len = (self.len + 1).min(LEADERBOARD_LEN);
+        Some(pos + 1)
+    }
+
+    fn save(&mut self) {
+        let Some(nvs) = self.nvs.as_mut() else {
+            return;
+        };
+        let mut buf = [0u8; BLOB_LEN];
+        let len = Self::encode(&self.entries[..self.len], &mut buf);
+        if let Err(e) = nvs.set_blob(NVS_KEY, &buf[..len]) {
+            warn!("Failed to persist leaderboard to NVS: {:?}", e);
+        }
+    }
+
+    fn encode(entries: &[Entry], buf: &mut [u8; BLOB_LEN]) -> usize {
+        buf[CRC32_LEN] = FORMAT_VERSION;
+        buf[CRC32_LEN + 1] = entries.len() as u8;
+        for (i, entry) in entries.iter().enumerate() {
+            let offset = HEADER_LEN + i * ENTRY_LEN;
+            buf[offset..offset + 4].copy_from_slice(&entry.score.to_le_bytes());
+            buf[offset + 4..offset + ENTRY_LEN].copy_from_slice(&entry.timestamp.to_le_bytes());
+        }
+        let len = HEADER_LEN + entries.len() * ENTRY_LEN;
+        write_crc32(&mut buf[..len]);
+        len
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let data = check_crc32(data)?;
+        let (&version, rest) = data.split_first()?;
+        let (&count, rest) = rest.split_first()?;
+        let count = count as usize;
+        if version != FORMAT_VERSION || count > LEADERBOARD_LEN || rest.len() != count * ENTRY_LEN {
+            return None;
+        }
+
+        let mut leaderboard = Self::in_memory();
+        for (i, chunk) in rest.chunks_exact(ENTRY_LEN).enumerate() {
+            leaderboard.entries[i] = Entry {
+                score: u32::from_le_bytes(chunk[..4].try_into().ok()?),
+                timestamp: u64::from_le_bytes(chunk[4..].try_into().ok()?),
+            };
+        }
+        leaderboard.len = count;
+
+        // A table that is not sorted cannot have been written by us
+        if leaderboard
+            .top_n(count)
+            .windows(2)
+            .any(|w| w[0].score > w[1].score)
+        {
+            return None;
+        }
+        Some(leaderboard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(score: u32) -> Entry {
+        Entry {
+            score,
+            timestamp: score as u64 * 10,
+        }
+    }
+
+    #[test]
+    fn test_insert_keeps_ascending_order() {
+        let mut board = Leaderboard::in_memory();
+        assert_eq!(board.insert(entry(7)), Some(1));
+        assert_eq!(board.insert(entry(3)), Some(1));
+        assert_eq!(board.insert(entry(5)), Some(2));
+        let scores: Vec<u32> = board.top_n(10).iter().map(|e| e.score).collect();
+        assert_eq!(scores, vec![3, 5, 7]);
+    }
+
+    #[test]
+    fn test_insert_full_board_drops_worst() {
+        let mut board = Leaderboard::in_memory();
+        for score in 1..=LEADERBOARD_LEN as u32 {
+            board.insert(entry(score));
+        }
+        assert_eq!(board.insert(entry(LEADERBOARD_LEN as u32 + 1)), None);
+        assert_eq!(board.insert(entry(2)), Some(3));
+        assert_eq!(board.top_n(LEADERBOARD_LEN).len(), LEADERBOARD_LEN);
+        assert_eq!(board.top_n(LEADERBOARD_LEN)[LEADERBOARD_LEN - 1].score, 9);
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let mut board = Leaderboard::in_memory();
+        board.insert(entry(4));
+        board.insert(entry(2));
+        let mut buf = [0u8; BLOB_LEN];
+        let len = Leaderboard::encode(board.top_n(10), &mut buf);
+        let decoded = Leaderboard::decode(&buf[..len]).unwrap();
+        assert_eq!(decoded.top_n(10), board.top_n(10));
+    }
+
+    #[test]
+    fn test_decode_rejects_corrupt_data() {
+        assert!(Leaderboard::decode(&[]).is_none());
+        let mut truncated = [0, 0, 0, 0, FORMAT_VERSION, 1, 0, 0];
+        write_crc32(&mut truncated);
+        assert!(Leaderboard::decode(&truncated).is_none());
+        let mut bad_version = [0, 0, 0, 0, FORMAT_VERSION + 1, 0];
+        write_crc32(&mut bad_version);
+        assert!(Leaderboard::decode(&bad_version).is_none());
+
+        let mut board = Leaderboard::in_memory();
+        board.insert(entry(4));
+        let mut buf = [0u8; BLOB_LEN];
+        let len = Leaderboard::encode(board.top_n(1), &mut buf);
+        buf[len - 1] ^= 1;
+        assert!(Leaderboard::decode(&buf[..len]).is_none());
+    }
+}
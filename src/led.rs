@@ -0,0 +1,178 @@
+//! Status LED on `LED_GPIO`
+//!
+//! A background task blinks the LED in the pattern of the current server
+//! state, which handlers change with `set_state`:
+//!
+//! - starting up: fast blink
+//! - access point ready: slow blink (1 Hz)
+//! - WebSocket client connected: solid on
+//! - error: 5 Hz blink
+//!
+//! Boards with an addressable RGB LED on GPIO8 (e.g. the ESP32-C3 DevKitM)
+//! need a plain LED on another pin instead.
+
+use anyhow::Result;
+use esp_idf_svc::hal::{
+    delay::FreeRtos,
+    gpio::{AnyOutputPin, Output, PinDriver},
+};
+use log::*;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::config::LED_GPIO;
+
+const LED_STACK_SIZE: usize = 2048;
+// How often a solid LED checks for a new state
+const STATE_POLL_MS: u32 = 100;
+const STARTUP_BLINK_MS: u32 = 50;
+const AP_READY_BLINK_MS: u32 = 500;
+const ERROR_BLINK_MS: u32 = 100;
+
+static STATE: AtomicU8 = AtomicU8::new(LedState::Startup as u8);
+
+/// Server state shown on the LED
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LedState {
+    Startup,
+    ApReady,
+    ClientConnected,
+    Error,
+}
+
+impl LedState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Startup,
+            1 => Self::ApReady,
+            2 => Self::ClientConnected,
+            _ => Self::Error,
+        }
+    }
+
+    fn pattern(self) -> Pattern {
+        match self {
+            Self::Startup => Pattern::Blink {
+                on_ms: STARTUP_BLINK_MS,
+                off_ms: STARTUP_BLINK_MS,
+            },
+            Self::ApReady => Pattern::Blink {
+                on_ms: AP_READY_BLINK_MS,
+                off_ms: AP_READY_BLINK_MS,
+            },
+            Self::ClientConnected => Pattern::Solid,
+            Self::Error => Pattern::Blink {
+                on_ms: ERROR_BLINK_MS,
+                off_ms: ERROR_BLINK_MS,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pattern {
+    Solid,
+    Blink { on_ms: u32, off_ms: u32 },
+}
+
+/// Change the pattern shown by the LED task
+pub fn set_state(state: LedState) {
+    STATE.store(state as u8, Ordering::Relaxed);
+}
+
+fn state() -> LedState {
+    LedState::from_u8(STATE.load(Ordering::Relaxed))
+}
+
+/// The LED output pin
+pub struct Led {
+    pin: PinDriver<'static, AnyOutputPin, Output>,
+}
+
+impl Led {
+    pub fn new() -> Result<Self> {
+        // SAFETY: `LED_GPIO` is not used by anything else, see config.rs
+        let pin = unsafe { AnyOutputPin::new(LED_GPIO) };
+        Ok(Self {
+            pin: PinDriver::output(pin)?,
+        })
+    }
+
+    pub fn solid(&mut self) {
+        if let Err(e) = self.pin.set_high() {
+            warn!("Failed to turn LED on: {:?}", e);
+        }
+    }
+
+    pub fn off(&mut self) {
+        if let Err(e) = self.pin.set_low() {
+            warn!("Failed to turn LED off: {:?}", e);
+        }
+    }
+
+    /// Blink once: on for `on_ms`, then off for `off_ms`
+    pub fn blink(&mut self, on_ms: u32, off_ms: u32) {
+        self.solid();
+        FreeRtos::delay_ms(on_ms);
+        self.off();
+        FreeRtos::delay_ms(off_ms);
+    }
+}
+
+/// Spawn the task showing the current state on the LED
+pub fn spawn() -> Result<()> {
+    let mut led = Led::new()?;
+
+    std::thread::Builder::new()
+        .name("led".into())
+        .stack_size(LED_STACK_SIZE)
+        .spawn(move || loop {
+            match state().pattern() {
+                Pattern::Solid => {
+                    led.solid();
+                    FreeRtos::delay_ms(STATE_POLL_MS);
+                }
+                Pattern::Blink { on_ms, off_ms } => led.blink(on_ms, off_ms),
+            }
+        })?;
+
+    info!("Status LED on GPIO{}", LED_GPIO);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        for state in [
+            LedState::Startup,
+            LedState::ApReady,
+            LedState::ClientConnected,
+            LedState::Error,
+        ] {
+            assert_eq!(LedState::from_u8(state as u8), state);
+        }
+    }
+
+    #[test]
+    fn test_patterns() {
+        // 1 Hz and 5 Hz: one on/off cycle per 1000 and 200 ms
+        assert_eq!(
+            LedState::ApReady.pattern(),
+            Pattern::Blink {
+                on_ms: 500,
+                off_ms: 500
+            }
+        );
+        assert_eq!(
+            LedState::Error.pattern(),
+            Pattern::Blink {
+                on_ms: 100,
+                off_ms: 100
+            }
+        );
+        assert_eq!(LedState::ClientConnected.pattern(), Pattern::Solid);
+    }
+}
@@ -0,0 +1,124 @@
+//! Logger keeping the most recent log lines in RAM
+//!
+//! Wraps the ESP-IDF logger so everything still goes to the serial console,
+//! and keeps the last `LOG_BUFFER_LINES` lines for GET /logs/stream.
+
+use esp_idf_svc::log::EspLogger;
+use log::{Log, Metadata, Record};
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::config::{LOG_BUFFER_LINES, LOG_LINE_MAX_LEN};
+use crate::utils::now_ms;
+
+static LOGGER: RingLogger = RingLogger {
+    inner: EspLogger::new(),
+    lines: Mutex::new(VecDeque::new()),
+};
+
+struct RingLogger {
+    inner: EspLogger,
+    lines: Mutex<VecDeque<String>>,
+}
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.log(record);
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_line(now_ms(), record);
+        // Never block or panic while logging, dropping a line is fine
+        if let Ok(mut lines) = self.lines.try_lock() {
+            push_line(&mut lines, line);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install the logger, replaces `EspLogger::initialize_default`
+pub fn init() {
+    log::set_logger(&LOGGER)
+        .map(|()| log::set_max_level(LOGGER.inner.get_max_level()))
+        .unwrap();
+}
+
+/// Copy of the buffered lines, oldest first
+pub fn lines() -> Vec<String> {
+    LOGGER.lines.lock().unwrap().iter().cloned().collect()
+}
+
+fn format_line(timestamp_ms: u64, record: &Record) -> String {
+    let mut line = format!(
+        "{} {} {}: {}",
+        timestamp_ms,
+        record.level(),
+        record.target(),
+        record.args()
+    );
+    if line.len() > LOG_LINE_MAX_LEN {
+        let mut end = LOG_LINE_MAX_LEN;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        line.truncate(end);
+    }
+    line
+}
+
+fn push_line(lines: &mut VecDeque<String>, line: String) {
+    if lines.len() >= LOG_BUFFER_LINES {
+        lines.pop_front();
+    }
+    lines.push_back(line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn test_format_line() {
+        let line = format_line(
+            1500,
+            &Record::builder()
+                .args(format_args!("hello {}", 42))
+                .level(Level::Warn)
+                .target("fw::test")
+                .build(),
+        );
+        assert_eq!(line, "1500 WARN fw::test: hello 42");
+    }
+
+    #[test]
+    fn test_format_line_truncates() {
+        let long = "é".repeat(LOG_LINE_MAX_LEN);
+        let line = format_line(
+            0,
+            &Record::builder()
+                .args(format_args!("{}", long))
+                .level(Level::Info)
+                .build(),
+        );
+        assert!(line.len() <= LOG_LINE_MAX_LEN);
+        assert!(line.ends_with('é'));
+    }
+
+    #[test]
+    fn test_push_line_drops_oldest() {
+        let mut lines = VecDeque::new();
+        for i in 0..LOG_BUFFER_LINES + 2 {
+            push_line(&mut lines, i.to_string());
+        }
+        assert_eq!(lines.len(), LOG_BUFFER_LINES);
+        assert_eq!(lines.front().unwrap(), "2");
+        assert_eq!(lines.back().unwrap(), &(LOG_BUFFER_LINES + 1).to_string());
+    }
+}
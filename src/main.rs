@@ -2,33 +2,139 @@
 //!
 //! Go to http://192.168.71.1 to play
 
+mod auth;
+mod button;
+mod captive_dns;
+mod channel_selection;
 mod config;
+#[cfg(feature = "debug-console")]
+mod debug_console;
+mod error;
+#[cfg(feature = "game")]
 mod guessing_game;
+#[cfg(feature = "game")]
+mod heartbeat;
+#[cfg(feature = "game")]
+mod leaderboard;
+mod led;
+mod log_buffer;
+mod math_quiz;
+#[cfg(feature = "oled")]
 mod oled;
+mod rate_limit;
+mod request_log;
+mod reset;
 mod rssi;
 mod server;
+#[cfg(feature = "game")]
+mod session;
+mod state;
+mod task_stats;
+#[cfg(feature = "tournament")]
+mod tournament;
 mod utils;
+#[cfg(feature = "scan")]
+mod wifi_scan;
+mod word_guess;
+mod ws_utils;
 
+#[cfg(feature = "game")]
 use core::cmp::Ordering;
-use embedded_svc::{http::Method, io::Write, ws::FrameType};
+use embedded_svc::{
+    http::{Headers, Method},
+    io::Write,
+    ws::FrameType,
+};
 use esp_idf_svc::{
-    hal::peripherals::Peripherals,
-    sys::{EspError, ESP_ERR_INVALID_SIZE},
+    hal::{delay::FreeRtos, peripherals::Peripherals, reset::restart},
+    nvs::EspDefaultNvsPartition,
+    ota::EspOta,
+    sys::{EspError, ESP_ERR_NO_MEM},
 };
 use log::*;
-use std::{collections::BTreeMap, ffi::CStr, sync::{Arc, Mutex}};
+use std::{
+    collections::BTreeMap,
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
+#[cfg(any(feature = "game", feature = "oled"))]
+use std::ffi::CStr;
 
-use crate::config::{INDEX_HTML, MAX_DISPLAY_LEN, MAX_LEN};
-use crate::guessing_game::GuessingGame;
+use crate::auth::{check_admin_token, require_auth};
+use crate::button::ButtonContext;
+use crate::config::{
+    INDEX_HTML, INDEX_HTML_ETAG, MAX_CONFIG_BODY_LEN, MAX_LEN, MAX_PROVISION_BODY_LEN,
+    MAX_REBOOT_DELAY_S, MAX_RSSI_EXPORT_S, MAX_WS_SESSIONS, NOT_FOUND_HTML, OTA_CHUNK_LEN,
+    REBOOT_DEFAULT_DELAY_S, REBOOT_STACK_SIZE, RESET_REBOOT_DELAY_MS, RSSI_EXPORT_DEFAULT_S,
+    RSSI_POLL_INTERVAL_MS, SETUP_SSID, STATIONS_RATE_LIMIT_REQUESTS, VERSION_JSON, WORD_LEN,
+};
+#[cfg(feature = "game")]
+use crate::config::{GUESS_JSON_SUBPROTOCOL, MAX_BROADCAST_BODY_LEN, WS_MAX_MSG_PER_SEC};
+#[cfg(all(feature = "game", feature = "oled"))]
+use crate::config::OLED_PROGRESS_BAR_Y;
+#[cfg(feature = "scan")]
+use crate::config::SCAN_RATE_LIMIT_REQUESTS;
+#[cfg(feature = "oled")]
+use crate::config::{MAX_DISPLAY_LEN, OLED_SCROLL_DELAY_MS};
+use crate::error::ServerError;
+#[cfg(feature = "game")]
+use crate::guessing_game::{bounds_hint_json, GuessingGame, WsIncoming, WsMessage};
+#[cfg(feature = "game")]
+use crate::heartbeat::Heartbeat;
+#[cfg(feature = "game")]
+use crate::leaderboard::Leaderboard;
+use crate::led::LedState;
+use crate::math_quiz::MathQuiz;
+#[cfg(feature = "oled")]
 use crate::oled::OledDisplay;
-use crate::rssi::{calculate_distance_from_rssi, get_station_rssi};
-use crate::server::create_server;
-use crate::utils::{nth, rand};
+use crate::rate_limit::RateLimiter;
+use crate::request_log::logged;
+use crate::reset::ResetScope;
+use crate::rssi::{
+    calculate_distance_from_rssi, calibrate, calibration, classify_signal, export_csv_row,
+    filtered_distances, get_nearest_station, get_stations, latest_station_rssi, load_calibration,
+    rssi_stats, set_calibration, stations_to_json, to_percentage, RssiReading,
+    EXPORT_CSV_HEADER,
+};
+use crate::server::{
+    client_ipv4, configure_watchdog, create_server, feed_watchdog, rate_limited, rate_limited_to,
+    respond, respond_html, respond_json, set_tcp_keepalive, start_response, too_many_requests,
+    ChunkedWriter, Credentials, NetworkInfo, Provisioning, WatchdogCheckpoint, WifiMode,
+};
+#[cfg(feature = "game")]
+use crate::server::{peer_ipv4, ws_handler_version, ws_handler_with_subprotocol};
+#[cfg(feature = "game")]
+use crate::session::{new_session_secret, SecretScope, Session, SessionStore, WsProtocol};
+use crate::state::SharedState;
+use crate::utils::{
+    etag_matches, extract_json_f32, extract_json_string, extract_json_u32, format_duration,
+    get_request_header, http_date, now_ms, now_us, parse_mac_address, query_params,
+    read_json_body,
+};
+#[cfg(feature = "game")]
+use crate::utils::{extract_json_bool, parse_query_string, rand};
+use crate::word_guess::WordGuess;
+#[cfg(feature = "game")]
+use crate::ws_utils::{spawn_broadcast, spawn_broadcast_and_close, spawn_close};
 
 
 fn main() -> anyhow::Result<()> {
     esp_idf_svc::sys::link_patches();
-    esp_idf_svc::log::EspLogger::initialize_default();
+    log_buffer::init();
+
+    // Confirm this image boots, otherwise the bootloader rolls back to the
+    // previous one on the next reset
+    match EspOta::new().and_then(|mut ota| ota.mark_running_slot_valid()) {
+        Ok(()) => info!("Running firmware marked valid"),
+        Err(e) => warn!("Failed to mark running firmware valid: {:?}", e),
+    }
+
+    // Reset the chip if setup or a handler hangs, instead of only on a starved CPU
+    if let Err(e) = configure_watchdog() {
+        warn!("Failed to configure the task watchdog: {:?}", e);
+    }
+    // Subscribes the main task with esp_task_wdt_add(NULL) until main() returns
+    let _watchdog = WatchdogCheckpoint::start();
 
     info!("Starting HTTP/WebSocket server...");
 
@@ -36,12 +142,23 @@ fn main() -> anyhow::Result<()> {
     let peripherals = Peripherals::take()?;
     
     // Extract what we need for OLED and WiFi
+    #[cfg(feature = "oled")]
     let i2c = peripherals.i2c0;
+    #[cfg(feature = "oled")]
     let sda = peripherals.pins.gpio5;
+    #[cfg(feature = "oled")]
     let scl = peripherals.pins.gpio6;
     let modem = peripherals.modem;
+    let button_pin = peripherals.pins.gpio0;
+
+    // Fast blink until the access point is up
+    if let Err(e) = led::spawn() {
+        warn!("Failed to start status LED: {:?}", e);
+        warn!("Continuing without status LED...");
+    }
     
     // Initialize OLED display (uses I2C0, GPIO5, GPIO6)
+    #[cfg(feature = "oled")]
     let oled_display = match OledDisplay::init(i2c, sda, scl) {
         Ok(display) => {
             info!("OLED display initialized successfully");
@@ -57,88 +174,822 @@ fn main() -> anyhow::Result<()> {
         }
     };
 
-    let mut server = create_server(modem)?;
+    // The default NVS partition can only be taken once, share it by cloning
+    let nvs = EspDefaultNvsPartition::take()?;
+    #[cfg(feature = "game")]
+    let leaderboard = Arc::new(Mutex::new(Leaderboard::load(nvs.clone())));
+    load_calibration(nvs.clone());
+    // Games that were running when the board went down, resumed by client IP
+    #[cfg(feature = "game")]
+    let (session_store, restored_games) = SessionStore::load(nvs.clone());
+    #[cfg(feature = "game")]
+    let session_store = Arc::new(Mutex::new(session_store));
+    #[cfg(feature = "game")]
+    let restored_games = Mutex::new(restored_games);
+    let nvs_for_calibration = nvs.clone();
+    let nvs_for_rssi_config = nvs.clone();
+    let nvs_for_provisioning = nvs.clone();
+    let nvs_for_button = nvs.clone();
+    let nvs_for_reset = nvs.clone();
+
+    let provisioning = Provisioning::load(nvs.clone());
+    let (mut server, wifi_status) = create_server(modem, nvs, &provisioning)
+        .inspect_err(|_| led::set_state(LedState::Error))?;
+    led::set_state(LedState::ApReady);
+
+    // Point every DNS lookup from AP clients at us for the captive portal,
+    // except in mixed mode where lookups for the upstream network must work
+    if wifi_status.mode == WifiMode::Mixed {
+        info!("Upstream network joined, captive portal DNS disabled");
+    } else if let Err(e) = captive_dns::spawn(wifi_status.ap_ip) {
+        warn!("Failed to start captive portal DNS: {:?}", e);
+    }
+
+    // Shared per-IP rate limiter for all HTTP endpoints
+    let rate_limiter = Arc::new(Mutex::new(RateLimiter::default()));
+
+    // Until credentials are provisioned, only POST /provision is served
+    #[cfg_attr(not(feature = "oled"), allow(unused_variables))]
+    let credentials = match provisioning {
+        Provisioning::Provisioned(credentials) => credentials,
+        Provisioning::Setup => {
+            warn!("No Wi-Fi credentials provisioned, join `{}` to set them", SETUP_SSID);
+            #[cfg(feature = "oled")]
+            if let Some(oled) = &oled_display {
+                if let Err(e) = oled.display_message(&format!("Setup: join {}", SETUP_SSID)) {
+                    warn!("Failed to display setup message: {:?}", e);
+                }
+            }
+
+            let provisioning = Mutex::new(provisioning);
+            let limiter_for_provision = rate_limiter.clone();
+            server.fn_handler("/provision", Method::Post, logged(move |mut req| {
+                if rate_limited(&limiter_for_provision, &mut req) {
+                    return too_many_requests(req);
+                }
+                let body = match read_json_body(&mut req, MAX_PROVISION_BODY_LEN) {
+                    Ok(body) => body,
+                    Err(e) => return ServerError::from_body_error(e).respond(req),
+                };
+                // The body holds passwords, so it is not logged
+                let credentials = match Credentials::from_json(&body) {
+                    Ok(credentials) => credentials,
+                    Err(reason) => {
+                        warn!("Rejected provisioned credentials: {}", reason);
+                        return ServerError::BadRequest(reason.to_string()).respond(req);
+                    }
+                };
+                let stored = provisioning
+                    .lock()
+                    .unwrap()
+                    .provision(nvs_for_provisioning.clone(), credentials);
+                if let Err(e) = stored {
+                    return ServerError::Io(e).respond(req);
+                }
+
+                info!("Provisioned, rebooting into normal operation...");
+                respond_json(req, 202, r#"{"rebooting":true}"#)?;
+
+                // Give the response a moment to reach the client
+                FreeRtos::delay_ms(500);
+                restart();
+            }))?;
+
+            info!("Setup server started. Waiting for credentials...");
+            core::mem::forget(server);
+            return Ok(());
+        }
+    };
+
+    let limiter_for_index = rate_limiter.clone();
+    server.fn_handler("/", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_index, &mut req) {
+            return too_many_requests(req);
+        }
+        // Browsers must revalidate every time, which is cheap thanks to the ETag
+        let cache_headers = [
+            ("Cache-Control", "no-cache"),
+            ("ETag", INDEX_HTML_ETAG),
+            ("Connection", "keep-alive"),
+        ];
+        if get_request_header(&req, "If-None-Match")
+            .is_some_and(|tags| etag_matches(tags, INDEX_HTML_ETAG))
+        {
+            debug!("Index page not modified for {}", req.uri());
+            start_response(req, 304, Some("Not Modified"), &cache_headers)?;
+            return Ok(());
+        }
 
-    server.fn_handler("/", Method::Get, |req| {
         info!("Serving index page to client from {}", req.uri());
-        let mut resp = req
-            .into_response(200, Some("OK"), &[
-                ("Content-Type", "text/html; charset=utf-8"),
-                ("Cache-Control", "no-cache, no-store, must-revalidate"),
-                ("Pragma", "no-cache"),
-                ("Expires", "0"),
-                ("Connection", "keep-alive"),
-            ])
-            .map_err(|e| {
-                error!("Error creating response: {:?}", e);
-                EspError::from_infallible::<ESP_ERR_INVALID_SIZE>()
-            })?;
-        resp.write_all(INDEX_HTML.as_bytes()).map_err(|e| {
-            error!("Error writing response: {:?}", e);
-            EspError::from_infallible::<ESP_ERR_INVALID_SIZE>()
-        })?;
+        respond(req, 200, "text/html; charset=utf-8", &cache_headers, INDEX_HTML.as_bytes())?;
         info!("Index page served successfully");
         Ok::<(), EspError>(())
-    })?;
+    }))?;
 
     // Health check endpoint
-    server.fn_handler("/health", Method::Get, |req| {
+    let limiter_for_health = rate_limiter.clone();
+    server.fn_handler("/health", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_health, &mut req) {
+            return too_many_requests(req);
+        }
         info!("Health check request from {}", req.uri());
-        let mut resp = req
-            .into_response(200, Some("OK"), &[("Content-Type", "text/plain")])
-            .map_err(|e| {
-                error!("Error creating health response: {:?}", e);
-                EspError::from_infallible::<ESP_ERR_INVALID_SIZE>()
-            })?;
-        resp.write_all(b"OK").map_err(|e| {
-            error!("Error writing health response: {:?}", e);
-            EspError::from_infallible::<ESP_ERR_INVALID_SIZE>()
-        })?;
+        respond(req, 200, "text/plain", &[], b"OK")
+    }))?;
+
+    // Lighter liveness check than /health, the page times it for the round trip
+    let limiter_for_ping = rate_limiter.clone();
+    server.fn_handler("/ping", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_ping, &mut req) {
+            return too_many_requests(req);
+        }
+        let server_time_us = now_us();
+        info!("Ping answered at {} us", server_time_us);
+        let last_modified = http_date(server_time_us / 1_000_000);
+        let response = format!(r#"{{"server_time_us":{},"pong":true}}"#, server_time_us);
+        let headers = [("Last-Modified", last_modified.as_str())];
+        respond(req, 200, "application/json", &headers, response.as_bytes())
+    }))?;
+
+    // Firmware build metadata
+    let limiter_for_version = rate_limiter.clone();
+    server.fn_handler("/version", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_version, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("Version request received");
+        respond_json(req, 200, VERSION_JSON)
+    }))?;
+
+    // Recent log lines, streamed in chunks as plain text
+    let limiter_for_logs = rate_limiter.clone();
+    server.fn_handler("/logs/stream", Method::Get, logged(require_auth(move |mut req| {
+        if rate_limited(&limiter_for_logs, &mut req) {
+            return too_many_requests(req);
+        }
+        // Copy first so logging from other tasks isn't blocked while sending
+        let lines = log_buffer::lines();
+        info!("Streaming {} log lines", lines.len());
+        let resp = start_response(req, 200, Some("OK"), &[("Content-Type", "text/plain; charset=utf-8")])?;
+        let mut writer = ChunkedWriter::new(resp);
+        for line in &lines {
+            writer
+                .write_all(line.as_bytes())
+                .and_then(|()| writer.write_all(b"\n"))
+                .map_err(|e| ServerError::from(e).into_esp_error())?;
+        }
+        writer.flush().map_err(|e| ServerError::from(e).into_esp_error())?;
         Ok::<(), EspError>(())
-    })?;
+    })))?;
+
+    // Captive portal probes: answer the connectivity checks of Android and
+    // Windows, and send Apple devices to the game page
+    let limiter_for_generate_204 = rate_limiter.clone();
+    server.fn_handler("/generate_204", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_generate_204, &mut req) {
+            return too_many_requests(req);
+        }
+        debug!("Captive portal probe {}", req.uri());
+        start_response(req, 204, Some("No Content"), &[])?;
+        Ok::<(), EspError>(())
+    }))?;
+
+    let portal_url = format!("http://{}/", wifi_status.ap_ip);
+    let limiter_for_hotspot_detect = rate_limiter.clone();
+    server.fn_handler("/hotspot-detect.html", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_hotspot_detect, &mut req) {
+            return too_many_requests(req);
+        }
+        debug!("Captive portal probe {}, redirecting to {}", req.uri(), portal_url);
+        start_response(req, 302, Some("Found"), &[("Location", portal_url.as_str())])?;
+        Ok::<(), EspError>(())
+    }))?;
+
+    for (path, body) in [
+        ("/connecttest.txt", "Microsoft Connect Test"),
+        ("/ncsi.txt", "Microsoft NCSI"),
+    ] {
+        let limiter_for_ncsi = rate_limiter.clone();
+        server.fn_handler(path, Method::Get, logged(move |mut req| {
+            if rate_limited(&limiter_for_ncsi, &mut req) {
+                return too_many_requests(req);
+            }
+            debug!("Captive portal probe {}", req.uri());
+            respond(req, 200, "text/plain", &[], body.as_bytes())
+        }))?;
+    }
+
+    // Game sessions, RSSI history and the other state shared between
+    // handlers and background tasks, see state.rs for the locking rules
+    let app_state = SharedState::default();
 
     // Add endpoint to get RSSI and distance
-    server.fn_handler("/rssi", Method::Get, |req| {
+
+    // Reports the first station in AP list order, GET /rssi/nearest the closest one
+    // Reads the cache of the polling task instead of querying the Wi-Fi driver
+    let limiter_for_rssi = rate_limiter.clone();
+    server.fn_handler("/rssi", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_rssi, &mut req) {
+            return too_many_requests(req);
+        }
         info!("RSSI request received");
-        let rssi = get_station_rssi();
+        let station = latest_station_rssi();
 
-        let response = if let Some(rssi_value) = rssi {
-            let distance = calculate_distance_from_rssi(rssi_value);
+        let response = if let Some((rssi_value, mac)) = station {
+            let raw_distance = calculate_distance_from_rssi(rssi_value);
+            // Smoothed by the polling task over successive readings to hide
+            // RSSI jitter, raw until the filters saw their first reading
+            let (distance, filtered_distance) =
+                filtered_distances().unwrap_or((raw_distance, raw_distance));
+            let quality = classify_signal(rssi_value);
+            let (rssi_mean, rssi_std_dev) = rssi_stats();
             info!(
                 "Sending RSSI response: RSSI={} dBm, Distance={:.2} m",
                 rssi_value, distance
             );
             format!(
-                r#"{{"rssi": {}, "distance": {:.2}, "unit": "meters", "raw_distance": {:.4}}}"#,
-                rssi_value, distance, distance
+                r#"{{"rssi": {}, "rssi_mean": {:.1}, "rssi_std_dev": {:.1}, "distance": {:.2}, "unit": "meters", "raw_distance": {:.4}, "filtered_distance": {:.2}, "signal_quality": "{}", "signal_bars": {}, "signal_pct": {}, "mac": "{}"}}"#,
+                rssi_value,
+                rssi_mean,
+                rssi_std_dev,
+                distance,
+                raw_distance,
+                filtered_distance,
+                quality.as_str(),
+                quality.as_bars(),
+                to_percentage(rssi_value),
+                parse_mac_address(&mac)
             )
         } else {
             warn!("No RSSI available - no connected stations");
-            r#"{"rssi": null, "distance": null, "error": "No connected station"}"#.to_string()
-        };
-
-        let mut resp = req
-            .into_response(200, Some("OK"), &[("Content-Type", "application/json")])
-            .map_err(|e| {
-                error!("Error creating response: {:?}", e);
-                EspError::from_infallible::<ESP_ERR_INVALID_SIZE>()
-            })?;
-        resp.write_all(response.as_bytes()).map_err(|e| {
-            error!("Error writing response: {:?}", e);
-            EspError::from_infallible::<ESP_ERR_INVALID_SIZE>()
-        })?;
+            r#"{"rssi": null, "rssi_mean": null, "rssi_std_dev": null, "distance": null, "filtered_distance": null, "signal_quality": "NoSignal", "signal_bars": 0, "signal_pct": 0, "error": "No connected station"}"#
+                .to_string()
+        };
+
+        respond_json(req, 200, &response)
+    }))?;
+
+    // Station with the strongest signal, i.e. presumably the closest one
+    let limiter_for_nearest = rate_limiter.clone();
+    server.fn_handler("/rssi/nearest", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_nearest, &mut req) {
+            return too_many_requests(req);
+        }
+        let response = match get_nearest_station() {
+            Some((rssi, mac)) => format!(
+                r#"{{"mac":"{}","rssi":{},"distance_m":{:.1},"signal_quality":"{}"}}"#,
+                parse_mac_address(&mac),
+                rssi,
+                calculate_distance_from_rssi(rssi),
+                classify_signal(rssi).as_str()
+            ),
+            None => {
+                r#"{"mac": null, "rssi": null, "distance_m": null, "signal_quality": "NoSignal", "error": "No connected station"}"#
+                    .to_string()
+            }
+        };
+
+        respond_json(req, 200, &response)
+    }))?;
+
+    // All stations connected to the access point, with its own lower rate limit
+    let stations_limiter = Arc::new(Mutex::new(RateLimiter::default()));
+    let app_state_for_stations = app_state.clone();
+    server.fn_handler("/wifi/stations", Method::Get, logged(move |mut req| {
+        if rate_limited_to(&stations_limiter, &mut req, STATIONS_RATE_LIMIT_REQUESTS) {
+            return too_many_requests(req);
+        }
+        let stations: Vec<([u8; 6], i8, f32)> = get_stations()
+            .into_iter()
+            .map(|(rssi, mac)| (mac, rssi, calculate_distance_from_rssi(rssi)))
+            .collect();
+        info!("Listing {} connected stations", stations.len());
+        let timestamp_ms = now_ms();
+        let mut history = app_state_for_stations.rssi_history();
+        for &(mac, rssi, distance_m) in &stations {
+            history.push(mac, RssiReading {
+                timestamp_ms,
+                rssi,
+                distance_m,
+            });
+        }
+        drop(history);
+
+        respond_json(req, 200, &stations_to_json(&stations))
+    }))?;
+
+    // Nearby access points, only in mixed mode where there is a station interface to scan with
+    #[cfg(feature = "scan")]
+    let scan_limiter = Arc::new(Mutex::new(RateLimiter::default()));
+    #[cfg(feature = "scan")]
+    server.fn_handler("/wifi/scan", Method::Get, logged(move |mut req| {
+        if rate_limited_to(&scan_limiter, &mut req, SCAN_RATE_LIMIT_REQUESTS) {
+            return too_many_requests(req);
+        }
+        if wifi_status.mode != WifiMode::Mixed {
+            let msg = format!("scanning needs mixed mode, running in {}", wifi_status.mode.name());
+            return ServerError::Unavailable(msg).respond(req);
+        }
+        let aps = match wifi_scan::scan() {
+            Ok(aps) => aps,
+            Err(e) => {
+                warn!("Wi-Fi scan failed: {:?}", e);
+                return ServerError::Unavailable("scan failed".to_string()).respond(req);
+            }
+        };
+        info!("Scan found {} access points", aps.len());
+        respond_json(req, 200, &wifi_scan::to_json(aps))
+    }))?;
+
+    // Access point addresses, IPv6 included, to check reachability without a serial console
+    let limiter_for_network = rate_limiter.clone();
+    server.fn_handler("/network", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_network, &mut req) {
+            return too_many_requests(req);
+        }
+        let info = NetworkInfo::query(wifi_status.ap_ip, wifi_status.ssid, get_stations().len());
+        info!("Network info request received: {:?}", info);
+        let response = info.to_json();
+
+        respond_json(req, 200, &response)
+    }))?;
+
+    // CSV export of recent RSSI readings for diagnosing connectivity
+    let limiter_for_rssi_history = rate_limiter.clone();
+    let app_state_for_history = app_state.clone();
+    server.fn_handler("/rssi/history", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_rssi_history, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("RSSI history request received");
+        // Readings of the station last sampled by the polling task
+        let csv = {
+            let history = app_state_for_history.rssi_history();
+            history.to_csv(&history.latest_station().unwrap_or_default())
+        };
+
+        respond(req, 200, "text/csv", &[], csv.as_bytes())
+    }))?;
+
+    // CSV download of the readings of all stations over the last `duration_s` seconds
+    // e.g. /rssi/export.csv?duration_s=300&station=AA%3ABB%3ACC%3ADD%3AEE%3AFF
+    let limiter_for_rssi_export = rate_limiter.clone();
+    let app_state_for_export = app_state.clone();
+    server.fn_handler("/rssi/export.csv", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_rssi_export, &mut req) {
+            return too_many_requests(req);
+        }
+        let params = query_params(req.uri());
+        let duration_s = params
+            .get("duration_s")
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(RSSI_EXPORT_DEFAULT_S)
+            .min(MAX_RSSI_EXPORT_S);
+        // Optionally only export one station, given as AA:BB:CC:DD:EE:FF
+        let station = params.get("station");
+        // Copy first so the history isn't locked while sending
+        let mut readings = app_state_for_export
+            .rssi_history()
+            .readings_since(now_ms().saturating_sub(duration_s * 1000));
+        if let Some(station) = station {
+            readings.retain(|(mac, _)| parse_mac_address(mac).eq_ignore_ascii_case(station));
+        }
+        info!("Exporting {} RSSI readings from the last {} s", readings.len(), duration_s);
+
+        let resp = start_response(req, 200, Some("OK"), &[
+            ("Content-Type", "text/csv"),
+            ("Content-Disposition", r#"attachment; filename="rssi_export.csv""#),
+        ])?;
+        let mut writer = ChunkedWriter::new(resp);
+        writer
+            .write_all(EXPORT_CSV_HEADER.as_bytes())
+            .map_err(|e| ServerError::from(e).into_esp_error())?;
+        for (mac, reading) in &readings {
+            writer
+                .write_all(export_csv_row(mac, reading).as_bytes())
+                .map_err(|e| ServerError::from(e).into_esp_error())?;
+        }
+        writer.flush().map_err(|e| ServerError::from(e).into_esp_error())?;
         Ok::<(), EspError>(())
-    })?;
+    }))?;
+
+    // Path loss model parameters used for distance estimates
+    let limiter_for_rssi_config = rate_limiter.clone();
+    server.fn_handler("/rssi/config", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_rssi_config, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("RSSI config request received");
+        let response = calibration().to_json();
+
+        respond_json(req, 200, &response)
+    }))?;
+
+    // Calibrate the RSSI at 1m with the station placed at a known distance
+    let limiter_for_calibrate = rate_limiter.clone();
+    server.fn_handler("/rssi/calibrate", Method::Post, logged(move |mut req| {
+        if rate_limited(&limiter_for_calibrate, &mut req) {
+            return too_many_requests(req);
+        }
+        let body = match read_json_body(&mut req, MAX_CONFIG_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => return ServerError::from_body_error(e).respond(req),
+        };
+        let Some(Ok(known_distance_m)) = extract_json_f32(&body, "known_distance_m") else {
+            return ServerError::BadRequest("expected {\"known_distance_m\":<meters>}".to_string())
+                .respond(req);
+        };
+
+        match calibrate(nvs_for_calibration.clone(), known_distance_m) {
+            Ok(state) => {
+                respond_json(req, 200, &state.to_json())
+            }
+            Err(reason) => ServerError::BadRequest(reason.to_string()).respond(req),
+        }
+    }))?;
+
+    // Path loss model parameters together with their defaults
+    let limiter_for_rssi_config_get = rate_limiter.clone();
+    server.fn_handler("/config/rssi", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_rssi_config_get, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("RSSI config request received");
+        let response = calibration().to_json_with_defaults();
+
+        respond_json(req, 200, &response)
+    }))?;
+
+    // Set the path loss model parameters directly, persisted across reboots
+    let limiter_for_rssi_config_post = rate_limiter.clone();
+    server.fn_handler("/config/rssi", Method::Post, logged(move |mut req| {
+        if rate_limited(&limiter_for_rssi_config_post, &mut req) {
+            return too_many_requests(req);
+        }
+        let body = match read_json_body(&mut req, MAX_CONFIG_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => return ServerError::from_body_error(e).respond(req),
+        };
+        match calibration().updated_from_json(&body) {
+            Ok(state) => {
+                set_calibration(nvs_for_rssi_config.clone(), state);
+                info!("RSSI config updated: {:?}", state);
+                respond_json(req, 200, &state.to_json_with_defaults())
+            }
+            Err(reason) => {
+                warn!("Rejected RSSI config `{}`: {}", body, reason);
+                ServerError::BadRequest(reason.to_string()).respond(req)
+            }
+        }
+    }))?;
+
+    // Server-Sent Events stream of RSSI readings
+    // NOTE: the HTTP server runs all handlers on a single task, so this
+    // handler blocks every other request while a client is subscribed.
+    // Only one SSE client is supported at a time.
+    let limiter_for_rssi_events = rate_limiter.clone();
+    server.fn_handler("/events/rssi", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_rssi_events, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("RSSI event stream opened");
+        let mut resp = start_response(req, 200, Some("OK"), &[
+            ("Content-Type", "text/event-stream"),
+            ("Cache-Control", "no-cache"),
+        ])?;
+
+        let mut next_event_ms = now_ms();
+        loop {
+            let event = match latest_station_rssi() {
+                Some((rssi, _)) => format!(
+                    "data: {{\"rssi\":{},\"distance\":{:.2},\"signal_pct\":{}}}\n\n",
+                    rssi,
+                    calculate_distance_from_rssi(rssi),
+                    to_percentage(rssi)
+                ),
+                None => "data: {\"rssi\":null,\"distance\":null,\"signal_pct\":0}\n\n".to_string(),
+            };
+            // A write error means the client went away
+            if let Err(e) = resp.write_all(event.as_bytes()).and_then(|_| resp.flush()) {
+                info!("RSSI event stream closed: {:?}", e);
+                return Ok(());
+            }
+
+            // The stream stays open as long as the client wants it
+            feed_watchdog();
+            next_event_ms += RSSI_POLL_INTERVAL_MS;
+            FreeRtos::delay_ms(next_event_ms.saturating_sub(now_ms()) as u32);
+        }
+    }))?;
+
+    #[cfg(feature = "game")]
+    let app_state_for_config_get = app_state.clone();
+    #[cfg(feature = "game")]
+    let limiter_for_config_get = rate_limiter.clone();
+    #[cfg(feature = "game")]
+    server.fn_handler("/config/game", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_config_get, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("Game config request received");
+        let response = app_state_for_config_get.game_config().to_json();
+
+        respond_json(req, 200, &response)
+    }))?;
+
+    #[cfg(feature = "game")]
+    let app_state_for_config_post = app_state.clone();
+    #[cfg(feature = "game")]
+    let limiter_for_config_post = rate_limiter.clone();
+    #[cfg(feature = "game")]
+    server.fn_handler("/config/game", Method::Post, logged(move |mut req| {
+        if rate_limited(&limiter_for_config_post, &mut req) {
+            return too_many_requests(req);
+        }
+        let body = match read_json_body(&mut req, MAX_CONFIG_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => return ServerError::from_body_error(e).respond(req),
+        };
+
+        // HTML forms post `min=1&max=500`, everything else is taken as JSON
+        let is_form = get_request_header(&req, "Content-Type")
+            .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+        let mut config = app_state_for_config_post.game_config();
+        let updated = if is_form {
+            config.updated_from_form(&parse_query_string(&body))
+        } else {
+            config.updated_from_json(&body)
+        };
+        match updated {
+            Ok(new_config) => {
+                *config = new_config;
+                info!("Game range updated to {}-{}", new_config.min, new_config.max);
+                let response = new_config.to_json();
+                drop(config);
+                respond_json(req, 200, &response)?;
+            }
+            Err(reason) => {
+                drop(config);
+                warn!("Rejected game config `{}`: {}", body, reason);
+                return ServerError::BadRequest(reason.to_string()).respond(req);
+            }
+        }
+        Ok::<(), EspError>(())
+    }))?;
+
+    // Leaderboard endpoint returning the best scores as JSON
+    #[cfg(feature = "game")]
+    let leaderboard_for_http = leaderboard.clone();
+    #[cfg(feature = "game")]
+    let limiter_for_leaderboard = rate_limiter.clone();
+    #[cfg(feature = "game")]
+    server.fn_handler("/leaderboard", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_leaderboard, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("Leaderboard request received");
+        let response = leaderboard_for_http.lock().unwrap().to_json();
+
+        respond_json(req, 200, &response)
+    }))?;
+
+    // Called with the new count whenever a WebSocket session opens or closes
+    #[cfg(feature = "oled")]
+    let oled_for_status = oled_display.clone();
+    #[cfg(feature = "oled")]
+    let ap_ip = wifi_status.ap_ip.to_string();
+    let show_ws_session_count = Arc::new(move |count: u32| {
+        led::set_state(if count > 0 { LedState::ClientConnected } else { LedState::ApReady });
+        #[cfg(feature = "oled")]
+        if let Some(oled) = &oled_for_status {
+            if let Err(e) = oled.update_status_bar(&ap_ip, count as usize) {
+                warn!("Failed to update OLED status bar: {:?}", e);
+            }
+        }
+    });
+
+    // Resource usage endpoint for monitoring
+    let app_state_for_metrics = app_state.clone();
+    let limiter_for_metrics = rate_limiter.clone();
+    server.fn_handler("/metrics", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_metrics, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("Metrics request received");
+        let (free_heap, min_free_heap, stack_hwm) = unsafe {
+            (
+                esp_idf_svc::sys::esp_get_free_heap_size(),
+                esp_idf_svc::sys::esp_get_minimum_free_heap_size(),
+                // The main task is gone once main() returns, so report the
+                // HTTP server task that runs all handlers instead
+                esp_idf_svc::sys::uxTaskGetStackHighWaterMark(core::ptr::null_mut()),
+            )
+        };
+        #[cfg(feature = "game")]
+        let game_sessions = app_state_for_metrics.sessions().len();
+        #[cfg(not(feature = "game"))]
+        let game_sessions = 0;
+        let uptime_s = now_ms() / 1000;
+        let sta_ip = match wifi_status.sta_ip {
+            Some(ip) => format!("\"{}\"", ip),
+            None => "null".to_string(),
+        };
+        let response = format!(
+            r#"{{"free_heap":{},"min_free_heap":{},"stack_hwm":{},"uptime_s":{},"uptime":"{}","open_ws_sessions":{},"game_sessions":{},"max_game_sessions":{},"wifi_mode":"{}","sta_ip":{}}}"#,
+            free_heap,
+            min_free_heap,
+            stack_hwm,
+            uptime_s,
+            format_duration(uptime_s),
+            app_state_for_metrics.open_ws_sessions(),
+            game_sessions,
+            MAX_WS_SESSIONS,
+            wifi_status.mode.name(),
+            sta_ip
+        );
+        // Splice the task list in front of the closing brace
+        #[cfg(feature = "task-stats")]
+        let response = format!(
+            r#"{},"tasks":{}}}"#,
+            &response[..response.len() - 1],
+            task_stats::to_json()
+        );
+
+        respond_json(req, 200, &response)
+    }))?;
+
+    // Over-the-air firmware update, admin only
+    let limiter_for_ota = rate_limiter.clone();
+    server.fn_handler("/ota", Method::Post, logged(require_auth(move |mut req| {
+        if rate_limited(&limiter_for_ota, &mut req) {
+            return too_many_requests(req);
+        }
+
+        info!("Starting OTA update ({:?} bytes announced)", req.content_len());
+        let mut ota = EspOta::new().map_err(|e| ServerError::from(e).into_esp_error())?;
+        let mut update = ota
+            .initiate_update()
+            .map_err(|e| ServerError::from(e).into_esp_error())?;
+
+        let mut buf = vec![0u8; OTA_CHUNK_LEN];
+        let mut total = 0;
+        loop {
+            let read = match req.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    // Dropping the update aborts it, the running image stays untouched
+                    update.abort()?;
+                    return Err(ServerError::from(e).into_esp_error());
+                }
+            };
+            if let Err(e) = update.write(&buf[..read]) {
+                update.abort()?;
+                return ServerError::from(e).respond(req);
+            }
+            total += read;
+            // Uploads take a while over Wi-Fi, each chunk written shows progress
+            feed_watchdog();
+        }
+
+        if total == 0 {
+            update.abort()?;
+            return ServerError::BadRequest("empty firmware image".to_string()).respond(req);
+        }
+        if let Err(e) = update.complete() {
+            error!("Firmware image rejected after {} bytes: {:?}", total, e);
+            return ServerError::from(e).respond(req);
+        }
+        info!("OTA update written ({} bytes), rebooting...", total);
+
+        respond(req, 200, "text/plain", &[], b"Update complete, rebooting")?;
+
+        // Give the response a moment to reach the client
+        FreeRtos::delay_ms(500);
+        restart();
+    })))?;
+
+    // Remote reboot, authenticated with ADMIN_TOKEN in the body
+    let limiter_for_reboot = rate_limiter.clone();
+    server.fn_handler("/reboot", Method::Post, logged(move |mut req| {
+        if rate_limited(&limiter_for_reboot, &mut req) {
+            return too_many_requests(req);
+        }
+        let requester = client_ipv4(&mut req).map(Ipv4Addr::from);
+        let body = match read_json_body(&mut req, MAX_CONFIG_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => return ServerError::from_body_error(e).respond(req),
+        };
+        if !extract_json_string(&body, "token").is_some_and(|token| check_admin_token(&token)) {
+            warn!("Rejecting reboot request from {:?}: bad token", requester);
+            return ServerError::Unauthorized.respond(req);
+        }
+        let delay_s = match extract_json_u32(&body, "delay_s") {
+            Some(Ok(delay_s)) if delay_s <= MAX_REBOOT_DELAY_S => delay_s,
+            // Only default when the key is missing, not when its value is invalid
+            None => REBOOT_DEFAULT_DELAY_S,
+            _ => {
+                let msg = format!("delay_s must be between 0 and {}", MAX_REBOOT_DELAY_S);
+                return ServerError::BadRequest(msg).respond(req);
+            }
+        };
+
+        warn!("Reboot in {} s requested by {:?}", delay_s, requester);
+        if delay_s > 0 {
+            let spawned = std::thread::Builder::new()
+                .name("reboot".into())
+                .stack_size(REBOOT_STACK_SIZE)
+                .spawn(move || {
+                    FreeRtos::delay_ms(delay_s * 1000);
+                    info!("Rebooting as requested");
+                    restart();
+                });
+            if let Err(e) = spawned {
+                error!("Failed to schedule reboot: {:?}", e);
+                return ServerError::Io(EspError::from_infallible::<ESP_ERR_NO_MEM>()).respond(req);
+            }
+        }
+
+        respond_json(req, 202, &format!(r#"{{"rebooting_in_s":{}}}"#, delay_s))?;
+
+        if delay_s == 0 {
+            // Give the response a moment to reach the client
+            FreeRtos::delay_ms(500);
+            restart();
+        }
+        Ok::<(), EspError>(())
+    }))?;
+
+    // Factory reset, authenticated with ADMIN_TOKEN in the body like /reboot
+    let limiter_for_reset = rate_limiter.clone();
+    server.fn_handler("/reset", Method::Post, logged(move |mut req| {
+        if rate_limited(&limiter_for_reset, &mut req) {
+            return too_many_requests(req);
+        }
+        let requester = client_ipv4(&mut req).map(Ipv4Addr::from);
+        let body = match read_json_body(&mut req, MAX_CONFIG_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => return ServerError::from_body_error(e).respond(req),
+        };
+        if !extract_json_string(&body, "token").is_some_and(|token| check_admin_token(&token)) {
+            warn!("Rejecting reset request from {:?}: bad token", requester);
+            return ServerError::Unauthorized.respond(req);
+        }
+        let scope = match ResetScope::from_name(extract_json_string(&body, "scope").as_deref()) {
+            Ok(scope) => scope,
+            Err(reason) => return ServerError::BadRequest(reason.to_string()).respond(req),
+        };
+
+        warn!("Factory reset ({}) requested by {:?}", scope.as_str(), requester);
+        let cleared = match reset::erase(nvs_for_reset.clone(), scope) {
+            Ok(cleared) => cleared,
+            Err(e) => {
+                error!("Factory reset failed: {:?}", e);
+                return ServerError::Io(e).respond(req);
+            }
+        };
+        let spawned = std::thread::Builder::new()
+            .name("reboot".into())
+            .stack_size(REBOOT_STACK_SIZE)
+            .spawn(|| {
+                FreeRtos::delay_ms(RESET_REBOOT_DELAY_MS);
+                info!("Rebooting to factory defaults");
+                restart();
+            });
+        if let Err(e) = spawned {
+            error!("Failed to schedule reboot: {:?}", e);
+            return ServerError::Io(EspError::from_infallible::<ESP_ERR_NO_MEM>()).respond(req);
+        }
+
+        respond_json(req, 202, &reset::to_json(scope, &cleared))
+    }))?;
+
+    // CORS preflight for every endpoint
+    server.fn_handler("/*", Method::Options, logged(|req| {
+        debug!("CORS preflight request for {}", req.uri());
+        start_response(req, 204, Some("No Content"), &[])?;
+        Ok::<(), EspError>(())
+    }))?;
 
     // WebSocket endpoint for displaying messages on OLED
+    #[cfg(feature = "oled")]
     if let Some(oled) = oled_display.clone() {
         let oled_for_display = oled.clone();
+        let app_state_for_display = app_state.clone();
+        let show_ws_session_count_for_display = show_ws_session_count.clone();
         server.ws_handler("/ws/display", move |ws| {
+            let _watchdog = WatchdogCheckpoint::start();
             if ws.is_new() {
+                let open = app_state_for_display.ws_session_opened(ws.session(), "/ws/display");
+                show_ws_session_count_for_display(open);
+                set_tcp_keepalive(ws.session());
                 info!("New display WebSocket session {}", ws.session());
                 let _ = ws.send(FrameType::Text(false), b"Connected! Send a message to display on OLED.");
                 return Ok(());
             } else if ws.is_closed() {
+                let Some(open) = app_state_for_display.ws_session_closed(ws.session(), "/ws/display") else {
+                    return Ok(());
+                };
+                show_ws_session_count_for_display(open);
                 info!("Closed display WebSocket session {}", ws.session());
                 return Ok(());
             }
@@ -170,7 +1021,7 @@ fn main() -> anyhow::Result<()> {
                 match user_string.to_str() {
                     Ok(msg) => msg,
                     Err(e) => {
-                        warn!("Failed to decode UTF-8 string: {:?}", e);
+                        warn!("{}: {:?}", ServerError::Encoding, e);
                         ws.send(FrameType::Text(false), "[UTF-8 Error]".as_bytes())?;
                         return Ok(());
                     }
@@ -180,7 +1031,7 @@ fn main() -> anyhow::Result<()> {
                 match std::str::from_utf8(&buf[..len]) {
                     Ok(msg) => msg,
                     Err(e) => {
-                        warn!("Failed to decode UTF-8 string: {:?}", e);
+                        warn!("{}: {:?}", ServerError::Encoding, e);
                         ws.send(FrameType::Text(false), "[UTF-8 Error]".as_bytes())?;
                         return Ok(());
                     }
@@ -189,8 +1040,8 @@ fn main() -> anyhow::Result<()> {
 
             info!("Received message to display: {}", message);
 
-            // Display on OLED
-            if let Err(e) = oled_for_display.display_message(message) {
+            // Display on OLED, scrolling messages that do not fit on one screen
+            if let Err(e) = oled_for_display.display_message_scroll(message, OLED_SCROLL_DELAY_MS) {
                 error!("Failed to display message on OLED: {:?}", e);
                 ws.send(FrameType::Text(false), format!("Error displaying: {:?}", e).as_bytes())?;
             } else {
@@ -202,49 +1053,833 @@ fn main() -> anyhow::Result<()> {
         info!("WebSocket display endpoint registered at /ws/display");
     }
 
-    let guessing_games = Mutex::new(BTreeMap::<i32, GuessingGame>::new());
+    // WebSocket echo endpoint for testing custom clients
+    // Bytes echoed per session are logged when the session closes
+    let app_state_for_echo = app_state.clone();
+    let show_ws_session_count_for_echo = show_ws_session_count.clone();
+    server.ws_handler("/ws/echo", move |ws| {
+        let _watchdog = WatchdogCheckpoint::start();
+        let session_id = ws.session();
+        if ws.is_new() {
+            let open = app_state_for_echo.ws_session_opened(session_id, "/ws/echo");
+            show_ws_session_count_for_echo(open);
+            set_tcp_keepalive(session_id);
+            app_state_for_echo.echoed_bytes().insert(session_id, 0);
+            info!("New echo WebSocket session {}", session_id);
+            ws.send(FrameType::Text(false), b"ready")?;
+            return Ok(());
+        } else if ws.is_closed() {
+            let Some(open) = app_state_for_echo.ws_session_closed(session_id, "/ws/echo") else {
+                return Ok(());
+            };
+            show_ws_session_count_for_echo(open);
+            let total = app_state_for_echo.echoed_bytes().remove(&session_id).unwrap_or(0);
+            info!("Closed echo WebSocket session {} ({} bytes echoed)", session_id, total);
+            return Ok(());
+        }
+
+        // Same two-step recv as /ws/guess: size first, then the payload
+        let (frame_type, len) = match ws.recv(&mut []) {
+            Ok(frame) => frame,
+            Err(e) => {
+                error!("Error receiving echo frame from session {}: {:?}", session_id, e);
+                return Err(e);
+            }
+        };
+
+        match frame_type {
+            FrameType::Ping => {
+                ws.send(FrameType::Pong, &[])?;
+                return Ok(());
+            }
+            FrameType::Pong | FrameType::Close | FrameType::SocketClose => return Ok(()),
+            _ => {}
+        }
+
+        if len > MAX_LEN {
+            warn!("Echo payload too big: {} bytes (max: {})", len, MAX_LEN);
+            ws.send(FrameType::Text(false), "Payload too big".as_bytes())?;
+            ws.send(FrameType::Close, &[])?;
+            return Err(ServerError::PayloadTooLarge.into_esp_error());
+        }
+
+        let mut buf = [0; MAX_LEN];
+        ws.recv(buf.as_mut())?;
+        ws.send(frame_type, &buf[..len])?;
+        debug!("Echoed {} bytes ({:?}) to session {}", len, frame_type, session_id);
+        if let Some(total) = app_state_for_echo.echoed_bytes().get_mut(&session_id) {
+            *total += len;
+        }
+
+        Ok::<(), EspError>(())
+    })?;
 
-    server.ws_handler("/ws/guess", move |ws| {
+    let app_state_for_quiz = app_state.clone();
+    let show_ws_session_count_for_quiz = show_ws_session_count.clone();
+    server.ws_handler("/ws/quiz", move |ws| {
+        let _watchdog = WatchdogCheckpoint::start();
         let session_id = ws.session();
-        let mut sessions = guessing_games.lock().unwrap();
-        
         if ws.is_new() {
-            let secret = (rand() % 100) + 1;
-            sessions.insert(session_id, GuessingGame::new(secret));
+            let open = app_state_for_quiz.ws_session_opened(session_id, "/ws/quiz");
+            show_ws_session_count_for_quiz(open);
+            set_tcp_keepalive(session_id);
+            let mut quiz = MathQuiz::new();
+            let question = quiz.next_question().to_string();
+            app_state_for_quiz.math_quizzes().insert(session_id, quiz);
+            info!("New quiz WebSocket session {}", session_id);
+            ws.send(FrameType::Text(false), question.as_bytes())?;
+            return Ok(());
+        } else if ws.is_closed() {
+            let Some(open) = app_state_for_quiz.ws_session_closed(session_id, "/ws/quiz") else {
+                return Ok(());
+            };
+            show_ws_session_count_for_quiz(open);
+            app_state_for_quiz.math_quizzes().remove(&session_id);
+            info!("Closed quiz WebSocket session {}", session_id);
+            return Ok(());
+        }
+
+        // Same two-step recv as /ws/guess: size first, then the payload
+        let (frame_type, len) = ws.recv(&mut [])?;
+        match frame_type {
+            FrameType::Ping => {
+                ws.send(FrameType::Pong, &[])?;
+                return Ok(());
+            }
+            FrameType::Pong | FrameType::Close | FrameType::SocketClose => return Ok(()),
+            _ => {}
+        }
+
+        if len > MAX_LEN {
+            warn!("Quiz answer too big: {} bytes (max: {})", len, MAX_LEN);
+            ws.send(FrameType::Text(false), "Request too big".as_bytes())?;
+            ws.send(FrameType::Close, &[])?;
+            return Err(ServerError::PayloadTooLarge.into_esp_error());
+        }
+
+        let mut buf = [0; MAX_LEN];
+        ws.recv(buf.as_mut())?;
+        let Some(reply) = std::str::from_utf8(&buf[..len]).ok().and_then(MathQuiz::parse_answer)
+        else {
+            ws.send(FrameType::Text(false), b"Please answer with a number")?;
+            return Ok(());
+        };
+
+        // Feedback, then either the next question or the final score
+        let (feedback, follow_up, finished) = {
+            let mut quizzes = app_state_for_quiz.math_quizzes();
+            let Some(quiz) = quizzes.get_mut(&session_id) else {
+                warn!("Quiz session {}: {}", session_id, ServerError::GameNotFound);
+                drop(quizzes);
+                ws.send(FrameType::Close, &[])?;
+                return Ok(());
+            };
+            let feedback = quiz.answer(reply);
+            if quiz.is_finished() {
+                let summary = quiz.final_score();
+                info!("Quiz session {} finished: {}", session_id, summary);
+                quizzes.remove(&session_id);
+                (feedback, summary, true)
+            } else {
+                (feedback, quiz.next_question().to_string(), false)
+            }
+        };
+
+        ws.send(FrameType::Text(false), feedback.as_bytes())?;
+        ws.send(FrameType::Text(false), follow_up.as_bytes())?;
+        if finished {
+            ws.send(FrameType::Close, &[])?;
+        }
+
+        Ok::<(), EspError>(())
+    })?;
+
+    let app_state_for_words = app_state.clone();
+    let show_ws_session_count_for_words = show_ws_session_count.clone();
+    server.ws_handler("/ws/wordguess", move |ws| {
+        let _watchdog = WatchdogCheckpoint::start();
+        let session_id = ws.session();
+        if ws.is_new() {
+            let open = app_state_for_words.ws_session_opened(session_id, "/ws/wordguess");
+            show_ws_session_count_for_words(open);
+            set_tcp_keepalive(session_id);
+            app_state_for_words.word_games().insert(session_id, WordGuess::new());
+            info!("New word game WebSocket session {}", session_id);
+            let welcome = format!("Guess the {} letter word", WORD_LEN);
+            ws.send(FrameType::Text(false), welcome.as_bytes())?;
+            return Ok(());
+        } else if ws.is_closed() {
+            let Some(open) = app_state_for_words.ws_session_closed(session_id, "/ws/wordguess") else {
+                return Ok(());
+            };
+            show_ws_session_count_for_words(open);
+            app_state_for_words.word_games().remove(&session_id);
+            info!("Closed word game WebSocket session {}", session_id);
+            return Ok(());
+        }
+
+        // Same two-step recv as /ws/guess: size first, then the payload
+        let (frame_type, len) = ws.recv(&mut [])?;
+        match frame_type {
+            FrameType::Ping => {
+                ws.send(FrameType::Pong, &[])?;
+                return Ok(());
+            }
+            FrameType::Pong | FrameType::Close | FrameType::SocketClose => return Ok(()),
+            _ => {}
+        }
+
+        if len > MAX_LEN {
+            warn!("Word guess too big: {} bytes (max: {})", len, MAX_LEN);
+            ws.send(FrameType::Text(false), "Request too big".as_bytes())?;
+            ws.send(FrameType::Close, &[])?;
+            return Err(ServerError::PayloadTooLarge.into_esp_error());
+        }
+
+        let mut buf = [0; MAX_LEN];
+        ws.recv(buf.as_mut())?;
+        let guess = std::str::from_utf8(&buf[..len]).unwrap_or_default();
+
+        // Hint, plus the win message once the word is found
+        let (hint, win) = {
+            let mut games = app_state_for_words.word_games();
+            let Some(game) = games.get_mut(&session_id) else {
+                warn!("Word game session {}: {}", session_id, ServerError::GameNotFound);
+                drop(games);
+                ws.send(FrameType::Close, &[])?;
+                return Ok(());
+            };
+            let hint = game.check(guess);
+            let win = game.is_solved().then(|| game.win_message());
+            if let Some(win) = &win {
+                info!("Word game session {} finished: {}", session_id, win);
+                games.remove(&session_id);
+            }
+            (hint, win)
+        };
+
+        let Some(hint) = hint else {
+            let reply = format!("Please send a {} letter word", WORD_LEN);
+            ws.send(FrameType::Text(false), reply.as_bytes())?;
+            return Ok(());
+        };
+        ws.send(FrameType::Text(false), hint.as_bytes())?;
+        if let Some(win) = win {
+            ws.send(FrameType::Text(false), win.as_bytes())?;
+            ws.send(FrameType::Close, &[])?;
+        }
+
+        Ok::<(), EspError>(())
+    })?;
+
+    // /ws/proximity subscribers, pushed to by the RSSI polling task
+    let proximity_subscribers = Arc::new(Mutex::new(BTreeMap::new()));
+    rssi::spawn_poller(app_state.clone(), proximity_subscribers.clone())?;
+    let app_state_for_proximity = app_state.clone();
+    let show_ws_session_count_for_proximity = show_ws_session_count.clone();
+    server.ws_handler("/ws/proximity", move |ws| {
+        let _watchdog = WatchdogCheckpoint::start();
+        let session_id = ws.session();
+        if ws.is_new() {
+            let open = app_state_for_proximity.ws_session_opened(session_id, "/ws/proximity");
+            show_ws_session_count_for_proximity(open);
+            set_tcp_keepalive(session_id);
+            let sender = ws.create_detached_sender()?;
+            proximity_subscribers.lock().unwrap().insert(session_id, sender);
+            info!("New proximity WebSocket session {}", session_id);
+            // Zone changes can be rare, so start the client off with the current one
+            if let Some(report) = rssi::proximity_report() {
+                ws.send(FrameType::Text(false), report.to_json().as_bytes())?;
+            }
+            return Ok(());
+        } else if ws.is_closed() {
+            let Some(open) = app_state_for_proximity.ws_session_closed(session_id, "/ws/proximity") else {
+                return Ok(());
+            };
+            show_ws_session_count_for_proximity(open);
+            proximity_subscribers.lock().unwrap().remove(&session_id);
+            info!("Closed proximity WebSocket session {}", session_id);
+            return Ok(());
+        }
+
+        // Push only: client frames are read off the socket and ignored
+        let (frame_type, len) = ws.recv(&mut [])?;
+        match frame_type {
+            FrameType::Ping => {
+                ws.send(FrameType::Pong, &[])?;
+                return Ok(());
+            }
+            FrameType::Pong | FrameType::Close | FrameType::SocketClose => return Ok(()),
+            _ => {}
+        }
+
+        if len > MAX_LEN {
+            warn!("Proximity frame too big: {} bytes (max: {})", len, MAX_LEN);
+            ws.send(FrameType::Close, &[])?;
+            return Err(ServerError::PayloadTooLarge.into_esp_error());
+        }
+        let mut buf = [0; MAX_LEN];
+        ws.recv(buf.as_mut())?;
+
+        Ok::<(), EspError>(())
+    })?;
+
+    #[cfg(feature = "game")]
+    let heartbeat = Arc::new(Heartbeat::default());
+    #[cfg(feature = "game")]
+    heartbeat::spawn(heartbeat.clone(), app_state.clone(), session_store.clone())?;
+
+    // BOOT button: hints for running games, otherwise stats on the OLED
+    let button_context = ButtonContext {
+        #[cfg(any(feature = "game", feature = "oled"))]
+        state: app_state.clone(),
+        #[cfg(feature = "game")]
+        heartbeat: heartbeat.clone(),
+        #[cfg(feature = "oled")]
+        oled: oled_display.clone(),
+        nvs: nvs_for_button,
+    };
+    if let Err(e) = button::spawn(button_pin, button_context) {
+        warn!("Failed to start button handler: {:?}", e);
+        warn!("Continuing without button input...");
+    }
+
+    #[cfg(feature = "debug-console")]
+    if let Err(e) = debug_console::spawn(app_state.clone()) {
+        warn!("Failed to start debug console: {:?}", e);
+    }
+
+    // Admin listing of guessing game sessions
+    #[cfg(feature = "game")]
+    let app_state_for_admin = app_state.clone();
+    #[cfg(feature = "game")]
+    let limiter_for_sessions = rate_limiter.clone();
+    #[cfg(feature = "game")]
+    server.fn_handler("/admin/sessions", Method::Get, logged(require_auth(move |mut req| {
+        if rate_limited(&limiter_for_sessions, &mut req) {
+            return too_many_requests(req);
+        }
+        let now = now_ms();
+        let sessions: Vec<String> = app_state_for_admin
+            .sessions()
+            .iter()
+            .map(|(&session_id, session)| session.to_summary_json(session_id, now))
+            .collect();
+        info!("Listing {} sessions", sessions.len());
+        respond_json(req, 200, &format!("[{}]", sessions.join(",")))
+    })))?;
+
+    // Recent HTTP requests, newest first
+    let limiter_for_request_log = rate_limiter.clone();
+    server.fn_handler("/admin/log", Method::Get, logged(require_auth(move |mut req| {
+        if rate_limited(&limiter_for_request_log, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("Request log requested");
+        let response = request_log::to_json();
+        respond_json(req, 200, &response)
+    })))?;
+
+    // Stack high-water mark of every task, to find the one that overflowed
+    let limiter_for_stack = rate_limiter.clone();
+    server.fn_handler("/admin/debug/stack", Method::Get, logged(require_auth(move |mut req| {
+        if rate_limited(&limiter_for_stack, &mut req) {
+            return too_many_requests(req);
+        }
+        info!("Stack report requested");
+        let Some(tasks) = task_stats::stack_report_json() else {
+            return ServerError::Io(EspError::from_infallible::<ESP_ERR_NO_MEM>()).respond(req);
+        };
+        respond_json(req, 200, &format!(r#"{{"tasks":{}}}"#, tasks))
+    })))?;
+
+    // Admin endpoint pushing an announcement to every guessing game session
+    #[cfg(feature = "game")]
+    let heartbeat_for_broadcast = heartbeat.clone();
+    #[cfg(feature = "game")]
+    let limiter_for_broadcast = rate_limiter.clone();
+    #[cfg(feature = "game")]
+    server.fn_handler("/admin/broadcast", Method::Post, logged(require_auth(move |mut req| {
+        if rate_limited(&limiter_for_broadcast, &mut req) {
+            return too_many_requests(req);
+        }
+        let body = match read_json_body(&mut req, MAX_BROADCAST_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => return ServerError::from_body_error(e).respond(req),
+        };
+        let Some(message) = extract_json_string(&body, "message") else {
+            return ServerError::BadRequest("expected {\"message\":\"...\"}".to_string()).respond(req);
+        };
+
+        let senders = heartbeat_for_broadcast.senders();
+        let session_count = senders.len();
+        info!("Broadcasting `{}` to {} sessions", message, session_count);
+        if let Err(e) = spawn_broadcast(senders, message) {
+            error!("Failed to spawn broadcast thread: {:?}", e);
+            return ServerError::Io(EspError::from_infallible::<ESP_ERR_NO_MEM>()).respond(req);
+        }
+
+        respond_json(req, 202, &format!(r#"{{"sessions":{}}}"#, session_count))
+    })))?;
+
+    // Operator announcement to every guessing game session, optionally ending them all
+    #[cfg(feature = "game")]
+    let heartbeat_for_announce = heartbeat.clone();
+    #[cfg(feature = "game")]
+    let app_state_for_announce = app_state.clone();
+    #[cfg(feature = "game")]
+    let session_store_for_announce = session_store.clone();
+    #[cfg(feature = "game")]
+    let limiter_for_announce = rate_limiter.clone();
+    #[cfg(feature = "game")]
+    server.fn_handler("/game/announce", Method::Post, logged(require_auth(move |mut req| {
+        if rate_limited(&limiter_for_announce, &mut req) {
+            return too_many_requests(req);
+        }
+        let body = match read_json_body(&mut req, MAX_BROADCAST_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => return ServerError::from_body_error(e).respond(req),
+        };
+        let Some(message) = extract_json_string(&body, "message") else {
+            let msg = "expected {\"message\":\"...\",\"close_after\":false}";
+            return ServerError::BadRequest(msg.to_string()).respond(req);
+        };
+        let close_after = match extract_json_bool(&body, "close_after") {
+            None => false,
+            Some(Ok(close_after)) => close_after,
+            Some(Err(())) => {
+                let msg = "close_after must be true or false";
+                return ServerError::BadRequest(msg.to_string()).respond(req);
+            }
+        };
+
+        let senders = heartbeat_for_announce.senders();
+        let session_count = senders.len();
+        info!("Announcing `{}` to {} sessions (close: {})", message, session_count, close_after);
+        let spawned = if close_after {
+            spawn_broadcast_and_close(senders, message)
+        } else {
+            spawn_broadcast(senders, message)
+        };
+        if let Err(e) = spawned {
+            error!("Failed to spawn announcement thread: {:?}", e);
+            return ServerError::Io(EspError::from_infallible::<ESP_ERR_NO_MEM>()).respond(req);
+        }
+        if close_after {
+            let mut sessions = app_state_for_announce.sessions();
+            if !sessions.is_empty() {
+                sessions.clear();
+                session_store_for_announce.lock().unwrap().save(&sessions);
+            }
+            info!("Cleared all guessing game sessions");
+        }
+
+        let response = format!(r#"{{"sessions":{},"closed":{}}}"#, session_count, close_after);
+        respond_json(req, 202, &response)
+    })))?;
+
+    // Operator-chosen secret for the next session or all open ones, for demos
+    #[cfg(feature = "game")]
+    let app_state_for_secret = app_state.clone();
+    #[cfg(feature = "game")]
+    let limiter_for_secret = rate_limiter.clone();
+    #[cfg(feature = "game")]
+    server.fn_handler("/game/set-secret", Method::Post, logged(require_auth(move |mut req| {
+        if rate_limited(&limiter_for_secret, &mut req) {
+            return too_many_requests(req);
+        }
+        let body = match read_json_body(&mut req, MAX_CONFIG_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => return ServerError::from_body_error(e).respond(req),
+        };
+        let scope = match SecretScope::from_name(extract_json_string(&body, "scope").as_deref()) {
+            Ok(scope) => scope,
+            Err(reason) => return ServerError::BadRequest(reason.to_string()).respond(req),
+        };
+        let config = *app_state_for_secret.game_config();
+        let secret = match extract_json_u32(&body, "secret") {
+            Some(Ok(secret)) if config.contains(secret) => secret,
+            _ => {
+                let msg = format!("secret must be between {} and {}", config.min, config.max);
+                return ServerError::BadRequest(msg).respond(req);
+            }
+        };
+
+        let sessions_reset = match scope {
+            SecretScope::Next => {
+                *app_state_for_secret.secret_override() = Some(secret);
+                0
+            }
+            SecretScope::All => {
+                let mut sessions = app_state_for_secret.sessions();
+                for session in sessions.values_mut() {
+                    session.game.reset(secret);
+                }
+                sessions.len()
+            }
+        };
+        info!("Operator set the secret for {} ({} sessions reset)", scope.as_str(), sessions_reset);
+
+        let response = format!(
+            r#"{{"secret":{},"scope":"{}","sessions_reset":{}}}"#,
+            secret,
+            scope.as_str(),
+            sessions_reset
+        );
+        respond_json(req, 200, &response)
+    })))?;
+
+    // Range the secret of a session is still in, e.g. /game/hint?session_id=3
+    #[cfg(feature = "game")]
+    let app_state_for_hint = app_state.clone();
+    #[cfg(feature = "game")]
+    let limiter_for_hint = rate_limiter.clone();
+    #[cfg(feature = "game")]
+    server.fn_handler("/game/hint", Method::Get, logged(move |mut req| {
+        if rate_limited(&limiter_for_hint, &mut req) {
+            return too_many_requests(req);
+        }
+        let Some(session_id) = query_params(req.uri())
+            .get("session_id")
+            .and_then(|value| value.parse::<i32>().ok())
+        else {
+            let msg = "expected ?session_id=<number>";
+            return ServerError::BadRequest(msg.to_string()).respond(req);
+        };
+        let state = app_state_for_hint.lock();
+        let config = state.game_config;
+        let bounds = state
+            .sessions
+            .get(&session_id)
+            .map(|session| session.game.secret_bounds(config.min, config.max));
+        drop(state);
+        let Some((low, high)) = bounds else {
+            return ServerError::GameNotFound.respond(req);
+        };
+        info!("Hint for session {}: {}-{}", session_id, low, high);
+
+        respond_json(req, 200, &bounds_hint_json(low, high))
+    }))?;
+
+    // Admin endpoint closing a guessing game session, /ws/guess checks the kick list
+    #[cfg(feature = "game")]
+    let app_state_for_kick = app_state.clone();
+    #[cfg(feature = "game")]
+    let heartbeat_for_kick = heartbeat.clone();
+    #[cfg(feature = "game")]
+    let limiter_for_kick = rate_limiter.clone();
+    #[cfg(feature = "game")]
+    server.fn_handler("/admin/kick", Method::Post, logged(require_auth(move |mut req| {
+        if rate_limited(&limiter_for_kick, &mut req) {
+            return too_many_requests(req);
+        }
+        let body = match read_json_body(&mut req, MAX_CONFIG_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => return ServerError::from_body_error(e).respond(req),
+        };
+        let Some(Ok(session_id)) = extract_json_u32(&body, "session_id") else {
+            return ServerError::BadRequest("expected {\"session_id\":N}".to_string()).respond(req);
+        };
+        let session_id = session_id as i32;
+        {
+            let mut state = app_state_for_kick.lock();
+            if !state.sessions.contains_key(&session_id) {
+                drop(state);
+                warn!("Kick for unknown session {}", session_id);
+                return ServerError::GameNotFound.respond(req);
+            }
+            state.kick_list.insert(session_id);
+        }
+        // Close right away instead of waiting for the client's next frame
+        let sender = heartbeat_for_kick
+            .senders()
+            .into_iter()
+            .find(|(session, _)| *session == session_id);
+        if let Some((_, sender)) = sender {
+            let reason = "Disconnected by an administrator".to_string();
+            if let Err(e) = spawn_close(session_id, sender, reason) {
+                warn!("Failed to spawn close for session {}: {:?}", session_id, e);
+            }
+        }
+        info!("Kicked session {}", session_id);
+
+        respond_json(req, 200, &format!(r#"{{"kicked":{}}}"#, session_id))
+    })))?;
+
+    // Tournament mode: everyone connected to /ws/tournament races for one secret
+    #[cfg(feature = "tournament")]
+    {
+        use crate::config::MAX_TOURNAMENT_PLAYERS;
+        use crate::tournament::Tournament;
+        use esp_idf_svc::http::server::ws::EspHttpWsDetachedSender;
+
+        // Connected players, also used to push announcements
+        let tournament_players =
+            Arc::new(Mutex::new(BTreeMap::<i32, EspHttpWsDetachedSender>::new()));
+        // Round in progress, cleared once every player has finished
+        let tournament = Arc::new(Mutex::new(None::<Tournament>));
+
+        // Announce the winner to everyone who played in a finished round
+        let announce_if_complete = {
+            let tournament_players = tournament_players.clone();
+            let tournament = tournament.clone();
+            move || {
+                let finished = {
+                    let mut tournament = tournament.lock().unwrap();
+                    if tournament.as_ref().is_some_and(|t| t.is_complete()) {
+                        tournament.take()
+                    } else {
+                        None
+                    }
+                };
+                let Some(finished) = finished else {
+                    return;
+                };
+                let players = tournament_players.lock().unwrap();
+                let senders: Vec<_> = finished
+                    .player_ids()
+                    .into_iter()
+                    .filter_map(|id| players.get(&id).map(|sender| (id, sender.clone())))
+                    .collect();
+                drop(players);
+                info!("Tournament over, winner: {:?}", finished.winner());
+                if let Err(e) = spawn_broadcast(senders, finished.result_message()) {
+                    error!("Failed to announce tournament result: {:?}", e);
+                }
+            }
+        };
+
+        let tournament_players_for_start = tournament_players.clone();
+        let tournament_for_start = tournament.clone();
+        let app_state_for_tournament = app_state.clone();
+        let limiter_for_tournament = rate_limiter.clone();
+        server.fn_handler("/tournament/start", Method::Post, logged(move |mut req| {
+            if rate_limited(&limiter_for_tournament, &mut req) {
+                return too_many_requests(req);
+            }
+            let mut current = tournament_for_start.lock().unwrap();
+            if current.is_some() {
+                drop(current);
+                return ServerError::BadRequest("tournament already running".to_string())
+                    .respond(req);
+            }
+            let senders: Vec<_> = tournament_players_for_start
+                .lock()
+                .unwrap()
+                .iter()
+                .take(MAX_TOURNAMENT_PLAYERS)
+                .map(|(&id, sender)| (id, sender.clone()))
+                .collect();
+            if senders.is_empty() {
+                drop(current);
+                return ServerError::BadRequest("no players connected".to_string()).respond(req);
+            }
+
+            let ids: Vec<i32> = senders.iter().map(|(id, _)| *id).collect();
+            let config = *app_state_for_tournament.game_config();
+            let started = Tournament::new(&ids, config.secret_from(rand()), &config);
+            let message = started.start_message();
+            *current = Some(started);
+            drop(current);
+
+            if let Err(e) = spawn_broadcast(senders, message) {
+                error!("Failed to spawn tournament start broadcast: {:?}", e);
+                *tournament_for_start.lock().unwrap() = None;
+                return ServerError::Io(EspError::from_infallible::<ESP_ERR_NO_MEM>()).respond(req);
+            }
+
+            respond_json(req, 202, &format!(r#"{{"players":{}}}"#, ids.len()))
+        }))?;
+
+        server.ws_handler("/ws/tournament", move |ws| {
+            let _watchdog = WatchdogCheckpoint::start();
+            let session_id = ws.session();
+
+            if ws.is_new() {
+                let mut players = tournament_players.lock().unwrap();
+                if players.len() >= MAX_TOURNAMENT_PLAYERS {
+                    warn!("Rejecting tournament session {}: lobby full", session_id);
+                    drop(players);
+                    ws.send(FrameType::Text(false), b"Tournament full, try again later")?;
+                    ws.send(FrameType::Close, &[])?;
+                    return Ok(());
+                }
+                players.insert(session_id, ws.create_detached_sender()?);
+                set_tcp_keepalive(session_id);
+                let waiting =
+                    format!(r#"{{"result":"tournament_waiting","players":{}}}"#, players.len());
+                drop(players);
+                info!("Tournament session {} joined", session_id);
+                ws.send(FrameType::Text(false), waiting.as_bytes())?;
+                return Ok(());
+            } else if ws.is_closed() {
+                tournament_players.lock().unwrap().remove(&session_id);
+                if let Some(current) = tournament.lock().unwrap().as_mut() {
+                    current.record_loss(session_id);
+                }
+                info!("Tournament session {} left", session_id);
+                announce_if_complete();
+                return Ok(());
+            }
+
+            // Same two-step recv as /ws/guess: size first, then the payload
+            let (frame_type, len) = ws.recv(&mut [])?;
+            match frame_type {
+                FrameType::Ping => {
+                    ws.send(FrameType::Pong, &[])?;
+                    return Ok(());
+                }
+                FrameType::Pong | FrameType::Close | FrameType::SocketClose => return Ok(()),
+                _ => {}
+            }
+            if len > MAX_LEN {
+                warn!("Request too big: {} bytes (max: {})", len, MAX_LEN);
+                ws.send(FrameType::Text(false), "Request too big".as_bytes())?;
+                ws.send(FrameType::Close, &[])?;
+                return Err(ServerError::PayloadTooLarge.into_esp_error());
+            }
+            let mut buf = [0; MAX_LEN];
+            ws.recv(buf.as_mut())?;
+
+            let Ok(user_string) = std::str::from_utf8(&buf[..len]) else {
+                warn!("{} from tournament session {}", ServerError::Encoding, session_id);
+                ws.send(FrameType::Text(false), "[UTF-8 Error]".as_bytes())?;
+                return Ok(());
+            };
+            let user_string = user_string.trim_end_matches('\0');
+            let json = WsMessage::is_json(user_string);
+
+            let reply = {
+                let mut current = tournament.lock().unwrap();
+                match current.as_mut() {
+                    None => WsMessage::Error("No tournament in progress".to_string()),
+                    Some(round) => match GuessingGame::parse_guess(user_string, round.config()) {
+                        None => WsMessage::Error(format!(
+                            "Please enter a number between {} and {}",
+                            round.config().min,
+                            round.config().max
+                        )),
+                        Some(user_guess) => round.play(session_id, user_guess).unwrap_or_else(|| {
+                            WsMessage::Error("You are not playing in this round".to_string())
+                        }),
+                    },
+                }
+            };
+            ws.send(FrameType::Text(false), reply.render(json).as_bytes())?;
+
+            if let WsMessage::Win { .. } | WsMessage::GameOver { .. } = reply {
+                announce_if_complete();
+            }
+            Ok::<(), EspError>(())
+        })?;
+    }
+
+    #[cfg(all(feature = "game", feature = "oled"))]
+    let oled_for_guess = oled_display.clone();
+    #[cfg(feature = "game")]
+    ws_handler_with_subprotocol(&mut server, c"/ws/guess", GUESS_JSON_SUBPROTOCOL, move |ws| {
+        let _watchdog = WatchdogCheckpoint::start();
+        let session_id = ws.session();
+        // Counted before taking the lock, the counters lock the state themselves
+        if ws.is_closed() {
+            let Some(open) = app_state.ws_session_closed(session_id, "/ws/guess") else {
+                return Ok(());
+            };
+            show_ws_session_count(open);
+        }
+        let mut state = app_state.lock();
+        if ws.is_closed() {
+            state.kick_list.remove(&session_id);
+        } else if !ws.is_new() && state.kick_list.contains(&session_id) {
+            drop(state);
+            info!("Closing kicked session {}", session_id);
+            ws.send(FrameType::Close, &[])?;
+            return Ok(());
+        }
+        let config = state.game_config;
+
+        if ws.is_new() {
+            if state.sessions.len() >= MAX_WS_SESSIONS {
+                warn!(
+                    "Rejecting WebSocket session {}: {} of {} sessions in use",
+                    session_id,
+                    state.sessions.len(),
+                    MAX_WS_SESSIONS
+                );
+                drop(state);
+                ws.send(FrameType::Text(false), b"Server full, try again later")?;
+                ws.send(FrameType::Close, &[])?;
+                return Ok(());
+            }
+            // Session IDs are the socket descriptors
+            set_tcp_keepalive(session_id);
+            let remote_ip = peer_ipv4(session_id).map(|ip| ip.octets());
+            let restored = remote_ip.and_then(|ip| restored_games.lock().unwrap().remove(&ip));
+            let game = match restored {
+                Some(game) => {
+                    info!("Resuming game restored from NVS for session {}", session_id);
+                    game
+                }
+                None => {
+                    // Hardware RNG, so sessions opened at the same time get independent secrets
+                    let secret = new_session_secret(&mut state.secret_override, &config, rand());
+                    GuessingGame::from_config(secret, &config)
+                }
+            };
+            let mut session = Session::new(game, now_ms(), remote_ip);
+            session.protocol = ws_handler_version(ws);
+            let json = session.protocol.json;
+            state.sessions.insert(session_id, session);
+            session_store.lock().unwrap().save(&state.sessions);
             info!(
-                "New WebSocket session {} ({} total sessions open)",
+                "New WebSocket session {} from {:?} ({} total sessions open)",
                 session_id,
-                sessions.len()
+                remote_ip.map(Ipv4Addr::from),
+                state.sessions.len()
             );
 
             // Send welcome message
-            let welcome_msg = "Welcome to the guessing game! Enter a number between 1 and 100".to_string();
-            drop(sessions); // Release lock before sending
+            let welcome_msg = WsMessage::Welcome {
+                min: config.min,
+                max: config.max,
+            }
+            .render(json);
+            drop(state); // Release lock before sending
+            show_ws_session_count(app_state.ws_session_opened(session_id, "/ws/guess"));
+            match ws.create_detached_sender() {
+                Ok(sender) => heartbeat.register(session_id, sender),
+                Err(e) => warn!("No heartbeat for session {}: {:?}", session_id, e),
+            }
             ws.send(FrameType::Text(false), welcome_msg.as_bytes())?;
             return Ok(());
         } else if ws.is_closed() {
+            heartbeat.unregister(session_id);
             // Only remove this specific session - other sessions are unaffected
-            let removed = sessions.remove(&session_id);
-            if removed.is_some() {
+            let removed = state.sessions.remove(&session_id);
+            // Idle and stale sessions were already dropped and saved by the
+            // heartbeat
+            if let Some(session) = removed {
+                session_store.lock().unwrap().save(&state.sessions);
                 info!(
-                    "Closed WebSocket session {} ({} total sessions remaining)",
+                    "Closed WebSocket session {} after {}, {} messages, {} bytes ({} total sessions remaining)",
                     session_id,
-                    sessions.len()
+                    format_duration(session.duration_ms(now_ms()) / 1000),
+                    session.messages_received,
+                    session.bytes_received,
+                    state.sessions.len()
                 );
-            } else {
-                warn!("Attempted to remove non-existent session {}", session_id);
             }
             return Ok(());
         }
 
         // Release the lock before blocking on recv to allow other sessions to proceed
-        drop(sessions);
+        drop(state);
         
         // NOTE: Due to the way the underlying C implementation works, ws.recv()
         // may only be called with an empty buffer exactly once to receive the
         // incoming buffer size, then must be called exactly once to receive the
         // actual payload.
-        let (_frame_type, len) = match ws.recv(&mut []) {
+        let (frame_type, len) = match ws.recv(&mut []) {
             Ok(frame) => {
                 let len = frame.1;
                 debug!("Received frame of length: {} from session {}", len, session_id);
@@ -256,22 +1891,53 @@ fn main() -> anyhow::Result<()> {
             }
         };
 
+        match frame_type {
+            FrameType::Pong => {
+                heartbeat.pong(session_id);
+                return Ok(());
+            }
+            FrameType::Ping => {
+                heartbeat.seen(session_id);
+                ws.send(FrameType::Pong, &[])?;
+                return Ok(());
+            }
+            FrameType::Close | FrameType::SocketClose => heartbeat.seen(session_id),
+            _ => {
+                heartbeat.seen(session_id);
+                heartbeat.active(session_id);
+            }
+        }
+
         if len > MAX_LEN {
             warn!("Request too big: {} bytes (max: {})", len, MAX_LEN);
             ws.send(FrameType::Text(false), "Request too big".as_bytes())?;
             ws.send(FrameType::Close, &[])?;
-            return Err(EspError::from_infallible::<ESP_ERR_INVALID_SIZE>());
+            return Err(ServerError::PayloadTooLarge.into_esp_error());
         }
 
         let mut buf = [0; MAX_LEN]; // Small digit buffer can go on the stack
         ws.recv(buf.as_mut())?;
+        let (within_rate_limit, protocol) = app_state
+            .sessions()
+            .get_mut(&session_id)
+            .map(|session| {
+                session.record_message(len);
+                (session.within_rate_limit(now_ms()), session.protocol)
+            })
+            .unwrap_or((true, WsProtocol::default()));
+        if !within_rate_limit {
+            warn!("Session {} exceeded {} messages per second", session_id, WS_MAX_MSG_PER_SEC);
+            ws.send(FrameType::Text(false), "Rate limit exceeded".as_bytes())?;
+            ws.send(FrameType::Close, &[])?;
+            return Ok(());
+        }
 
         // Try to parse as null-terminated C string first, otherwise use the length
         let user_string = if let Ok(c_str) = CStr::from_bytes_until_nul(&buf[..len]) {
             match c_str.to_str() {
                 Ok(s) => s,
                 Err(e) => {
-                    warn!("Failed to decode UTF-8 string from session {}: {:?}", session_id, e);
+                    warn!("{} from session {}: {:?}", ServerError::Encoding, session_id, e);
                     ws.send(FrameType::Text(false), "[UTF-8 Error]".as_bytes())?;
                     return Ok(());
                 }
@@ -281,73 +1947,193 @@ fn main() -> anyhow::Result<()> {
             match std::str::from_utf8(&buf[..len]) {
                 Ok(s) => s,
                 Err(e) => {
-                    warn!("Failed to decode UTF-8 string from session {}: {:?}", session_id, e);
+                    warn!("{} from session {}: {:?}", ServerError::Encoding, session_id, e);
                     ws.send(FrameType::Text(false), "[UTF-8 Error]".as_bytes())?;
                     return Ok(());
                 }
             }
         };
 
-        let Some(user_guess) = GuessingGame::parse_guess(user_string) else {
-            info!("Invalid guess from session {}: {}", session_id, user_string);
-            ws.send(
-                FrameType::Text(false),
-                "Please enter a number between 1 and 100".as_bytes(),
-            )?;
-            return Ok(());
+        // Sessions that negotiated the JSON subprotocol always get JSON, others
+        // get replies in the same format the client used
+        let json = protocol.json || WsMessage::is_json(user_string);
+
+        let user_guess = match WsIncoming::parse(user_string, &config) {
+            Ok(WsIncoming::Guess(user_guess)) => user_guess,
+            Ok(WsIncoming::Ping) => {
+                ws.send(FrameType::Text(false), WsMessage::Pong.render(json).as_bytes())?;
+                return Ok(());
+            }
+            Ok(WsIncoming::GiveUp) => {
+                let gave_up = {
+                    let mut sessions = app_state.sessions();
+                    let gave_up = sessions
+                        .get_mut(&session_id)
+                        .map(|session| (session.game.give_up(), session.game.history().to_vec()));
+                    if gave_up.is_some() {
+                        session_store.lock().unwrap().save(&sessions);
+                    }
+                    gave_up
+                };
+                let Some((secret, history)) = gave_up else {
+                    warn!("Session {}: {}", session_id, ServerError::GameNotFound);
+                    let reply = WsMessage::Error("No game in progress".to_string());
+                    ws.send(FrameType::Text(false), reply.render(json).as_bytes())?;
+                    return Ok(());
+                };
+                info!("Session {} gave up, secret was {}", session_id, secret);
+                let reply = WsMessage::GaveUp { secret, history };
+                ws.send(FrameType::Text(false), reply.render(json).as_bytes())?;
+                ws.send(FrameType::Close, &[])?;
+                return Ok(());
+            }
+            Err(reply) => {
+                info!("Invalid guess from session {}: {}", session_id, user_string);
+                ws.send(FrameType::Text(false), reply.render(json).as_bytes())?;
+                return Ok(());
+            }
         };
 
         // Process the guess and prepare reply - acquire lock only for this session
-        let (reply, new_secret) = {
-            let mut sessions = guessing_games.lock().unwrap();
+        #[cfg_attr(not(feature = "oled"), allow(unused_variables))]
+        let (reply, new_secret, guesses, round_message) = {
+            let mut sessions = app_state.sessions();
+            // Sessions rejected on connect because the server was full have no game
+            if !sessions.contains_key(&session_id) && sessions.len() >= MAX_WS_SESSIONS {
+                warn!("Session {}: {}, server full", session_id, ServerError::GameNotFound);
+                drop(sessions);
+                let reply = WsMessage::Error("Server full, try again later".to_string());
+                ws.send(FrameType::Text(false), reply.render(json).as_bytes())?;
+                ws.send(FrameType::Close, &[])?;
+                return Ok(());
+            }
             let session = match sessions.get_mut(&session_id) {
-                Some(s) => s,
+                Some(s) => &mut s.game,
                 None => {
-                    warn!("Session {} not found, creating new one", session_id);
-                    let secret = (rand() % 100) + 1;
-                    sessions.insert(session_id, GuessingGame::new(secret));
-                    sessions.get_mut(&session_id).unwrap()
+                    warn!("Session {}: {}, creating new one", session_id, ServerError::GameNotFound);
+                    let secret = config.secret_from(rand());
+                    let game = GuessingGame::from_config(secret, &config);
+                    let remote_ip = peer_ipv4(session_id).map(|ip| ip.octets());
+                    sessions.insert(session_id, Session::new(game, now_ms(), remote_ip));
+                    &mut sessions.get_mut(&session_id).unwrap().game
                 }
             };
-            
-            match session.guess(user_guess) {
-                (Ordering::Greater, n) => {
-                    let reply = format!("Your {} guess was too high", nth(n));
-                    (reply, None)
+
+            // Games only stay done after giving up, a win starts the next one
+            if session.is_done() {
+                warn!("Guess on finished game from session {}", session_id);
+                let reply = WsMessage::Error("This game is over, reconnect to play again".to_string());
+                drop(sessions);
+                ws.send(FrameType::Text(false), reply.render(json).as_bytes())?;
+                return Ok(());
+            }
+
+            let outcome = match session.guess(user_guess) {
+                (Ordering::Greater | Ordering::Less, n, true) => {
+                    info!("Session {} ran out of guesses after {}", session_id, n);
+                    (WsMessage::GameOver { secret: session.secret() }, None, n)
                 }
-                (Ordering::Less, n) => {
-                    let reply = format!("Your {} guess was too low", nth(n));
-                    (reply, None)
+                (ordering @ (Ordering::Greater | Ordering::Less), n, false) => {
+                    let distance = user_guess.abs_diff(session.secret());
+                    let remaining = session.guesses_remaining();
+                    let difficulty = session.difficulty();
+                    let reply = WsMessage::result(difficulty, ordering, n, remaining, distance);
+                    (reply, None, n)
                 }
-                (Ordering::Equal, n) => {
-                    let secret_num = session.secret();
-                    let reply = format!(
-                        "You guessed {} on your {} try! Game over. Enter a new number to play again!",
-                        secret_num,
-                        nth(n)
-                    );
-                    // Generate a new secret for the next game
-                    let new_secret = (rand() % 100) + 1;
-                    sessions.insert(session_id, GuessingGame::new(new_secret));
-                    info!("Generated new secret {} for session {}", new_secret, session_id);
-                    (reply, Some(new_secret))
+                (Ordering::Equal, n, _) => {
+                    let reply = WsMessage::Win {
+                        secret: session.secret(),
+                        attempts: n,
+                        history: session.history().to_vec(),
+                    };
+                    // Start the next game in the same session slot, multi-round
+                    // sessions do that in `finish_round` instead
+                    let new_secret = (config.rounds <= 1).then(|| {
+                        let new_secret = config.secret_from(rand());
+                        session.reset(new_secret);
+                        info!("Generated new secret {} for session {}", new_secret, session_id);
+                        new_secret
+                    });
+                    (reply, new_secret, n)
                 }
-            }
+            };
+            let (reply, new_secret, guesses) = outcome;
+            let round_message = match reply {
+                WsMessage::Win { .. } | WsMessage::GameOver { .. } => {
+                    let won = matches!(reply, WsMessage::Win { .. });
+                    let next_secret = config.secret_from(rand());
+                    let round_message = sessions
+                        .get_mut(&session_id)
+                        .and_then(|s| s.finish_round(won, guesses, config.rounds, next_secret));
+                    session_store.lock().unwrap().save(&sessions);
+                    round_message
+                }
+                _ => None,
+            };
+            (reply, new_secret, guesses, round_message)
         };
         
         if let Some(secret) = new_secret {
             info!("Game won by session {}! New secret: {}", session_id, secret);
         }
+
+        if let WsMessage::Win { attempts, .. } = reply {
+            leaderboard.lock().unwrap().record(attempts);
+        }
+
+        #[cfg(feature = "oled")]
+        if let Some(oled) = &oled_for_guess {
+            let value = u8::try_from(guesses).unwrap_or(u8::MAX);
+            let max = u8::try_from(config.max_guesses).unwrap_or(u8::MAX);
+            if let Err(e) = oled.draw_progress_bar(value, max, OLED_PROGRESS_BAR_Y) {
+                warn!("Failed to draw progress bar: {:?}", e);
+            }
+        }
         
         // Send reply (lock is already released)
-        ws.send(FrameType::Text(false), reply.as_ref())?;
+        ws.send(FrameType::Text(false), reply.render(json).as_bytes())?;
+
+        match round_message {
+            Some(message @ WsMessage::RoundComplete { .. }) => {
+                info!("Session {}: {}", session_id, message.to_text());
+                ws.send(FrameType::Text(false), message.render(json).as_bytes())?;
+            }
+            Some(message) => {
+                info!("Session {}: {}", session_id, message.to_text());
+                ws.send(FrameType::Text(false), message.render(json).as_bytes())?;
+                ws.send(FrameType::Close, &[])?;
+            }
+            None if matches!(reply, WsMessage::GameOver { .. }) => {
+                ws.send(FrameType::Close, &[])?;
+            }
+            None => {}
+        }
         
         Ok::<(), EspError>(())
     })?;
 
+    // Fallback for unknown paths, must be registered after every other route
+    for method in [Method::Get, Method::Post] {
+        let limiter_for_not_found = rate_limiter.clone();
+        server.fn_handler("/*", method, logged(move |mut req| {
+            if rate_limited(&limiter_for_not_found, &mut req) {
+                return too_many_requests(req);
+            }
+            warn!("No route for {}", req.uri());
+            respond_html(req, 404, NOT_FOUND_HTML)
+        }))?;
+    }
 
     info!("Server started successfully. Waiting for connections...");
 
+    // Let phones join the AP by scanning the screen
+    #[cfg(feature = "oled")]
+    if let Some(oled) = &oled_display {
+        if let Err(e) = oled.display_qr_wifi(wifi_status.ssid, &credentials.game_password) {
+            warn!("Failed to display Wi-Fi QR code: {:?}", e);
+        }
+    }
+
     // Keep server running beyond when main() returns (forever)
     // Do not call this if you ever want to stop or access it later.
     // Otherwise you can either add an infinite loop so the main task
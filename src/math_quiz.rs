@@ -0,0 +1,155 @@
+//! Arithmetic quiz played over the /ws/quiz WebSocket
+//!
+//! Each session gets `QUIZ_QUESTIONS` random questions using addition,
+//! subtraction or multiplication of two operands between 1 and 20.
+
+use log::*;
+
+use crate::config::QUIZ_QUESTIONS;
+use crate::utils::rand;
+
+// Operand range for generated questions (inclusive)
+const MIN_OPERAND: u32 = 1;
+const MAX_OPERAND: u32 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operation {
+    Add,
+    Subtract,
+    Multiply,
+}
+
+impl Operation {
+    fn from_random(n: u32) -> Self {
+        match n % 3 {
+            0 => Self::Add,
+            1 => Self::Subtract,
+            _ => Self::Multiply,
+        }
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            Self::Add => '+',
+            Self::Subtract => '-',
+            Self::Multiply => '*',
+        }
+    }
+}
+
+/// Quiz state for a single session
+#[derive(Default)]
+pub struct MathQuiz {
+    question: String,
+    answer: u32,
+    score: u32,
+    asked: u32,
+}
+
+impl MathQuiz {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate the next question and return its text
+    pub fn next_question(&mut self) -> &str {
+        let a = MIN_OPERAND + rand() % (MAX_OPERAND - MIN_OPERAND + 1);
+        let b = MIN_OPERAND + rand() % (MAX_OPERAND - MIN_OPERAND + 1);
+        self.set_question(a, b, Operation::from_random(rand()));
+        &self.question
+    }
+
+    fn set_question(&mut self, a: u32, b: u32, operation: Operation) {
+        // Larger operand first so subtraction never goes negative
+        let (a, b) = if operation == Operation::Subtract && a < b {
+            (b, a)
+        } else {
+            (a, b)
+        };
+        self.answer = match operation {
+            Operation::Add => a + b,
+            Operation::Subtract => a - b,
+            Operation::Multiply => a * b,
+        };
+        self.asked += 1;
+        self.question = format!(
+            "Question {}/{}: What is {} {} {}?",
+            self.asked,
+            QUIZ_QUESTIONS,
+            a,
+            operation.symbol(),
+            b
+        );
+        debug!("Quiz question `{}`, answer {}", self.question, self.answer);
+    }
+
+    /// Parse a client reply like "42"
+    pub fn parse_answer(input: &str) -> Option<u32> {
+        input.trim().trim_end_matches('\0').parse().ok()
+    }
+
+    /// Check an answer to the current question and return the feedback text
+    pub fn answer(&mut self, reply: u32) -> String {
+        if reply == self.answer {
+            self.score += 1;
+            format!("Correct! Score: {}", self.score)
+        } else {
+            format!("Wrong, the answer was {}", self.answer)
+        }
+    }
+
+    /// Whether every question of the session has been asked
+    pub fn is_finished(&self) -> bool {
+        self.asked >= QUIZ_QUESTIONS
+    }
+
+    /// Message sent when the quiz ends
+    pub fn final_score(&self) -> String {
+        format!("Quiz over! Final score: {}/{}", self.score, self.asked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answers() {
+        let mut quiz = MathQuiz::new();
+        quiz.set_question(7, 12, Operation::Add);
+        assert_eq!(quiz.question, "Question 1/10: What is 7 + 12?");
+        assert_eq!(quiz.answer(19), "Correct! Score: 1");
+
+        quiz.set_question(3, 4, Operation::Multiply);
+        assert_eq!(quiz.answer(13), "Wrong, the answer was 12");
+        assert_eq!(quiz.score, 1);
+    }
+
+    #[test]
+    fn test_subtraction_is_never_negative() {
+        let mut quiz = MathQuiz::new();
+        quiz.set_question(5, 18, Operation::Subtract);
+        assert_eq!(quiz.question, "Question 1/10: What is 18 - 5?");
+        assert_eq!(quiz.answer, 13);
+    }
+
+    #[test]
+    fn test_next_question_in_range() {
+        let mut quiz = MathQuiz::new();
+        for _ in 0..QUIZ_QUESTIONS {
+            assert!(!quiz.is_finished());
+            quiz.next_question();
+            assert!(quiz.answer <= MAX_OPERAND * MAX_OPERAND);
+        }
+        assert!(quiz.is_finished());
+        assert_eq!(quiz.final_score(), "Quiz over! Final score: 0/10");
+    }
+
+    #[test]
+    fn test_parse_answer() {
+        assert_eq!(MathQuiz::parse_answer(" 42\n"), Some(42));
+        assert_eq!(MathQuiz::parse_answer("42\0"), Some(42));
+        assert_eq!(MathQuiz::parse_answer("-1"), None);
+        assert_eq!(MathQuiz::parse_answer("abc"), None);
+    }
+}
@@ -4,71 +4,128 @@
 //! SDA     GPIO5
 //! SCL     GPIO6
 //!
-//! I2C address: 0x3c
+//! I2C address: 0x3c, or 0x3d on some modules (detected at startup)
 
 use anyhow::Result;
 use esp_idf_hal::{
-    delay::FreeRtos,
+    delay::{Ets, FreeRtos, TickType},
     i2c::{I2C0, *},
-    gpio::{Gpio5, Gpio6},
+    gpio::{Gpio5, Gpio6, PinDriver, Pull},
     units::*,
 };
 use log::*;
 use embedded_graphics::{
-    mono_font::{ascii::FONT_6X10, MonoTextStyle, MonoTextStyleBuilder},
+    image::{Image, ImageRaw},
+    mono_font::{
+        ascii::{FONT_5X8, FONT_6X10, FONT_9X18},
+        MonoFont, MonoTextStyle, MonoTextStyleBuilder,
+    },
     pixelcolor::BinaryColor,
     prelude::*,
-    text::{Baseline, Text},
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
 };
+use qrcodegen::{QrCode, QrCodeEcc, QrSegment, Version};
 use ssd1306::{prelude::*, Ssd1306, mode::BufferedGraphicsMode};
-use std::sync::Mutex;
+use std::{ops::Range, sync::Mutex};
+
+use crate::config::{
+    OLED_ROTATION, SPLASH_BMP, SPLASH_HEIGHT, SPLASH_WIDTH, SSD1306_FALLBACK_ADDRESS,
+};
+use crate::utils::now_us;
 
 const SSD1306_ADDRESS: u8 = 0x3c;
+// Time to wait for an ACK while probing for the display
+const PROBE_TIMEOUT_MS: u64 = 50;
+// Largest QR code that fits the 40 px tall display at one pixel per module (37x37)
+const MAX_QR_VERSION: u8 = 5;
+// Rows at the bottom of the screen kept for the status bar, messages are drawn above
+const STATUS_BAR_HEIGHT: u32 = 10;
+// Height of the bar drawn by `draw_progress_bar`
+const PROGRESS_BAR_HEIGHT: u32 = 2;
+// Bus recoveries tried after a failed update before giving up on it
+const MAX_RECOVERY_ATTEMPTS: u32 = 3;
+// Clock pulses that let a slave finish the byte it is stuck sending
+const BUS_CLEAR_PULSES: u32 = 9;
+// Half period of the bit-banged clock, about 100 kHz
+const BUS_CLEAR_HALF_PERIOD_US: u32 = 5;
+
+type Ssd1306Display<SIZE> =
+    Ssd1306<I2CInterface<I2cDriver<'static>>, SIZE, BufferedGraphicsMode<SIZE>>;
 
 /// OLED display wrapper for thread-safe access
 /// Supports both 128x64 and 72x40 displays
+///
+/// Content is drawn into an off-screen `Frame` first and only the pixels
+/// that differ from the last flushed frame are written to the panel, so the
+/// display never shows a cleared intermediate state.
 pub struct OledDisplay {
-    display: Mutex<DisplayType>,
+    // `None` once the display was lost in a failed bus recovery
+    display: Mutex<Option<DisplayType>>,
+    // Last frame sent to the panel, `None` until the first `present`
+    // Always locked after `display`
+    shadow: Mutex<Option<Frame>>,
+    // Microseconds the last flush of every row took, the baseline the
+    // partial flushes are logged against. Always locked after `shadow`
+    full_flush_us: Mutex<Option<u64>>,
+    // Status bar text drawn below every message, `None` until first set
+    status: Mutex<Option<String>>,
+    // Progress bar drawn over every message, `None` until first set
+    progress: Mutex<Option<ProgressBar>>,
+}
+
+/// Fonts messages can be drawn in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Font {
+    /// `FONT_5X8`, also used by the status bar
+    Small,
+    /// `FONT_6X10`, used unless another font is asked for
+    #[default]
+    Medium,
+    /// `FONT_9X18`, for numbers and short words
+    Large,
+}
+
+impl Font {
+    fn mono(self) -> &'static MonoFont<'static> {
+        match self {
+            Self::Small => &FONT_5X8,
+            Self::Medium => &FONT_6X10,
+            Self::Large => &FONT_9X18,
+        }
+    }
+
+    fn style(self) -> MonoTextStyle<'static, BinaryColor> {
+        MonoTextStyle::new(self.mono(), BinaryColor::On)
+    }
+
+    /// Characters per line and lines fitting in a `width` x `height` area
+    fn text_grid(self, width: u32, height: u32) -> (usize, usize) {
+        let glyph = self.mono().character_size;
+        ((width / glyph.width) as usize, (height / glyph.height) as usize)
+    }
+}
+
+/// How a message is placed above the status bar
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Word-wrapped lines from the top left
+    Wrapped(Font),
+    /// A single line centered in the message area
+    Centered(Font),
 }
 
 enum DisplayType {
     #[allow(dead_code)] // Available for future use with 128x64 displays
-    Size128x64(Ssd1306<I2CInterface<&'static mut I2cDriver<'static>>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>),
-    Size72x40(Ssd1306<I2CInterface<&'static mut I2cDriver<'static>>, DisplaySize72x40, BufferedGraphicsMode<DisplaySize72x40>>),
+    Size128x64(Ssd1306Display<DisplaySize128x64>),
+    Size72x40(Ssd1306Display<DisplaySize72x40>),
 }
 
 impl OledDisplay {
     /// Initialize the OLED display
     pub fn init(i2c: I2C0, sda: Gpio5, scl: Gpio6) -> Result<Self> {
         info!("Starting I2C SSD1306 initialization");
-
-        info!("I2C address: 0x{:02x}", SSD1306_ADDRESS);
-
-        let config = I2cConfig::new().baudrate(100.kHz().into());
-        let i2c_driver = I2cDriver::new(i2c, sda, scl, &config)?;
-
-        info!("Creating I2C display interface...");
-        // I2CInterface::new takes (i2c, address, data_byte)
-        // data_byte is typically 0x40 for data commands
-        // Make the driver static by leaking it (it will live for the lifetime of the program)
-        let i2c_driver = Box::leak(Box::new(i2c_driver));
-        let interface = I2CInterface::new(i2c_driver, SSD1306_ADDRESS, 0x40);
-
-        // Initialize for 72x40 display
-        info!("Initializing SSD1306 display (72x40)...");
-        let mut display = Ssd1306::new(
-            interface,
-            DisplaySize72x40,
-            DisplayRotation::Rotate0,
-        )
-        .into_buffered_graphics_mode();
-
-        info!("Calling display.init()...");
-        display.init().map_err(|e| {
-            error!("Display init failed: {:?}", e);
-            error!("Check I2C wiring (SDA=GPIO5, SCL=GPIO6) and address (try 0x3d if 0x3c doesn't work)");
-            anyhow::anyhow!("Display init error: {:?}", e)
-        })?;
+        let mut display = connect(i2c, sda, scl)?;
         
         info!("Display initialized successfully with 72x40!");
         
@@ -97,85 +154,230 @@ impl OledDisplay {
         display.clear(BinaryColor::Off).map_err(|e| anyhow::anyhow!("Clear error: {:?}", e))?;
         
         // Show initial ready message (adjusted for 72x40 - max 4 lines, ~12 chars per line)
-        let text_style = MonoTextStyleBuilder::new()
-            .font(&FONT_6X10)
-            .text_color(BinaryColor::On)
-            .build();
-        
-        Text::with_baseline("Server", Point::new(0, 5), text_style, Baseline::Top)
-            .draw(&mut display)
-            .map_err(|_| anyhow::anyhow!("Initial message draw error"))?;
-        Text::with_baseline("Ready!", Point::new(0, 15), text_style, Baseline::Top)
-            .draw(&mut display)
-            .map_err(|_| anyhow::anyhow!("Initial message draw error"))?;
-        Text::with_baseline("Waiting...", Point::new(0, 25), text_style, Baseline::Top)
-            .draw(&mut display)
-            .map_err(|_| anyhow::anyhow!("Initial message draw error"))?;
-        
-        display.flush().map_err(|e| anyhow::anyhow!("Flush error: {:?}", e))?;
-        info!("Initial ready message displayed");
-        
-        Ok(Self {
-            display: Mutex::new(DisplayType::Size72x40(display)),
-        })
+        // unless a splash screen is configured
+        if SPLASH_BMP.is_none() {
+            let text_style = MonoTextStyleBuilder::new()
+                .font(&FONT_6X10)
+                .text_color(BinaryColor::On)
+                .build();
+
+            Text::with_baseline("Server", Point::new(0, 5), text_style, Baseline::Top)
+                .draw(&mut display)
+                .map_err(|_| anyhow::anyhow!("Initial message draw error"))?;
+            Text::with_baseline("Ready!", Point::new(0, 15), text_style, Baseline::Top)
+                .draw(&mut display)
+                .map_err(|_| anyhow::anyhow!("Initial message draw error"))?;
+            Text::with_baseline("Waiting...", Point::new(0, 25), text_style, Baseline::Top)
+                .draw(&mut display)
+                .map_err(|_| anyhow::anyhow!("Initial message draw error"))?;
+
+            display.flush().map_err(|e| anyhow::anyhow!("Flush error: {:?}", e))?;
+            info!("Initial ready message displayed");
+        }
+
+        let oled = Self {
+            display: Mutex::new(Some(DisplayType::Size72x40(display))),
+            shadow: Mutex::new(None),
+            full_flush_us: Mutex::new(None),
+            status: Mutex::new(None),
+            progress: Mutex::new(None),
+        };
+        if let Some(splash) = SPLASH_BMP {
+            oled.display_raw_bitmap(splash, SPLASH_WIDTH, SPLASH_HEIGHT)?;
+        }
+        Ok(oled)
     }
 
-    /// Display a message on the OLED screen
+    /// Display a message on the OLED screen in the default `Font::Medium`
     /// Messages are wrapped to fit on multiple lines if needed
     pub fn display_message(&self, message: &str) -> Result<()> {
+        self.display_message_with_font(message, Font::default())
+    }
+
+    /// Display a message in `font`, wrapped to fit on multiple lines
+    /// If the update fails, the I2C bus is recovered and the message sent again
+    pub fn display_message_with_font(&self, message: &str, font: Font) -> Result<()> {
+        self.show(message, Layout::Wrapped(font))
+    }
+
+    /// Display `n` in `FONT_9X18`, centered above the status bar
+    #[allow(dead_code)] // Available for showing scores and guess counts
+    pub fn display_large_number(&self, n: u32) -> Result<()> {
+        self.show(&n.to_string(), Layout::Centered(Font::Large))
+    }
+
+    fn show(&self, message: &str, layout: Layout) -> Result<()> {
+        let result = self.draw_message(&mut self.display.lock().unwrap(), message, layout);
+        if let Err(e) = result {
+            warn!("OLED update failed, recovering the I2C bus: {:?}", e);
+            return self.recover_and_retry(message, layout);
+        }
+        info!("Display updated with message: {}", message);
+        Ok(())
+    }
+
+    /// Clear the I2C bus and reconnect the display, then show `message`
+    /// Gives up after `MAX_RECOVERY_ATTEMPTS` attempts
+    pub fn recover_and_retry(&self, message: &str, layout: Layout) -> Result<()> {
+        let mut display_guard = self.display.lock().unwrap();
+        for attempt in 1..=MAX_RECOVERY_ATTEMPTS {
+            warn!("I2C bus recovery attempt {} of {}", attempt, MAX_RECOVERY_ATTEMPTS);
+            let result = self
+                .reconnect(&mut display_guard)
+                .and_then(|()| self.draw_message(&mut display_guard, message, layout));
+            match result {
+                Ok(()) => {
+                    info!("OLED recovered, displayed message: {}", message);
+                    return Ok(());
+                }
+                Err(e) => warn!("I2C bus recovery attempt {} failed: {:?}", attempt, e),
+            }
+        }
+        error!("OLED still failing after {} recovery attempts", MAX_RECOVERY_ATTEMPTS);
+        Err(anyhow::anyhow!(
+            "OLED unreachable after {} I2C bus recoveries",
+            MAX_RECOVERY_ATTEMPTS
+        ))
+    }
+
+    /// Drop the display and its I2C driver, clear the bus and connect again
+    fn reconnect(&self, display: &mut Option<DisplayType>) -> Result<()> {
+        // Deletes the I2C driver, releasing the pins
+        *display = None;
+        // Safety: the only other owner of these peripherals was just dropped
+        let (i2c, sda, scl) = unsafe { (I2C0::new(), Gpio5::new(), Gpio6::new()) };
+        let (sda, scl) = clear_bus(sda, scl)?;
+        *display = Some(DisplayType::Size72x40(connect(i2c, sda, scl)?));
+        // The panel's memory is unknown after a re-init, so redraw everything
+        *self.shadow.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn draw_message(
+        &self,
+        display: &mut Option<DisplayType>,
+        message: &str,
+        layout: Layout,
+    ) -> Result<()> {
+        // Draw into a blank frame based on display type, then send the changes
+        match display.as_mut().ok_or_else(display_lost)? {
+            DisplayType::Size128x64(display) => {
+                let mut frame = Frame::new(display.size());
+                match layout {
+                    Layout::Wrapped(font) => self.draw_text_128x64(&mut frame, message, font)?,
+                    Layout::Centered(font) => draw_centered(&mut frame, message, font)?,
+                }
+                self.draw_overlays(&mut frame)?;
+                self.present(display, frame)?;
+                info!("128x64 display updated");
+            }
+            DisplayType::Size72x40(display) => {
+                let mut frame = Frame::new(display.size());
+                match layout {
+                    Layout::Wrapped(font) => self.draw_text_72x40(&mut frame, message, font)?,
+                    Layout::Centered(font) => draw_centered(&mut frame, message, font)?,
+                }
+                self.draw_overlays(&mut frame)?;
+                self.present(display, frame)?;
+                info!("72x40 display updated");
+            }
+        }
+        Ok(())
+    }
+    
+    /// Display a message, scrolling it upward one line at a time if it does
+    /// not fit on the screen
+    /// Scrolls through once, then rests on the last screen
+    pub fn display_message_scroll(&self, message: &str, scroll_delay_ms: u32) -> Result<()> {
+        if message.trim().is_empty() {
+            warn!("Ignoring empty message for OLED display");
+            return Ok(());
+        }
+
         let mut display_guard = self.display.lock().unwrap();
 
-        // Create text style
         let text_style = MonoTextStyleBuilder::new()
             .font(&FONT_6X10)
             .text_color(BinaryColor::On)
             .build();
 
-        // Clear and draw based on display type
-        match *display_guard {
-            DisplayType::Size128x64(ref mut display) => {
-                info!("Clearing 128x64 display...");
-                display.clear(BinaryColor::Off).map_err(|e| anyhow::anyhow!("Clear error: {:?}", e))?;
-                info!("Drawing text on 128x64 display...");
-                self.draw_text_128x64(display, message, &text_style)?;
-                info!("Flushing 128x64 display...");
-                display.flush().map_err(|e| anyhow::anyhow!("Flush error: {:?}", e))?;
-                info!("128x64 display flushed successfully");
+        match display_guard.as_mut().ok_or_else(display_lost)? {
+            DisplayType::Size128x64(display) => {
+                self.scroll_text(display, message, &text_style, scroll_delay_ms)?;
             }
-            DisplayType::Size72x40(ref mut display) => {
-                info!("Clearing 72x40 display...");
-                display.clear(BinaryColor::Off).map_err(|e| anyhow::anyhow!("Clear error: {:?}", e))?;
-                info!("Drawing text on 72x40 display...");
-                self.draw_text_72x40(display, message, &text_style)?;
-                info!("Flushing 72x40 display...");
-                display.flush().map_err(|e| anyhow::anyhow!("Flush error: {:?}", e))?;
-                info!("72x40 display flushed successfully");
+            DisplayType::Size72x40(display) => {
+                self.scroll_text(display, message, &text_style, scroll_delay_ms)?;
             }
         }
 
-        info!("Display updated with message: {}", message);
+        info!("Display scrolled through message: {}", message);
         Ok(())
     }
-    
-    fn draw_text_128x64<D: DrawTarget<Color = BinaryColor>>(
+
+    fn scroll_text<SIZE: DisplaySize>(
         &self,
-        display: &mut D,
+        display: &mut Ssd1306Display<SIZE>,
         message: &str,
         text_style: &MonoTextStyle<'_, BinaryColor>,
+        scroll_delay_ms: u32,
     ) -> Result<()> {
-        const CHARS_PER_LINE: usize = 20;
-        const MAX_LINES: usize = 6;
         const LINE_HEIGHT: i32 = 10;
+        const TOP_MARGIN: i32 = 0;
 
-        let lines = self.wrap_text(message, CHARS_PER_LINE, MAX_LINES);
+        // Already swapped for a display mounted sideways
+        let size = display.size();
+        let chars_per_line = (size.width / text_style.font.character_size.width) as usize;
+        let lines = self.wrap_text(message, chars_per_line, usize::MAX);
+        if lines.is_empty() {
+            warn!("Message is blank after wrapping, nothing to display");
+            return Ok(());
+        }
 
-        for (i, line) in lines.iter().enumerate() {
-            let y_pos = (i as i32 * LINE_HEIGHT) + 5;
-            if y_pos + LINE_HEIGHT <= 64 {
+        let message_height = (size.height - STATUS_BAR_HEIGHT) as i32;
+        let visible_lines = ((message_height - TOP_MARGIN) / LINE_HEIGHT) as usize;
+        let last_top = lines.len().saturating_sub(visible_lines);
+        debug!(
+            "Scrolling {} lines, {} visible at a time",
+            lines.len(),
+            visible_lines
+        );
+
+        for top in 0..=last_top {
+            if top > 0 {
+                FreeRtos::delay_ms(scroll_delay_ms);
+            }
+            let mut frame = Frame::new(display.size());
+            for (i, line) in lines[top..].iter().take(visible_lines).enumerate() {
+                let y_pos = (i as i32 * LINE_HEIGHT) + TOP_MARGIN;
                 Text::with_baseline(line, Point::new(0, y_pos), *text_style, Baseline::Top)
-                    .draw(display)
+                    .draw(&mut frame)
                     .map_err(|_| anyhow::anyhow!("Text draw error"))?;
             }
+            self.draw_overlays(&mut frame)?;
+            self.present(display, frame)?;
+        }
+        Ok(())
+    }
+
+    fn draw_text_128x64<D: DrawTarget<Color = BinaryColor>>(
+        &self,
+        display: &mut D,
+        message: &str,
+        font: Font,
+    ) -> Result<()> {
+        const TOP_MARGIN: u32 = 5;
+
+        let size = display.bounding_box().size;
+        let (chars_per_line, max_lines) =
+            font.text_grid(size.width, size.height - STATUS_BAR_HEIGHT - TOP_MARGIN);
+        let line_height = font.mono().character_size.height;
+        let lines = self.wrap_text(message, chars_per_line, max_lines);
+
+        for (i, line) in lines.iter().enumerate() {
+            let y_pos = (i as u32 * line_height + TOP_MARGIN) as i32;
+            Text::with_baseline(line, Point::new(0, y_pos), font.style(), Baseline::Top)
+                .draw(display)
+                .map_err(|_| anyhow::anyhow!("Text draw error"))?;
         }
         Ok(())
     }
@@ -184,21 +386,21 @@ impl OledDisplay {
         &self,
         display: &mut D,
         message: &str,
-        text_style: &MonoTextStyle<'_, BinaryColor>,
+        font: Font,
     ) -> Result<()> {
-        const CHARS_PER_LINE: usize = 12;
-        const MAX_LINES: usize = 4;
-        const LINE_HEIGHT: i32 = 10;
-
-        let lines = self.wrap_text(message, CHARS_PER_LINE, MAX_LINES);
+        // Starts at the top edge so three lines of `Font::Medium` fit above
+        // the status bar
+        let size = display.bounding_box().size;
+        let (chars_per_line, max_lines) =
+            font.text_grid(size.width, size.height - STATUS_BAR_HEIGHT);
+        let line_height = font.mono().character_size.height;
+        let lines = self.wrap_text(message, chars_per_line, max_lines);
 
         for (i, line) in lines.iter().enumerate() {
-            let y_pos = (i as i32 * LINE_HEIGHT) + 5;
-            if y_pos + LINE_HEIGHT <= 40 {
-                Text::with_baseline(line, Point::new(0, y_pos), *text_style, Baseline::Top)
-                    .draw(display)
-                    .map_err(|_| anyhow::anyhow!("Text draw error"))?;
-            }
+            let y_pos = (i as u32 * line_height) as i32;
+            Text::with_baseline(line, Point::new(0, y_pos), font.style(), Baseline::Top)
+                .draw(display)
+                .map_err(|_| anyhow::anyhow!("Text draw error"))?;
         }
         Ok(())
     }
@@ -230,11 +432,709 @@ impl OledDisplay {
 
     }
 
+    /// Show the AP's IP address and the number of open WebSocket sessions in
+    /// the status bar at the bottom of the screen
+    /// Only the status bar rows are redrawn, the message above is kept. If a
+    /// message is being drawn right now, it picks up the new status instead.
+    pub fn update_status_bar(&self, ip: &str, session_count: usize) -> Result<()> {
+        *self.status.lock().unwrap() = Some(status_bar_text(ip, session_count));
+
+        // Don't wait out a scrolling message on the caller's task
+        let Ok(mut display_guard) = self.display.try_lock() else {
+            debug!("Display busy, status bar drawn with the next frame");
+            return Ok(());
+        };
+        match display_guard.as_mut().ok_or_else(display_lost)? {
+            DisplayType::Size128x64(display) => self.redraw_overlays(display)?,
+            DisplayType::Size72x40(display) => self.redraw_overlays(display)?,
+        }
+        debug!("Status bar updated: {} with {} sessions", ip, session_count);
+        Ok(())
+    }
+
+    /// Show `value` out of `max` as a 2 pixel tall bar across the full width,
+    /// starting at row `row_y`
+    /// Only the bar's rows are redrawn, the message above is kept. Like the
+    /// status bar, it stays on screen over the following messages.
+    #[cfg(feature = "game")]
+    pub fn draw_progress_bar(&self, value: u8, max: u8, row_y: i32) -> Result<()> {
+        *self.progress.lock().unwrap() = Some(ProgressBar { value, max, row_y });
+
+        let Ok(mut display_guard) = self.display.try_lock() else {
+            debug!("Display busy, progress bar drawn with the next frame");
+            return Ok(());
+        };
+        match display_guard.as_mut().ok_or_else(display_lost)? {
+            DisplayType::Size128x64(display) => self.redraw_overlays(display)?,
+            DisplayType::Size72x40(display) => self.redraw_overlays(display)?,
+        }
+        debug!("Progress bar updated: {} of {}", value, max);
+        Ok(())
+    }
+
+    /// Draw the status and progress bars over the last flushed frame
+    fn redraw_overlays<SIZE: DisplaySize>(
+        &self,
+        display: &mut Ssd1306Display<SIZE>,
+    ) -> Result<()> {
+        let shadow = self.shadow.lock().unwrap().clone();
+        let mut frame = shadow.unwrap_or_else(|| Frame::new(display.size()));
+        self.draw_overlays(&mut frame)?;
+        self.present(display, frame)
+    }
+
+    /// Draw the status bar and then the progress bar into `frame`, if set
+    fn draw_overlays(&self, frame: &mut Frame) -> Result<()> {
+        if let Some(text) = self.status.lock().unwrap().as_deref() {
+            draw_status_bar(frame, text)?;
+        }
+        if let Some(progress) = *self.progress.lock().unwrap() {
+            progress.draw(frame)?;
+        }
+        Ok(())
+    }
+
     /// Display a welcome message
     /// This can be called to refresh the welcome message if needed
     pub fn display_welcome(&self) -> Result<()> {
         // The initial message is already shown during init, but we can update it
         self.display_message("Server Ready Waiting...")
     }
+
+    /// Display a QR code phones can scan to join the access point
+    /// `password` is the provisioned game password of the AP
+    pub fn display_qr_wifi(&self, ssid: &str, password: &str) -> Result<()> {
+        let payload = wifi_qr_payload(ssid, password);
+        let segments = QrSegment::make_segments(&payload);
+        let qr = QrCode::encode_segments_advanced(
+            &segments,
+            QrCodeEcc::Low,
+            Version::MIN,
+            Version::new(MAX_QR_VERSION),
+            None,
+            true,
+        )
+        .map_err(|e| anyhow::anyhow!("Wi-Fi credentials too long for a QR code: {:?}", e))?;
+
+        let mut display_guard = self.display.lock().unwrap();
+        match display_guard.as_mut().ok_or_else(display_lost)? {
+            DisplayType::Size128x64(display) => self.draw_qr(display, &qr)?,
+            DisplayType::Size72x40(display) => self.draw_qr(display, &qr)?,
+        }
+
+        info!("Displayed Wi-Fi QR code ({}x{} modules)", qr.size(), qr.size());
+        Ok(())
+    }
+
+    /// Display a 1 bit per pixel bitmap in row-major order, MSB first,
+    /// centered on the screen
+    /// `width` must be a multiple of 8, as each row starts on a new byte
+    pub fn display_raw_bitmap(&self, data: &[u8], width: u8, height: u8) -> Result<()> {
+        check_bitmap_len(data.len(), width, height)?;
+        let mut display_guard = self.display.lock().unwrap();
+        match display_guard.as_mut().ok_or_else(display_lost)? {
+            DisplayType::Size128x64(display) => self.draw_raw_bitmap(display, data, width)?,
+            DisplayType::Size72x40(display) => self.draw_raw_bitmap(display, data, width)?,
+        }
+        info!("Displayed {}x{} bitmap", width, height);
+        Ok(())
+    }
+
+    fn draw_raw_bitmap<SIZE: DisplaySize>(
+        &self,
+        display: &mut Ssd1306Display<SIZE>,
+        data: &[u8],
+        width: u8,
+    ) -> Result<()> {
+        let mut frame = Frame::new(display.size());
+        draw_bitmap(&mut frame, data, width)?;
+        self.present(display, frame)
+    }
+
+    /// Draw dark modules on a lit background, centered and scaled to fit
+    fn draw_qr<SIZE: DisplaySize>(
+        &self,
+        display: &mut Ssd1306Display<SIZE>,
+        qr: &QrCode,
+    ) -> Result<()> {
+        let modules = qr.size() as u32;
+        let size = display.size();
+        let (scale, x_offset, y_offset) = qr_layout(modules, size.width, size.height)
+            .ok_or_else(|| anyhow::anyhow!("QR code with {} modules does not fit the display", modules))?;
+
+        // Scanners expect dark modules on a light background, so light up
+        // the whole screen and leave the modules off
+        let mut frame = Frame::new(display.size());
+        frame.clear(BinaryColor::On).map_err(|_| anyhow::anyhow!("Clear error"))?;
+        let pixels = (0..modules as i32)
+            .flat_map(|y| (0..modules as i32).map(move |x| (x, y)))
+            .filter(|&(x, y)| qr.get_module(x, y))
+            .flat_map(|(x, y)| {
+                let scale = scale as i32;
+                (0..scale * scale).map(move |i| {
+                    Point::new(
+                        x_offset + x * scale + i % scale,
+                        y_offset + y * scale + i / scale,
+                    )
+                })
+            })
+            .map(|point| Pixel(point, BinaryColor::Off));
+        frame.draw_iter(pixels).map_err(|_| anyhow::anyhow!("QR draw error"))?;
+        self.present(display, frame)
+    }
+
+    /// Write the pixels of `frame` that differ from the last flushed frame
+    /// and flush them
+    /// The driver only sends the area it saw `set_pixel` calls for, so every
+    /// run of adjacent changed 8 pixel rows is flushed on its own: a status
+    /// bar update then only writes the status bar's rows, even if a line at
+    /// the top changed too, instead of every row in between
+    fn present<SIZE: DisplaySize>(
+        &self,
+        display: &mut Ssd1306Display<SIZE>,
+        frame: Frame,
+    ) -> Result<()> {
+        let mut shadow = self.shadow.lock().unwrap();
+        let dirty_rows = frame.dirty_rows(shadow.as_ref());
+        let row_ranges = dirty_row_ranges(&dirty_rows);
+        if row_ranges.is_empty() {
+            debug!("Frame unchanged, skipping flush");
+            return Ok(());
+        }
+        let start_us = now_us();
+        for rows in &row_ranges {
+            for (x, y, on) in frame
+                .changed_pixels(shadow.as_ref())
+                .filter(|&(_, y, _)| rows.contains(&(y / 8)))
+            {
+                display.set_pixel(x, y, on);
+            }
+            display.flush().map_err(|e| anyhow::anyhow!("Flush error: {:?}", e))?;
+        }
+        let elapsed_us = now_us().saturating_sub(start_us);
+        let flushed_rows = row_ranges.iter().map(|rows| rows.len()).sum::<usize>();
+        let mut full_flush_us = self.full_flush_us.lock().unwrap();
+        if flushed_rows == dirty_rows.len() {
+            *full_flush_us = Some(elapsed_us);
+            debug!("Flushed all {} rows in {} us", flushed_rows, elapsed_us);
+        } else {
+            debug!(
+                "Flushed {} of {} rows in {} us, all rows took {} us",
+                flushed_rows,
+                dirty_rows.len(),
+                elapsed_us,
+                full_flush_us.map_or("?".to_string(), |us| us.to_string())
+            );
+        }
+        *shadow = Some(frame);
+        Ok(())
+    }
+}
+
+/// Off-screen monochrome frame in the SSD1306 memory layout: one byte per
+/// column of each 8-pixel-high page, least significant bit on top
+#[derive(Clone, PartialEq, Eq)]
+struct Frame {
+    width: u32,
+    height: u32,
+    buffer: Vec<u8>,
+}
+
+impl Frame {
+    /// Blank frame with every pixel off
+    fn new(size: Size) -> Self {
+        Self {
+            width: size.width,
+            height: size.height,
+            buffer: vec![0; (size.width * size.height.div_ceil(8)) as usize],
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> bool {
+        self.buffer[((y / 8) * self.width + x) as usize] & (1 << (y % 8)) != 0
+    }
+
+    /// Pixels to write to go from `previous` to this frame
+    /// Every byte column that changed is returned whole; all pixels if there
+    /// is no previous frame or it has a different size
+    fn changed_pixels<'a>(
+        &'a self,
+        previous: Option<&'a Frame>,
+    ) -> impl Iterator<Item = (u32, u32, bool)> + 'a {
+        let previous = previous.filter(|p| p.width == self.width && p.height == self.height);
+        self.buffer
+            .iter()
+            .enumerate()
+            .filter(move |&(i, byte)| previous.map_or(true, |p| p.buffer[i] != *byte))
+            .flat_map(move |(i, _)| {
+                let x = i as u32 % self.width;
+                let page = i as u32 / self.width;
+                (page * 8..(page * 8 + 8).min(self.height)).map(move |y| (x, y, self.get(x, y)))
+            })
+    }
+
+    /// Which 8 pixel rows, the SSD1306's pages, differ from `previous`
+    /// All of them if there is no previous frame or it has a different size
+    fn dirty_rows(&self, previous: Option<&Frame>) -> Vec<bool> {
+        let width = self.width as usize;
+        match previous.filter(|p| p.width == self.width && p.height == self.height) {
+            Some(previous) => self
+                .buffer
+                .chunks(width)
+                .zip(previous.buffer.chunks(width))
+                .map(|(row, previous_row)| row != previous_row)
+                .collect(),
+            None => vec![true; self.buffer.len() / width],
+        }
+    }
+}
+
+/// Adjacent dirty rows merged into ranges, each sent with one flush
+fn dirty_row_ranges(dirty_rows: &[bool]) -> Vec<Range<u32>> {
+    let mut ranges: Vec<Range<u32>> = Vec::new();
+    for (row, _) in dirty_rows.iter().enumerate().filter(|&(_, &dirty)| dirty) {
+        let row = row as u32;
+        match ranges.last_mut() {
+            Some(range) if range.end == row => range.end += 1,
+            _ => ranges.push(row..row + 1),
+        }
+    }
+    ranges
+}
+
+impl OriginDimensions for Frame {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl DrawTarget for Frame {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (u32::try_from(point.x), u32::try_from(point.y)) else {
+                continue;
+            };
+            if x >= self.width || y >= self.height {
+                continue;
+            }
+            let index = ((y / 8) * self.width + x) as usize;
+            if color.is_on() {
+                self.buffer[index] |= 1 << (y % 8);
+            } else {
+                self.buffer[index] &= !(1 << (y % 8));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Probe the SSD1306 addresses and return the first one that ACKs
+/// A zero-byte write is just the address byte, so nothing reaches the panel
+pub fn detect_i2c_address(i2c: &mut I2cDriver) -> Option<u8> {
+    let timeout = TickType::new_millis(PROBE_TIMEOUT_MS).ticks();
+    [SSD1306_ADDRESS, SSD1306_FALLBACK_ADDRESS]
+        .into_iter()
+        .find(|&address| match i2c.write(address, &[], timeout) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("No ACK from I2C address 0x{:02x}: {:?}", address, e);
+                false
+            }
+        })
+}
+
+/// Create the I2C driver and initialize the SSD1306 on it
+fn connect(i2c: I2C0, sda: Gpio5, scl: Gpio6) -> Result<Ssd1306Display<DisplaySize72x40>> {
+    let config = I2cConfig::new().baudrate(100.kHz().into());
+    let mut i2c_driver = I2cDriver::new(i2c, sda, scl, &config)?;
+
+    let address = match detect_i2c_address(&mut i2c_driver) {
+        Some(address) => {
+            info!("Detected SSD1306 at I2C address 0x{:02x}", address);
+            address
+        }
+        None => {
+            warn!(
+                "No SSD1306 answered on I2C, trying default address 0x{:02x}",
+                SSD1306_ADDRESS
+            );
+            SSD1306_ADDRESS
+        }
+    };
+
+    info!("Creating I2C display interface...");
+    // I2CInterface::new takes (i2c, address, data_byte)
+    // data_byte is typically 0x40 for data commands
+    let interface = I2CInterface::new(i2c_driver, address, 0x40);
+
+    // Initialize for 72x40 display
+    info!("Initializing SSD1306 display (72x40, {:?})...", OLED_ROTATION);
+    let mut display = Ssd1306::new(interface, DisplaySize72x40, OLED_ROTATION)
+        .into_buffered_graphics_mode();
+
+    info!("Calling display.init()...");
+    display.init().map_err(|e| {
+        error!("Display init failed: {:?}", e);
+        error!("Check I2C wiring (SDA=GPIO5, SCL=GPIO6)");
+        anyhow::anyhow!("Display init error: {:?}", e)
+    })?;
+    Ok(display)
+}
+
+/// Free a bus whose SDA line is held low by a slave stuck mid-byte
+/// SCL is clocked `BUS_CLEAR_PULSES` times so the slave can shift out the
+/// rest of the byte, then a STOP condition resets its state machine.
+/// Returns the pins for the I2C driver once done.
+fn clear_bus(mut sda: Gpio5, mut scl: Gpio6) -> Result<(Gpio5, Gpio6)> {
+    {
+        let mut sda = PinDriver::input_output_od(&mut sda)?;
+        let mut scl = PinDriver::input_output_od(&mut scl)?;
+        sda.set_pull(Pull::Up)?;
+        scl.set_pull(Pull::Up)?;
+        sda.set_high()?;
+        for _ in 0..BUS_CLEAR_PULSES {
+            scl.set_low()?;
+            Ets::delay_us(BUS_CLEAR_HALF_PERIOD_US);
+            scl.set_high()?;
+            Ets::delay_us(BUS_CLEAR_HALF_PERIOD_US);
+        }
+
+        // STOP: SDA rises while SCL is high
+        scl.set_low()?;
+        sda.set_low()?;
+        Ets::delay_us(BUS_CLEAR_HALF_PERIOD_US);
+        scl.set_high()?;
+        Ets::delay_us(BUS_CLEAR_HALF_PERIOD_US);
+        sda.set_high()?;
+        Ets::delay_us(BUS_CLEAR_HALF_PERIOD_US);
+
+        if sda.is_low() {
+            warn!("SDA still held low after clearing the I2C bus");
+        }
+    }
+    Ok((sda, scl))
+}
+
+fn display_lost() -> anyhow::Error {
+    anyhow::anyhow!("OLED display lost after a failed I2C bus recovery")
+}
+
+/// Check that `len` bytes hold a `width` x `height` bitmap with byte-aligned rows
+fn check_bitmap_len(len: usize, width: u8, height: u8) -> Result<()> {
+    let expected = (width as usize * height as usize).div_ceil(8);
+    if len != expected {
+        anyhow::bail!("{}x{} bitmap needs {} bytes, got {}", width, height, expected, len);
+    }
+    if width % 8 != 0 {
+        anyhow::bail!("Bitmap width {} is not a multiple of 8", width);
+    }
+    Ok(())
+}
+
+/// Draw a bitmap checked by `check_bitmap_len` centered on `frame`, parts
+/// outside of it are cut off
+fn draw_bitmap(frame: &mut Frame, data: &[u8], width: u8) -> Result<()> {
+    let raw = ImageRaw::<BinaryColor>::new(data, width as u32);
+    let offset = frame.size().saturating_sub(raw.size()) / 2;
+    let origin = Point::new(offset.width as i32, offset.height as i32);
+    Image::new(&raw, origin)
+        .draw(frame)
+        .map_err(|_| anyhow::anyhow!("Bitmap draw error"))?;
+    Ok(())
+}
+
+/// Draw `text` on one line, centered in the area above the status bar
+fn draw_centered(frame: &mut Frame, text: &str, font: Font) -> Result<()> {
+    let message_height = frame.height.saturating_sub(STATUS_BAR_HEIGHT);
+    let center = Point::new((frame.width / 2) as i32, (message_height / 2) as i32);
+    let text_style = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Middle)
+        .build();
+    Text::with_text_style(text, center, font.style(), text_style)
+        .draw(frame)
+        .map_err(|_| anyhow::anyhow!("Text draw error"))?;
+    Ok(())
+}
+
+/// Bar filled in proportion to `value` out of `max`
+#[derive(Clone, Copy, Debug)]
+struct ProgressBar {
+    value: u8,
+    max: u8,
+    row_y: i32,
+}
+
+impl ProgressBar {
+    /// Clear the bar's rows of `frame` and fill its share of them
+    fn draw(self, frame: &mut Frame) -> Result<()> {
+        let top_left = Point::new(0, self.row_y);
+        let filled = progress_bar_width(self.value, self.max, frame.width);
+        Rectangle::new(top_left, Size::new(frame.width, PROGRESS_BAR_HEIGHT))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+            .draw(frame)
+            .map_err(|_| anyhow::anyhow!("Progress bar clear error"))?;
+        Rectangle::new(top_left, Size::new(filled, PROGRESS_BAR_HEIGHT))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(frame)
+            .map_err(|_| anyhow::anyhow!("Progress bar draw error"))?;
+        Ok(())
+    }
+}
+
+/// Filled pixels of a `width` wide bar showing `value` out of `max`,
+/// rounded to the nearest pixel
+fn progress_bar_width(value: u8, max: u8, width: u32) -> u32 {
+    if max == 0 {
+        return 0;
+    }
+    let (value, max) = (value.min(max) as u32, max as u32);
+    (value * width + max / 2) / max
+}
+
+/// Status bar line, e.g. `192.168.71.1 3`
+fn status_bar_text(ip: &str, session_count: usize) -> String {
+    format!("{} {}", ip, session_count)
+}
+
+/// Clear the status bar rows of `frame` and draw `text` in them
+fn draw_status_bar(frame: &mut Frame, text: &str) -> Result<()> {
+    let top = frame.height.saturating_sub(STATUS_BAR_HEIGHT);
+    Rectangle::new(
+        Point::new(0, top as i32),
+        Size::new(frame.width, frame.height - top),
+    )
+    .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+    .draw(frame)
+    .map_err(|_| anyhow::anyhow!("Status bar clear error"))?;
+    // The small font fits a full IPv4 address and the count on 72 px
+    let text_style = Font::Small.style();
+    Text::with_baseline(text, Point::new(0, top as i32 + 1), text_style, Baseline::Top)
+        .draw(frame)
+        .map_err(|_| anyhow::anyhow!("Status bar draw error"))?;
+    Ok(())
+}
+
+/// Wi-Fi join string understood by phone cameras
+/// Special characters in the SSID and password are backslash-escaped
+fn wifi_qr_payload(ssid: &str, password: &str) -> String {
+    fn escape(value: &str) -> String {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+    format!("WIFI:S:{};T:WPA;P:{};;", escape(ssid), escape(password))
+}
+
+/// Largest whole-pixel scale fitting a QR code of `modules` on the display,
+/// and the offsets that center it
+fn qr_layout(modules: u32, width: u32, height: u32) -> Option<(u32, i32, i32)> {
+    let scale = width.min(height) / modules;
+    if scale == 0 {
+        return None;
+    }
+    let side = modules * scale;
+    Some((scale, ((width - side) / 2) as i32, ((height - side) / 2) as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wifi_qr_payload() {
+        assert_eq!(
+            wifi_qr_payload("esp32", "password123"),
+            "WIFI:S:esp32;T:WPA;P:password123;;"
+        );
+        assert_eq!(
+            wifi_qr_payload("my;net", r"a\b:c"),
+            r"WIFI:S:my\;net;T:WPA;P:a\\b\:c;;"
+        );
+    }
+
+    #[test]
+    fn test_frame_draw() {
+        let mut frame = Frame::new(Size::new(72, 40));
+        frame
+            .draw_iter([
+                Pixel(Point::new(3, 9), BinaryColor::On),
+                Pixel(Point::new(-1, 0), BinaryColor::On),
+                Pixel(Point::new(72, 0), BinaryColor::On),
+            ])
+            .unwrap();
+        assert!(frame.get(3, 9));
+        assert!(!frame.get(3, 8));
+        assert_eq!(frame.buffer.iter().filter(|&&b| b != 0).count(), 1);
+    }
+
+    #[test]
+    fn test_frame_changed_pixels() {
+        let blank = Frame::new(Size::new(72, 40));
+        // Without a previous frame everything is written
+        assert_eq!(blank.changed_pixels(None).count(), 72 * 40);
+        assert_eq!(blank.changed_pixels(Some(&blank)).count(), 0);
+
+        let mut frame = blank.clone();
+        frame.draw_iter([Pixel(Point::new(5, 12), BinaryColor::On)]).unwrap();
+        let changed: Vec<_> = frame.changed_pixels(Some(&blank)).collect();
+        // The whole byte column of page 1 at x = 5
+        assert_eq!(changed.len(), 8);
+        assert!(changed.iter().all(|&(x, y, _)| x == 5 && (8..16).contains(&y)));
+        assert!(changed.contains(&(5, 12, true)));
+        assert!(changed.contains(&(5, 13, false)));
+
+        // A different size forces a full redraw
+        let other = Frame::new(Size::new(128, 64));
+        assert_eq!(frame.changed_pixels(Some(&other)).count(), 72 * 40);
+    }
+
+    #[test]
+    fn test_frame_dirty_rows() {
+        let blank = Frame::new(Size::new(72, 40));
+        assert_eq!(blank.dirty_rows(None), [true; 5]);
+        assert_eq!(blank.dirty_rows(Some(&blank)), [false; 5]);
+
+        let mut frame = blank.clone();
+        frame
+            .draw_iter([
+                Pixel(Point::new(0, 2), BinaryColor::On),
+                Pixel(Point::new(71, 39), BinaryColor::On),
+            ])
+            .unwrap();
+        assert_eq!(frame.dirty_rows(Some(&blank)), [true, false, false, false, true]);
+        let other = Frame::new(Size::new(128, 64));
+        assert_eq!(frame.dirty_rows(Some(&other)), [true; 5]);
+    }
+
+    #[test]
+    fn test_dirty_row_ranges() {
+        assert_eq!(dirty_row_ranges(&[false; 5]), []);
+        assert_eq!(dirty_row_ranges(&[true; 5]), vec![0..5]);
+        assert_eq!(
+            dirty_row_ranges(&[true, false, false, true, true]),
+            [0..1, 3..5]
+        );
+    }
+
+    #[test]
+    fn test_status_bar_keeps_message_area() {
+        assert_eq!(status_bar_text("192.168.71.1", 3), "192.168.71.1 3");
+
+        let mut frame = Frame::new(Size::new(72, 40));
+        frame.clear(BinaryColor::On).unwrap();
+        draw_status_bar(&mut frame, &status_bar_text("192.168.71.1", 3)).unwrap();
+        let top = 40 - STATUS_BAR_HEIGHT;
+        assert!((0..72).all(|x| (0..top).all(|y| frame.get(x, y))));
+        // Cleared behind the text, which doesn't touch the top row of the bar
+        assert!((0..72).all(|x| !frame.get(x, top)));
+        assert!((0..72).any(|x| (top..40).any(|y| frame.get(x, y))));
+    }
+
+    #[test]
+    fn test_font_text_grid() {
+        // The message area of the 72x40 display above the status bar
+        assert_eq!(Font::Small.text_grid(72, 30), (14, 3));
+        assert_eq!(Font::Medium.text_grid(72, 30), (12, 3));
+        assert_eq!(Font::Large.text_grid(72, 30), (8, 1));
+        assert_eq!(Font::default(), Font::Medium);
+    }
+
+    #[test]
+    fn test_parse_rotation() {
+        use crate::config::parse_rotation;
+        assert!(matches!(parse_rotation("0"), DisplayRotation::Rotate0));
+        assert!(matches!(parse_rotation("90"), DisplayRotation::Rotate90));
+        assert!(matches!(parse_rotation("180"), DisplayRotation::Rotate180));
+        assert!(matches!(parse_rotation("270"), DisplayRotation::Rotate270));
+        // Sideways, text is laid out on the 40x72 display
+        assert_eq!(Font::Medium.text_grid(40, 72 - STATUS_BAR_HEIGHT), (6, 6));
+    }
+
+    #[test]
+    fn test_check_bitmap_len() {
+        assert!(check_bitmap_len(360, 72, 40).is_ok());
+        assert!(check_bitmap_len(359, 72, 40).is_err());
+        // Rows of a 12 px wide bitmap wouldn't start on a byte
+        assert!(check_bitmap_len(3, 12, 2).is_err());
+    }
+
+    #[test]
+    fn test_draw_bitmap_centered() {
+        let mut frame = Frame::new(Size::new(72, 40));
+        draw_bitmap(&mut frame, &[0b1000_0000, 0b0000_0001], 8).unwrap();
+        assert!(frame.get(32, 19));
+        assert!(frame.get(39, 20));
+        assert!(!frame.get(33, 19));
+        assert_eq!(frame.buffer.iter().map(|b| b.count_ones()).sum::<u32>(), 2);
+    }
+
+    #[test]
+    fn test_draw_centered() {
+        let mut frame = Frame::new(Size::new(72, 40));
+        draw_centered(&mut frame, "42", Font::Large).unwrap();
+        let lit: Vec<(u32, u32)> = (0..72)
+            .flat_map(|x| (0..40).map(move |y| (x, y)))
+            .filter(|&(x, y)| frame.get(x, y))
+            .collect();
+        let left = lit.iter().map(|p| p.0).min().unwrap();
+        let right = lit.iter().map(|p| p.0).max().unwrap();
+        let top = lit.iter().map(|p| p.1).min().unwrap();
+        let bottom = lit.iter().map(|p| p.1).max().unwrap();
+        // Two 9 px glyphs around x = 36, clear of the status bar
+        assert!(left >= 27 && right < 45);
+        assert!(left.abs_diff(71 - right) <= 2);
+        assert!(bottom < 40 - STATUS_BAR_HEIGHT);
+        assert!(top.abs_diff(29 - bottom) <= 3);
+    }
+
+    #[test]
+    fn test_progress_bar_width() {
+        assert_eq!(progress_bar_width(0, 10, 72), 0);
+        assert_eq!(progress_bar_width(5, 10, 72), 36);
+        assert_eq!(progress_bar_width(10, 10, 72), 72);
+        // 72 / 7 = 10.29 rounds down, 2 * 72 / 7 = 20.57 rounds up
+        assert_eq!(progress_bar_width(1, 7, 72), 10);
+        assert_eq!(progress_bar_width(2, 7, 72), 21);
+        // Half a pixel rounds up
+        assert_eq!(progress_bar_width(1, 2, 71), 36);
+        assert_eq!(progress_bar_width(12, 10, 72), 72);
+        assert_eq!(progress_bar_width(3, 0, 72), 0);
+    }
+
+    #[test]
+    fn test_progress_bar_keeps_rows_above() {
+        let mut frame = Frame::new(Size::new(72, 40));
+        frame.clear(BinaryColor::On).unwrap();
+        let bar = ProgressBar {
+            value: 1,
+            max: 2,
+            row_y: 38,
+        };
+        bar.draw(&mut frame).unwrap();
+        assert!((0..72).all(|x| (0..38).all(|y| frame.get(x, y))));
+        assert!((0..36).all(|x| frame.get(x, 38) && frame.get(x, 39)));
+        assert!((36..72).all(|x| !frame.get(x, 38) && !frame.get(x, 39)));
+    }
+
+    #[test]
+    fn test_qr_layout() {
+        // Version 5 on the 72x40 display: one pixel per module, centered
+        assert_eq!(qr_layout(37, 72, 40), Some((1, 17, 1)));
+        // Version 1 on the 128x64 display: scaled up three times
+        assert_eq!(qr_layout(21, 128, 64), Some((3, 32, 0)));
+        assert_eq!(qr_layout(41, 72, 40), None);
+    }
 }
 
@@ -0,0 +1,79 @@
+//! Per-IP fixed-window rate limiting for HTTP endpoints
+
+use log::*;
+use std::collections::BTreeMap;
+
+use crate::utils::now_ms;
+
+// Forget expired clients once the table grows beyond this many entries
+const PRUNE_THRESHOLD: usize = 16;
+
+/// Tracks request counts per client IPv4 address
+#[derive(Default)]
+pub struct RateLimiter {
+    // IP -> (hit count, window start in ms since boot)
+    clients: BTreeMap<[u8; 4], (u32, u64)>,
+}
+
+impl RateLimiter {
+    /// Count a request from `ip` and return whether it is within `limit`
+    /// requests per `window_ms`
+    pub fn check(&mut self, ip: [u8; 4], limit: u32, window_ms: u64) -> bool {
+        self.check_at(ip, limit, window_ms, now_ms())
+    }
+
+    fn check_at(&mut self, ip: [u8; 4], limit: u32, window_ms: u64, now: u64) -> bool {
+        if self.clients.len() > PRUNE_THRESHOLD {
+            self.clients
+                .retain(|_, (_, start)| now.saturating_sub(*start) < window_ms);
+        }
+
+        let (hits, start) = self.clients.entry(ip).or_insert((0, now));
+        if now.saturating_sub(*start) >= window_ms {
+            *hits = 0;
+            *start = now;
+        }
+
+        *hits = hits.saturating_add(1);
+        if *hits > limit {
+            debug!(
+                "Rate limit exceeded for {}.{}.{}.{} ({} hits in window)",
+                ip[0], ip[1], ip[2], ip[3], hits
+            );
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: [u8; 4] = [192, 168, 71, 2];
+
+    #[test]
+    fn test_allows_up_to_limit() {
+        let mut limiter = RateLimiter::default();
+        for _ in 0..3 {
+            assert!(limiter.check_at(IP, 3, 1000, 0));
+        }
+        assert!(!limiter.check_at(IP, 3, 1000, 500));
+    }
+
+    #[test]
+    fn test_window_resets() {
+        let mut limiter = RateLimiter::default();
+        assert!(limiter.check_at(IP, 1, 1000, 0));
+        assert!(!limiter.check_at(IP, 1, 1000, 999));
+        assert!(limiter.check_at(IP, 1, 1000, 1000));
+    }
+
+    #[test]
+    fn test_clients_are_independent() {
+        let mut limiter = RateLimiter::default();
+        assert!(limiter.check_at(IP, 1, 1000, 0));
+        assert!(limiter.check_at([192, 168, 71, 3], 1, 1000, 0));
+        assert!(!limiter.check_at(IP, 1, 1000, 0));
+    }
+}
@@ -0,0 +1,240 @@
+//! Log of the most recent HTTP requests, served on GET /admin/log
+//!
+//! Every handler is registered through `logged`, which records the method,
+//! path and response status once the handler has returned. It also gives
+//! each request an ID, sent back in the `X-Request-ID` header and error
+//! bodies and logged with the status, and starts the timer behind the
+//! `X-Response-Time` header.
+
+use embedded_svc::http::Method;
+use esp_idf_svc::{
+    http::server::{EspHttpConnection, Request},
+    sys::EspError,
+};
+use log::*;
+use std::sync::{
+    atomic::{AtomicU16, Ordering},
+    Mutex,
+};
+
+use crate::config::REQUEST_LOG_LEN;
+use crate::server::{TimedRequest, WatchdogCheckpoint};
+use crate::utils::{generate_request_id, json_escape, now_ms};
+
+// Longer paths are truncated
+const PATH_LEN: usize = 64;
+
+static REQUEST_LOG: Mutex<RequestLog> = Mutex::new(RequestLog::new());
+
+// Status of the response sent by the running handler, 0 if none was noted
+// Handlers run one at a time on the HTTP server task, so one slot is enough
+static RESPONSE_STATUS: AtomicU16 = AtomicU16::new(0);
+// ID of the request the running handler serves
+static REQUEST_ID: Mutex<String> = Mutex::new(String::new());
+// When the request the running handler serves came in
+static REQUEST_TIMER: Mutex<Option<TimedRequest>> = Mutex::new(None);
+
+/// A single logged request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogEntry {
+    pub method: &'static str,
+    /// Request path, zero padded
+    pub path: [u8; PATH_LEN],
+    pub status: u16,
+    /// Milliseconds since boot when the response was sent
+    pub timestamp_ms: u64,
+}
+
+impl LogEntry {
+    const EMPTY: Self = Self {
+        method: "",
+        path: [0; PATH_LEN],
+        status: 0,
+        timestamp_ms: 0,
+    };
+
+    pub fn path(&self) -> &str {
+        let len = self.path.iter().position(|&b| b == 0).unwrap_or(PATH_LEN);
+        // Only ever filled from a &str cut at a char boundary
+        core::str::from_utf8(&self.path[..len]).unwrap_or("")
+    }
+}
+
+/// Ring buffer of the last `REQUEST_LOG_LEN` requests
+pub struct RequestLog {
+    entries: [LogEntry; REQUEST_LOG_LEN],
+    // Index the next entry is written to
+    head: usize,
+    len: usize,
+}
+
+impl RequestLog {
+    pub const fn new() -> Self {
+        Self {
+            entries: [LogEntry::EMPTY; REQUEST_LOG_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Add an entry, dropping the oldest one if the buffer is full
+    pub fn push(&mut self, entry: LogEntry) {
+        self.entries[self.head] = entry;
+        self.head = (self.head + 1) % REQUEST_LOG_LEN;
+        self.len = (self.len + 1).min(REQUEST_LOG_LEN);
+    }
+
+    /// Stored entries, newest first
+    pub fn iter(&self) -> impl Iterator<Item = &LogEntry> {
+        (1..=self.len)
+            .map(move |i| &self.entries[(self.head + REQUEST_LOG_LEN - i) % REQUEST_LOG_LEN])
+    }
+
+    /// Serialize the entries as a JSON array, newest first
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .iter()
+            .map(|entry| {
+                format!(
+                    r#"{{"method":"{}","path":"{}","status":{},"timestamp_ms":{}}}"#,
+                    entry.method,
+                    json_escape(entry.path()),
+                    entry.status,
+                    entry.timestamp_ms
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(","))
+    }
+}
+
+/// Request log as a JSON array, newest first
+pub fn to_json() -> String {
+    REQUEST_LOG.lock().unwrap().to_json()
+}
+
+/// Note the status of the response being sent
+/// Only needed for responses other than 200, which `logged` assumes when
+/// the handler succeeds
+pub fn set_status(status: u16) {
+    RESPONSE_STATUS.store(status, Ordering::Relaxed);
+}
+
+/// ID of the request being served, empty outside of `logged` handlers
+pub fn request_id() -> String {
+    REQUEST_ID.lock().unwrap().clone()
+}
+
+/// Timer of the request being served, `None` outside of `logged` handlers
+pub fn timer() -> Option<TimedRequest> {
+    *REQUEST_TIMER.lock().unwrap()
+}
+
+/// Wrap a handler so each request it serves is added to the request log
+pub fn logged<F>(
+    handler: F,
+) -> impl for<'r> Fn(Request<&mut EspHttpConnection<'r>>) -> Result<(), EspError> + Send + 'static
+where
+    F: for<'r> Fn(Request<&mut EspHttpConnection<'r>>) -> Result<(), EspError> + Send + 'static,
+{
+    move |req| {
+        let _watchdog = WatchdogCheckpoint::start();
+        *REQUEST_TIMER.lock().unwrap() = Some(TimedRequest::start());
+        let method = method_name(req.method());
+        let path = truncated_path(req.uri());
+        RESPONSE_STATUS.store(0, Ordering::Relaxed);
+        let request_id = generate_request_id();
+        REQUEST_ID.lock().unwrap().clone_from(&request_id);
+
+        let result = handler(req);
+
+        let status = match (RESPONSE_STATUS.swap(0, Ordering::Relaxed), &result) {
+            (0, Ok(())) => 200,
+            (0, Err(_)) => 500,
+            (status, _) => status,
+        };
+        let entry = LogEntry {
+            method,
+            path,
+            status,
+            timestamp_ms: now_ms(),
+        };
+        info!("{} {} {} [{}]", method, entry.path(), status, request_id);
+        REQUEST_LOG.lock().unwrap().push(entry);
+        REQUEST_ID.lock().unwrap().clear();
+        *REQUEST_TIMER.lock().unwrap() = None;
+        result
+    }
+}
+
+fn method_name(method: Method) -> &'static str {
+    match method {
+        Method::Get => "GET",
+        Method::Post => "POST",
+        Method::Put => "PUT",
+        Method::Delete => "DELETE",
+        Method::Head => "HEAD",
+        Method::Options => "OPTIONS",
+        _ => "OTHER",
+    }
+}
+
+fn truncated_path(uri: &str) -> [u8; PATH_LEN] {
+    let mut end = uri.len().min(PATH_LEN);
+    while !uri.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut path = [0; PATH_LEN];
+    path[..end].copy_from_slice(&uri.as_bytes()[..end]);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, timestamp_ms: u64) -> LogEntry {
+        LogEntry {
+            method: "GET",
+            path: truncated_path(path),
+            status: 200,
+            timestamp_ms,
+        }
+    }
+
+    #[test]
+    fn test_newest_first_and_wraps() {
+        let mut log = RequestLog::new();
+        assert_eq!(log.to_json(), "[]");
+        for t in 0..REQUEST_LOG_LEN as u64 + 3 {
+            log.push(entry("/health", t));
+        }
+        let timestamps: Vec<u64> = log.iter().map(|e| e.timestamp_ms).collect();
+        assert_eq!(timestamps.len(), REQUEST_LOG_LEN);
+        assert_eq!(timestamps[0], REQUEST_LOG_LEN as u64 + 2);
+        assert_eq!(timestamps[REQUEST_LOG_LEN - 1], 3);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut log = RequestLog::new();
+        log.push(entry("/rssi", 10));
+        log.push(LogEntry {
+            method: "POST",
+            status: 401,
+            ..entry("/ota", 20)
+        });
+        assert_eq!(
+            log.to_json(),
+            r#"[{"method":"POST","path":"/ota","status":401,"timestamp_ms":20},{"method":"GET","path":"/rssi","status":200,"timestamp_ms":10}]"#
+        );
+    }
+
+    #[test]
+    fn test_long_path_truncated() {
+        let long = format!("/{}", "é".repeat(PATH_LEN));
+        let entry = entry(&long, 0);
+        assert!(entry.path().len() <= PATH_LEN);
+        assert!(long.starts_with(entry.path()));
+    }
+}
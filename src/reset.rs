@@ -0,0 +1,125 @@
+//! Reset to factory defaults for POST /reset
+//!
+//! Either removes the entries this firmware keeps in NVS, namespace by
+//! namespace, or erases the whole default NVS partition, which also drops
+//! what ESP-IDF itself stores there (e.g. the Wi-Fi driver's settings).
+
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    sys::{self, EspError},
+};
+use log::*;
+
+use crate::{rssi, server};
+#[cfg(feature = "game")]
+use crate::{leaderboard, session};
+
+/// Namespace and key of every entry the firmware stores in NVS
+const STORED_ENTRIES: &[(&str, &str)] = &[
+    (server::NVS_NAMESPACE, server::NVS_KEY),
+    (rssi::NVS_NAMESPACE, rssi::NVS_KEY),
+    #[cfg(feature = "game")]
+    (session::NVS_NAMESPACE, session::NVS_KEY),
+    #[cfg(feature = "game")]
+    (leaderboard::NVS_NAMESPACE, leaderboard::NVS_KEY),
+];
+
+/// What a reset erases
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetScope {
+    /// Remove the firmware's keys from their namespaces
+    Namespace,
+    /// Erase the entire default NVS partition
+    Partition,
+}
+
+impl ResetScope {
+    /// Parse the `scope` of a POST /reset body, `Namespace` if absent
+    pub fn from_name(name: Option<&str>) -> Result<Self, &'static str> {
+        match name {
+            None | Some("namespace") => Ok(Self::Namespace),
+            Some("partition") => Ok(Self::Partition),
+            Some(_) => Err("scope must be \"namespace\" or \"partition\""),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Namespace => "namespace",
+            Self::Partition => "partition",
+        }
+    }
+}
+
+/// Erase the stored settings and games, returning the cleared namespaces
+/// Everything stays in memory until the reboot that should follow, and a
+/// partition erase leaves NVS unusable until then.
+pub fn erase(
+    partition: EspDefaultNvsPartition,
+    scope: ResetScope,
+) -> Result<Vec<&'static str>, EspError> {
+    match scope {
+        ResetScope::Namespace => {
+            for &(namespace, key) in STORED_ENTRIES {
+                let mut nvs = EspNvs::new(partition.clone(), namespace, true)?;
+                nvs.remove(key)?;
+                warn!("Erased NVS entry {}/{}", namespace, key);
+            }
+        }
+        ResetScope::Partition => {
+            // SAFETY: ESP-IDF deinitializes the partition before erasing it
+            EspError::convert(unsafe { sys::nvs_flash_erase() })?;
+            warn!("Erased the default NVS partition");
+        }
+    }
+    Ok(cleared_namespaces())
+}
+
+fn cleared_namespaces() -> Vec<&'static str> {
+    STORED_ENTRIES
+        .iter()
+        .map(|&(namespace, _)| namespace)
+        .collect()
+}
+
+/// JSON body of the POST /reset response
+pub fn to_json(scope: ResetScope, cleared: &[&str]) -> String {
+    let cleared: Vec<String> = cleared.iter().map(|ns| format!("\"{}\"", ns)).collect();
+    format!(
+        r#"{{"scope":"{}","cleared":[{}]}}"#,
+        scope.as_str(),
+        cleared.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_from_name() {
+        assert_eq!(ResetScope::from_name(None), Ok(ResetScope::Namespace));
+        assert_eq!(
+            ResetScope::from_name(Some("namespace")),
+            Ok(ResetScope::Namespace)
+        );
+        assert_eq!(
+            ResetScope::from_name(Some("partition")),
+            Ok(ResetScope::Partition)
+        );
+        assert!(ResetScope::from_name(Some("all")).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "game")]
+    fn test_to_json() {
+        assert_eq!(
+            to_json(ResetScope::Partition, &cleared_namespaces()),
+            r#"{"scope":"partition","cleared":["provisioning","rssi","sessions","leaderboard"]}"#
+        );
+        assert_eq!(
+            to_json(ResetScope::Namespace, &[]),
+            r#"{"scope":"namespace","cleared":[]}"#
+        );
+    }
+}
@@ -1,6 +1,498 @@
 //! RSSI (Received Signal Strength Indicator) and distance calculation
 
+use anyhow::Result;
+use esp_idf_svc::hal::delay::FreeRtos;
+use esp_idf_svc::http::server::ws::EspHttpWsDetachedSender;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
 use log::*;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::config::{
+    DISTANCE_EMA_ALPHA, ESP_RETRY_ATTEMPTS, KALMAN_INITIAL_ERROR, KALMAN_MEASUREMENT_NOISE,
+    KALMAN_PROCESS_NOISE, MAX_HISTORY_STATIONS, RSSI_HISTORY_LEN, RSSI_POLL_INTERVAL_MS,
+};
+use crate::state::SharedState;
+use crate::utils::{
+    check_crc32, extract_json_f32, now_ms, parse_mac_address, retry, write_crc32, CRC32_LEN,
+};
+use crate::ws_utils::broadcast;
+
+// Path loss exponent:
+//   2.0 = free space (no obstacles)
+//   2.5-3.0 = indoor with few walls
+//   3.0-4.0 = indoor with many walls/obstacles (typical home/office)
+//   4.0+ = heavy obstacles (concrete walls, multiple floors)
+// Using 3.5 for realistic indoor environment with walls
+const DEFAULT_PATH_LOSS_EXPONENT: f32 = 3.5;
+// RSSI at 1m is typically -30 to -40 dBm for ESP32-C3
+// Used until POST /rssi/calibrate measures the real value
+const DEFAULT_RSSI_AT_1M: f32 = -35.0;
+// Accepted ranges for POST /config/rssi
+const PATH_LOSS_EXPONENT_RANGE: core::ops::RangeInclusive<f32> = 1.5..=6.0;
+const RSSI_AT_1M_RANGE: core::ops::RangeInclusive<f32> = -80.0..=-20.0;
+
+pub const NVS_NAMESPACE: &str = "rssi";
+pub const NVS_KEY: &str = "calibration";
+// Layout: CRC-32 of the rest (u32 LE), version (1 byte), rssi_at_1m (f32 LE),
+// path_loss_exponent (f32 LE)
+const FORMAT_VERSION: u8 = 2;
+const BLOB_LEN: usize = CRC32_LEN + 9;
+
+// Proximity zone boundaries in meters: Near below the first, Far above the second
+const NEAR_ZONE_MAX_M: f32 = 1.0;
+const MEDIUM_ZONE_MAX_M: f32 = 5.0;
+// Signal quality boundaries in dBm: a reading above the first is Excellent,
+// down to the second Good, down to the third Fair and below it Poor
+const EXCELLENT_SIGNAL_MIN_DBM: i8 = -50;
+const GOOD_SIGNAL_MIN_DBM: i8 = -60;
+const FAIR_SIGNAL_MIN_DBM: i8 = -70;
+// At or below the noise floor nothing is really received
+const NOISE_FLOOR_DBM: i8 = -100;
+// Practical RSSI range mapped to 0-100% by `to_percentage`
+const PERCENTAGE_MIN_DBM: i8 = -90;
+const PERCENTAGE_MAX_DBM: i8 = -30;
+// Readings `WindowedStats` computes the mean and standard deviation over
+const STATS_WINDOW_LEN: usize = 16;
+// Consecutive readings that must agree before a zone change is reported
+const ZONE_CONFIRM_READINGS: u32 = 2;
+const POLLER_STACK_SIZE: usize = 4096;
+// Kept short, RSSI is read from the 1 s poll loop and request handlers
+const STA_LIST_RETRY_DELAY_MS: u32 = 20;
+
+/// Path loss model parameters used by `calculate_distance_from_rssi`
+static CALIBRATION: Mutex<CalibrationState> = Mutex::new(CalibrationState::DEFAULT);
+
+/// Filter state shared by every caller of `filter_distance`, fed by the poller
+static DISTANCE_FILTER: Mutex<KalmanFilter> = Mutex::new(KalmanFilter::new(
+    KALMAN_INITIAL_ERROR,
+    KALMAN_PROCESS_NOISE,
+    KALMAN_MEASUREMENT_NOISE,
+));
+
+/// Moving average shared by every caller of `smooth_distance`, fed by the poller
+static DISTANCE_EMA: Mutex<DistanceFilter> = Mutex::new(DistanceFilter::new(DISTANCE_EMA_ALPHA));
+
+/// Recent polled readings, for the mean and spread GET /rssi reports
+static RSSI_STATS: Mutex<WindowedStats> = Mutex::new(WindowedStats::new());
+
+/// Latest reading of the polling task: RSSI, station and milliseconds since boot
+/// `None` while no station is connected
+static LATEST_READING: Mutex<Option<(i8, [u8; 6], u64)>> = Mutex::new(None);
+
+/// Last zone reported on /ws/proximity and the change waiting for confirmation
+static PROXIMITY_ZONE: Mutex<ZoneTracker> = Mutex::new(ZoneTracker::new());
+
+/// Scalar Kalman filter for smoothing a noisy, slowly changing value
+/// Models the value as constant with random drift of `process_noise` per update
+#[derive(Debug, Clone, Copy)]
+pub struct KalmanFilter {
+    /// Current estimate, NaN until the first measurement
+    estimate: f32,
+    /// Variance of the estimate
+    error_covariance: f32,
+    /// Variance added by the value drifting between updates
+    process_noise: f32,
+    /// Variance of a single measurement
+    measurement_noise: f32,
+}
+
+impl KalmanFilter {
+    pub const fn new(initial_error: f32, process_noise: f32, measurement_noise: f32) -> Self {
+        Self {
+            estimate: f32::NAN,
+            error_covariance: initial_error,
+            process_noise,
+            measurement_noise,
+        }
+    }
+
+    /// Feed a measurement and return the updated estimate
+    pub fn update(&mut self, measurement: f32) -> f32 {
+        // Start from the first measurement instead of pulling it towards 0
+        if self.estimate.is_nan() {
+            self.estimate = measurement;
+            return self.estimate;
+        }
+        // Predict
+        self.error_covariance += self.process_noise;
+        // Correct
+        let gain = self.error_covariance / (self.error_covariance + self.measurement_noise);
+        self.estimate += gain * (measurement - self.estimate);
+        self.error_covariance *= 1.0 - gain;
+        self.estimate
+    }
+
+    /// Current estimate, `None` until the first measurement
+    pub fn estimate(&self) -> Option<f32> {
+        (!self.estimate.is_nan()).then_some(self.estimate)
+    }
+}
+
+/// Exponential moving average of a noisy value
+/// Cheaper than `KalmanFilter`, but follows real changes with a fixed lag
+#[derive(Debug, Clone, Copy)]
+pub struct DistanceFilter {
+    /// Weight of the newest value, 0.0-1.0; higher follows changes faster
+    pub alpha: f32,
+    /// Last output, `None` until the first value
+    pub last: Option<f32>,
+}
+
+impl DistanceFilter {
+    pub const fn new(alpha: f32) -> Self {
+        Self { alpha, last: None }
+    }
+
+    /// Feed a distance and return the smoothed one
+    /// The first value is returned as is
+    pub fn update(&mut self, raw_distance: f32) -> f32 {
+        let alpha = self.alpha.clamp(0.0, 1.0);
+        let output = match self.last {
+            Some(last) => alpha * raw_distance + (1.0 - alpha) * last,
+            None => raw_distance,
+        };
+        self.last = Some(output);
+        output
+    }
+}
+
+/// Mean and standard deviation of the last `STATS_WINDOW_LEN` RSSI readings
+/// A high standard deviation means a single reading is not to be trusted.
+#[derive(Debug, Clone, Copy)]
+pub struct WindowedStats {
+    window: [i8; STATS_WINDOW_LEN],
+    /// Index the next reading is written to
+    head: usize,
+    /// Readings in the window, up to `STATS_WINDOW_LEN`
+    count: usize,
+}
+
+impl WindowedStats {
+    pub const fn new() -> Self {
+        Self {
+            window: [0; STATS_WINDOW_LEN],
+            head: 0,
+            count: 0,
+        }
+    }
+
+    /// Add a reading, replacing the oldest one once the window is full
+    pub fn push(&mut self, rssi: i8) {
+        self.window[self.head] = rssi;
+        self.head = (self.head + 1) % STATS_WINDOW_LEN;
+        self.count = (self.count + 1).min(STATS_WINDOW_LEN);
+    }
+
+    fn readings(&self) -> &[i8] {
+        // Until the window is full, the readings are at its start
+        &self.window[..self.count]
+    }
+
+    /// Mean in dBm, 0.0 without readings
+    pub fn mean(&self) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.readings().iter().map(|&rssi| rssi as f32).sum::<f32>() / self.count as f32
+    }
+
+    /// Population standard deviation in dB, 0.0 without readings
+    pub fn std_dev(&self) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let variance = self
+            .readings()
+            .iter()
+            .map(|&rssi| (rssi as f32 - mean).powi(2))
+            .sum::<f32>()
+            / self.count as f32;
+        variance.sqrt()
+    }
+}
+
+/// Parameters of the log-distance path loss model
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalibrationState {
+    /// Expected RSSI in dBm with the station 1 meter away
+    pub rssi_at_1m: f32,
+    /// How quickly the signal weakens with distance
+    pub path_loss_exponent: f32,
+}
+
+impl CalibrationState {
+    const DEFAULT: Self = Self {
+        rssi_at_1m: DEFAULT_RSSI_AT_1M,
+        path_loss_exponent: DEFAULT_PATH_LOSS_EXPONENT,
+    };
+
+    /// Back-calculate the RSSI at 1m from a reading taken `known_distance_m` away
+    /// RSSI = RSSI_AT_1M - 10 * N * log10(distance), solved for RSSI_AT_1M
+    pub fn calibrated(self, rssi: i8, known_distance_m: f32) -> Self {
+        Self {
+            rssi_at_1m: rssi as f32 + 10.0 * self.path_loss_exponent * known_distance_m.log10(),
+            ..self
+        }
+    }
+
+    /// Parse and validate a JSON body like
+    /// `{"path_loss_exponent":3.5,"rssi_at_1m":-35.0}`
+    /// Missing fields keep their current value
+    pub fn updated_from_json(self, body: &str) -> Result<Self, &'static str> {
+        let path_loss_exponent = match extract_json_f32(body, "path_loss_exponent") {
+            Some(Ok(n)) if PATH_LOSS_EXPONENT_RANGE.contains(&n) => n,
+            Some(_) => return Err("path_loss_exponent must be between 1.5 and 6.0"),
+            None => self.path_loss_exponent,
+        };
+        let rssi_at_1m = match extract_json_f32(body, "rssi_at_1m") {
+            Some(Ok(rssi)) if RSSI_AT_1M_RANGE.contains(&rssi) => rssi,
+            Some(_) => return Err("rssi_at_1m must be between -80.0 and -20.0"),
+            None => self.rssi_at_1m,
+        };
+        Ok(Self {
+            rssi_at_1m,
+            path_loss_exponent,
+        })
+    }
+
+    pub fn to_json(self) -> String {
+        format!(
+            r#"{{"rssi_at_1m":{:.2},"path_loss_exponent":{:.2}}}"#,
+            self.rssi_at_1m, self.path_loss_exponent
+        )
+    }
+
+    /// Like `to_json`, with the built-in defaults alongside
+    pub fn to_json_with_defaults(self) -> String {
+        format!(
+            r#"{{"rssi_at_1m":{:.2},"path_loss_exponent":{:.2},"defaults":{}}}"#,
+            self.rssi_at_1m,
+            self.path_loss_exponent,
+            Self::DEFAULT.to_json()
+        )
+    }
+
+    fn encode(self) -> [u8; BLOB_LEN] {
+        let mut buf = [0u8; BLOB_LEN];
+        let payload = &mut buf[CRC32_LEN..];
+        payload[0] = FORMAT_VERSION;
+        payload[1..5].copy_from_slice(&self.rssi_at_1m.to_le_bytes());
+        payload[5..9].copy_from_slice(&self.path_loss_exponent.to_le_bytes());
+        write_crc32(&mut buf);
+        buf
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != BLOB_LEN {
+            return None;
+        }
+        let data = check_crc32(data)?;
+        if data[0] != FORMAT_VERSION {
+            return None;
+        }
+        let state = Self {
+            rssi_at_1m: f32::from_le_bytes(data[1..5].try_into().ok()?),
+            path_loss_exponent: f32::from_le_bytes(data[5..9].try_into().ok()?),
+        };
+        (state.rssi_at_1m.is_finite() && state.path_loss_exponent > 0.0).then_some(state)
+    }
+}
+
+/// Current path loss model parameters
+pub fn calibration() -> CalibrationState {
+    *CALIBRATION.lock().unwrap()
+}
+
+/// Load the calibration stored by `calibrate`, keeping the defaults if there is none
+pub fn load_calibration(partition: EspDefaultNvsPartition) {
+    let nvs = match EspNvs::new(partition, NVS_NAMESPACE, true) {
+        Ok(nvs) => nvs,
+        Err(e) => {
+            warn!("Failed to open RSSI NVS namespace: {:?}", e);
+            return;
+        }
+    };
+    let mut buf = [0u8; BLOB_LEN];
+    match nvs.get_blob(NVS_KEY, &mut buf) {
+        Ok(Some(data)) => match CalibrationState::decode(data) {
+            Some(state) => {
+                *CALIBRATION.lock().unwrap() = state;
+                info!("Loaded RSSI calibration from NVS: {:?}", state);
+            }
+            None => warn!("RSSI calibration in NVS is corrupt, using defaults"),
+        },
+        Ok(None) => info!("No RSSI calibration stored in NVS, using defaults"),
+        Err(e) => warn!("Failed to read RSSI calibration from NVS: {:?}", e),
+    }
+}
+
+/// Calibrate the RSSI at 1m with the station placed `known_distance_m` away
+/// The result is used right away and persisted to NVS
+pub fn calibrate(
+    partition: EspDefaultNvsPartition,
+    known_distance_m: f32,
+) -> Result<CalibrationState, &'static str> {
+    if !(0.1..=200.0).contains(&known_distance_m) {
+        return Err("known_distance_m must be between 0.1 and 200");
+    }
+    let Some((rssi, _)) = get_station_rssi() else {
+        return Err("no station connected");
+    };
+
+    let state = calibration().calibrated(rssi, known_distance_m);
+    info!(
+        "Calibrated RSSI at 1m to {:.2} dBm from {} dBm at {:.2} m",
+        state.rssi_at_1m, rssi, known_distance_m
+    );
+    set_calibration(partition, state);
+    Ok(state)
+}
+
+/// Use new path loss model parameters right away and persist them to NVS
+pub fn set_calibration(partition: EspDefaultNvsPartition, state: CalibrationState) {
+    *CALIBRATION.lock().unwrap() = state;
+    match EspNvs::new(partition, NVS_NAMESPACE, true) {
+        Ok(mut nvs) => {
+            if let Err(e) = nvs.set_blob(NVS_KEY, &state.encode()) {
+                warn!("Failed to persist RSSI calibration to NVS: {:?}", e);
+            }
+        }
+        Err(e) => warn!("Failed to open RSSI NVS namespace: {:?}", e),
+    }
+}
+
+/// A single RSSI sample
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RssiReading {
+    /// Milliseconds since boot when the sample was taken
+    pub timestamp_ms: u64,
+    pub rssi: i8,
+    /// Unfiltered distance estimate in meters
+    pub distance_m: f32,
+}
+
+/// Ring buffer of the most recent RSSI readings of one station
+/// Once full, each new reading overwrites the oldest one
+struct StationHistory {
+    readings: [RssiReading; RSSI_HISTORY_LEN],
+    // Index the next reading is written to
+    head: usize,
+    len: usize,
+}
+
+impl Default for StationHistory {
+    fn default() -> Self {
+        Self {
+            readings: [RssiReading::default(); RSSI_HISTORY_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+}
+
+impl StationHistory {
+    /// Add a reading, dropping the oldest one if the buffer is full
+    fn push(&mut self, reading: RssiReading) {
+        self.readings[self.head] = reading;
+        self.head = (self.head + 1) % RSSI_HISTORY_LEN;
+        self.len = (self.len + 1).min(RSSI_HISTORY_LEN);
+    }
+
+    /// Stored readings, oldest first
+    fn iter(&self) -> impl Iterator<Item = &RssiReading> {
+        let start = (self.head + RSSI_HISTORY_LEN - self.len) % RSSI_HISTORY_LEN;
+        (0..self.len).map(move |i| &self.readings[(start + i) % RSSI_HISTORY_LEN])
+    }
+
+    /// Timestamp of the newest reading, 0 if there is none
+    fn last_timestamp_ms(&self) -> u64 {
+        self.iter().last().map_or(0, |reading| reading.timestamp_ms)
+    }
+}
+
+/// Recent RSSI readings of each station, keyed by MAC address
+/// Keeps up to `MAX_HISTORY_STATIONS` stations, forgetting the one heard
+/// from least recently to make room for a new one
+#[derive(Default)]
+pub struct RssiHistory {
+    stations: BTreeMap<[u8; 6], StationHistory>,
+    // Station of the most recent reading
+    latest: Option<[u8; 6]>,
+}
+
+impl RssiHistory {
+    /// Add a reading of station `mac`
+    pub fn push(&mut self, mac: [u8; 6], reading: RssiReading) {
+        if !self.stations.contains_key(&mac) && self.stations.len() >= MAX_HISTORY_STATIONS {
+            let stale = self
+                .stations
+                .iter()
+                .min_by_key(|(_, history)| history.last_timestamp_ms())
+                .map(|(&stale, _)| stale);
+            if let Some(stale) = stale {
+                self.stations.remove(&stale);
+            }
+        }
+        self.stations.entry(mac).or_default().push(reading);
+        self.latest = Some(mac);
+    }
+
+    /// Stored readings of station `mac`, oldest first
+    pub fn iter(&self, mac: &[u8; 6]) -> impl Iterator<Item = &RssiReading> {
+        self.stations
+            .get(mac)
+            .into_iter()
+            .flat_map(StationHistory::iter)
+    }
+
+    /// Station the most recent reading was taken from
+    pub fn latest_station(&self) -> Option<[u8; 6]> {
+        self.latest
+    }
+
+    /// Readings of all stations taken at or after `since_ms`, oldest first
+    pub fn readings_since(&self, since_ms: u64) -> Vec<([u8; 6], RssiReading)> {
+        let mut readings: Vec<([u8; 6], RssiReading)> = self
+            .stations
+            .iter()
+            .flat_map(|(&mac, history)| history.iter().map(move |&reading| (mac, reading)))
+            .filter(|(_, reading)| reading.timestamp_ms >= since_ms)
+            .collect();
+        readings.sort_by_key(|(_, reading)| reading.timestamp_ms);
+        readings
+    }
+
+    /// Serialize the readings of station `mac` as CSV with a header row,
+    /// oldest first
+    pub fn to_csv(&self, mac: &[u8; 6]) -> String {
+        let mut csv = String::from("timestamp_ms,rssi_dbm,distance_m\n");
+        for reading in self.iter(mac) {
+            csv.push_str(&format!(
+                "{},{},{:.2}\n",
+                reading.timestamp_ms, reading.rssi, reading.distance_m
+            ));
+        }
+        csv
+    }
+}
+
+/// Header row of GET /rssi/export.csv
+pub const EXPORT_CSV_HEADER: &str = "timestamp_ms,mac,rssi_dbm,distance_m\n";
+
+/// One row of GET /rssi/export.csv
+pub fn export_csv_row(mac: &[u8; 6], reading: &RssiReading) -> String {
+    format!(
+        "{},{},{},{:.2}\n",
+        reading.timestamp_ms,
+        parse_mac_address(mac),
+        reading.rssi,
+        reading.distance_m
+    )
+}
 
 /// Calculate distance from RSSI using log-distance path loss model
 /// RSSI: Received Signal Strength Indicator in dBm
@@ -8,18 +500,12 @@ use log::*;
 /// Accounts for walls and obstacles which significantly weaken signal
 pub fn calculate_distance_from_rssi(rssi: i8) -> f32 {
     // Path loss parameters for indoor environments with walls/obstacles
-    // Path loss exponent:
-    //   2.0 = free space (no obstacles)
-    //   2.5-3.0 = indoor with few walls
-    //   3.0-4.0 = indoor with many walls/obstacles (typical home/office)
-    //   4.0+ = heavy obstacles (concrete walls, multiple floors)
-    // Using 3.5 for realistic indoor environment with walls
-    const PATH_LOSS_EXPONENT: f32 = 3.5;
-
-    // Reference distance (1 meter) and reference RSSI at 1m
-    // RSSI at 1m is typically -30 to -40 dBm for ESP32-C3
+    // Reference distance (1 meter), the reference RSSI is calibrated for it
     const REFERENCE_DISTANCE: f32 = 1.0;
-    const RSSI_AT_1M: f32 = -35.0; // Typical RSSI at 1 meter distance (no obstacles)
+    let CalibrationState {
+        rssi_at_1m,
+        path_loss_exponent,
+    } = calibration();
 
     let rssi_f32 = rssi as f32;
 
@@ -30,9 +516,10 @@ pub fn calculate_distance_from_rssi(rssi: i8) -> f32 {
     // Higher path loss exponent accounts for walls reducing signal strength
 
     let distance =
-        REFERENCE_DISTANCE * 10.0_f32.powf((RSSI_AT_1M - rssi_f32) / (10.0 * PATH_LOSS_EXPONENT));
+        REFERENCE_DISTANCE * 10.0_f32.powf((rssi_at_1m - rssi_f32) / (10.0 * path_loss_exponent));
 
-    info!(
+    // Runs for every poll, so kept out of the info log
+    debug!(
         "RSSI: {} dBm, Calculated distance (before clamp): {:.2} m",
         rssi, distance
     );
@@ -43,7 +530,7 @@ pub fn calculate_distance_from_rssi(rssi: i8) -> f32 {
     let clamped_distance = distance.max(0.1).min(200.0);
 
     if clamped_distance == 200.0 && distance > 200.0 {
-        warn!(
+        debug!(
             "Distance calculated as {:.2}m, clamped to 200m. Signal very weak (possibly through many walls).",
             distance
         );
@@ -52,31 +539,708 @@ pub fn calculate_distance_from_rssi(rssi: i8) -> f32 {
     clamped_distance
 }
 
-/// Get RSSI from connected station
-/// Note: This is a simplified implementation that gets RSSI from the first connected station
-pub fn get_station_rssi() -> Option<i8> {
+/// RSSI and MAC address of every station connected to the access point
+/// Empty if none are connected or the list can't be read
+pub fn get_stations() -> Vec<(i8, [u8; 6])> {
     unsafe {
         use esp_idf_svc::sys::*;
 
         // Allocate buffer for station list
         let mut sta_list: wifi_sta_list_t = std::mem::zeroed();
-        let ret = esp_wifi_ap_get_sta_list(&mut sta_list);
+        // Fails transiently while Wi-Fi is (re)starting
+        let ret = retry(
+            || EspError::convert(esp_wifi_ap_get_sta_list(&mut sta_list)),
+            ESP_RETRY_ATTEMPTS,
+            STA_LIST_RETRY_DELAY_MS,
+        );
 
-        info!(
-            "esp_wifi_ap_get_sta_list returned: {}, num stations: {}",
+        debug!(
+            "esp_wifi_ap_get_sta_list returned: {:?}, num stations: {}",
             ret, sta_list.num
         );
 
-        if ret == ESP_OK as i32 && sta_list.num > 0 {
-            // Get RSSI from first connected station
-            // In a real scenario, you'd match the station by MAC address
-            let rssi = sta_list.sta[0].rssi;
-            info!("Station RSSI: {} dBm", rssi);
-            Some(rssi)
+        if ret.is_err() {
+            return Vec::new();
+        }
+        let num = (sta_list.num.max(0) as usize).min(sta_list.sta.len());
+        sta_list.sta[..num]
+            .iter()
+            .map(|station| (station.rssi, station.mac))
+            .collect()
+    }
+}
+
+/// Get RSSI and MAC address of the connected station
+/// Note: This is a simplified implementation that gets RSSI from the first connected station
+/// in the AP's list order, `get_nearest_station` picks the one with the strongest signal
+pub fn get_station_rssi() -> Option<(i8, [u8; 6])> {
+    // In a real scenario, you'd match the station by MAC address
+    let Some(&(rssi, mac)) = get_stations().first() else {
+        debug!("No connected stations or error getting station list");
+        return None;
+    };
+    debug!("Station {} RSSI: {} dBm", parse_mac_address(&mac), rssi);
+    Some((rssi, mac))
+}
+
+/// RSSI and MAC address of the connected station as of the last poll
+/// Cheaper than `get_station_rssi`, at most `RSSI_POLL_INTERVAL_MS` old
+pub fn latest_station_rssi() -> Option<(i8, [u8; 6])> {
+    LATEST_READING
+        .lock()
+        .unwrap()
+        .map(|(rssi, mac, _)| (rssi, mac))
+}
+
+/// Get RSSI and MAC address of the connected station with the strongest signal
+pub fn get_nearest_station() -> Option<(i8, [u8; 6])> {
+    let nearest = strongest(&get_stations());
+    if let Some((rssi, mac)) = nearest {
+        info!(
+            "Nearest station {} RSSI: {} dBm",
+            parse_mac_address(&mac),
+            rssi
+        );
+    }
+    nearest
+}
+
+/// Station with the highest (least negative) RSSI, the first one on a tie
+fn strongest(stations: &[(i8, [u8; 6])]) -> Option<(i8, [u8; 6])> {
+    stations
+        .iter()
+        .copied()
+        .reduce(|best, station| if station.0 > best.0 { station } else { best })
+}
+
+/// Serialize connected stations for GET /wifi/stations
+/// Each entry is (MAC, RSSI in dBm, estimated distance in meters)
+pub fn stations_to_json(stations: &[([u8; 6], i8, f32)]) -> String {
+    let entries: Vec<String> = stations
+        .iter()
+        .map(|(mac, rssi, distance)| {
+            format!(
+                r#"{{"mac":"{}","rssi":{},"distance_m":{:.1}}}"#,
+                parse_mac_address(mac),
+                rssi,
+                distance
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"count":{},"stations":[{}]}}"#,
+        entries.len(),
+        entries.join(",")
+    )
+}
+
+/// Smooth a distance reading with the shared Kalman filter
+pub fn filter_distance(distance: f32) -> f32 {
+    let filtered = DISTANCE_FILTER.lock().unwrap().update(distance);
+    debug!("Distance {:.2} m, filtered {:.2} m", distance, filtered);
+    filtered
+}
+
+/// Mean and standard deviation of the recent polled readings
+pub fn rssi_stats() -> (f32, f32) {
+    let stats = RSSI_STATS.lock().unwrap();
+    (stats.mean(), stats.std_dev())
+}
+
+/// Smooth a distance reading with the shared moving average
+pub fn smooth_distance(distance: f32) -> f32 {
+    DISTANCE_EMA.lock().unwrap().update(distance)
+}
+
+/// Kalman-filtered and moving average distance as of the last poll
+/// `None` before the first reading
+pub fn filtered_distances() -> Option<(f32, f32)> {
+    let filtered = DISTANCE_FILTER.lock().unwrap().estimate()?;
+    let smoothed = DISTANCE_EMA.lock().unwrap().last?;
+    Some((filtered, smoothed))
+}
+
+/// Read the station RSSI and return the distance in meters, smoothed with
+/// the shared moving average
+#[allow(dead_code)] // Available for callers that don't need the raw RSSI
+pub fn get_filtered_distance() -> Option<f32> {
+    let (rssi, _) = get_station_rssi()?;
+    Some(smooth_distance(calculate_distance_from_rssi(rssi)))
+}
+
+/// Read the station RSSI and return the Kalman-smoothed distance in meters
+#[allow(dead_code)] // Available for callers that don't need the raw RSSI
+pub fn get_station_distance_filtered() -> Option<f32> {
+    let (rssi, _) = get_station_rssi()?;
+    Some(filter_distance(calculate_distance_from_rssi(rssi)))
+}
+
+/// Rough distance band of the connected station
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProximityZone {
+    /// Closer than 1 m
+    Near,
+    /// Between 1 and 5 m
+    Medium,
+    /// Further than 5 m
+    Far,
+}
+
+impl ProximityZone {
+    pub fn from_distance(distance_m: f32) -> Self {
+        if distance_m < NEAR_ZONE_MAX_M {
+            Self::Near
+        } else if distance_m <= MEDIUM_ZONE_MAX_M {
+            Self::Medium
         } else {
-            warn!("No connected stations or error getting station list");
-            None
+            Self::Far
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Near => "Near",
+            Self::Medium => "Medium",
+            Self::Far => "Far",
         }
     }
 }
 
+/// How good a station's signal is, for people rather than in dBm
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalQuality {
+    /// Above -50 dBm
+    Excellent,
+    /// -50 to -60 dBm
+    Good,
+    /// -61 to -70 dBm
+    Fair,
+    /// Below -70 dBm
+    Poor,
+    /// Not a valid reading: 0 dBm or more, or at the noise floor
+    NoSignal,
+}
+
+impl SignalQuality {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Excellent => "Excellent",
+            Self::Good => "Good",
+            Self::Fair => "Fair",
+            Self::Poor => "Poor",
+            Self::NoSignal => "NoSignal",
+        }
+    }
+
+    /// Signal strength as 0 to 4 bars, like a phone's status bar
+    pub fn as_bars(self) -> u8 {
+        match self {
+            Self::Excellent => 4,
+            Self::Good => 3,
+            Self::Fair => 2,
+            Self::Poor => 1,
+            Self::NoSignal => 0,
+        }
+    }
+}
+
+/// Classify a station's RSSI in dBm
+pub fn classify_signal(rssi: i8) -> SignalQuality {
+    if rssi >= 0 || rssi <= NOISE_FLOOR_DBM {
+        SignalQuality::NoSignal
+    } else if rssi > EXCELLENT_SIGNAL_MIN_DBM {
+        SignalQuality::Excellent
+    } else if rssi >= GOOD_SIGNAL_MIN_DBM {
+        SignalQuality::Good
+    } else if rssi >= FAIR_SIGNAL_MIN_DBM {
+        SignalQuality::Fair
+    } else {
+        SignalQuality::Poor
+    }
+}
+
+/// Signal strength as 0 to 100%, for signal bars in the web UI
+/// -90 dBm and below is 0%, -30 dBm and above 100%, linear in between
+pub fn to_percentage(rssi: i8) -> u8 {
+    let clamped = rssi.clamp(PERCENTAGE_MIN_DBM, PERCENTAGE_MAX_DBM);
+    let span = (PERCENTAGE_MAX_DBM - PERCENTAGE_MIN_DBM) as i16;
+    ((clamped - PERCENTAGE_MIN_DBM) as i16 * 100 / span) as u8
+}
+
+/// Reading that moved the station into a new zone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProximityReport {
+    pub zone: ProximityZone,
+    pub rssi: i8,
+    pub distance_m: f32,
+}
+
+impl ProximityReport {
+    pub fn to_json(self) -> String {
+        format!(
+            "{{\"zone\":\"{}\",\"rssi\":{},\"distance\":{:.1}}}",
+            self.zone.as_str(),
+            self.rssi,
+            self.distance_m
+        )
+    }
+}
+
+/// Debounces zone changes so readings near a boundary don't flip the zone
+/// back and forth: a new zone is only reported once `ZONE_CONFIRM_READINGS`
+/// consecutive readings agree on it
+pub struct ZoneTracker {
+    reported: Option<ProximityReport>,
+    // Zone seen in the latest readings that differs from the reported one,
+    // with the number of consecutive readings in it
+    candidate: Option<(ProximityZone, u32)>,
+}
+
+impl ZoneTracker {
+    pub const fn new() -> Self {
+        Self {
+            reported: None,
+            candidate: None,
+        }
+    }
+
+    /// Last confirmed report, `None` until the first zone is confirmed
+    pub fn reported(&self) -> Option<ProximityReport> {
+        self.reported
+    }
+
+    /// Feed a reading, returns the report if it confirms a zone change
+    pub fn update(&mut self, rssi: i8, distance_m: f32) -> Option<ProximityReport> {
+        let zone = ProximityZone::from_distance(distance_m);
+        if self.reported.map(|report| report.zone) == Some(zone) {
+            self.candidate = None;
+            return None;
+        }
+
+        let readings = match self.candidate {
+            Some((candidate, readings)) if candidate == zone => readings + 1,
+            _ => 1,
+        };
+        if readings < ZONE_CONFIRM_READINGS {
+            self.candidate = Some((zone, readings));
+            return None;
+        }
+
+        self.candidate = None;
+        let report = ProximityReport {
+            zone,
+            rssi,
+            distance_m,
+        };
+        self.reported = Some(report);
+        Some(report)
+    }
+}
+
+/// Last confirmed proximity report, for clients that just subscribed
+pub fn proximity_report() -> Option<ProximityReport> {
+    PROXIMITY_ZONE.lock().unwrap().reported()
+}
+
+/// Spawn the task polling the station RSSI every `RSSI_POLL_INTERVAL_MS`
+/// Each reading is cached for `latest_station_rssi`, fed to both distance
+/// filters, added to the history in `state` and the stats window, and zone
+/// changes are pushed to the /ws/proximity subscribers
+pub fn spawn_poller(
+    state: SharedState,
+    subscribers: Arc<Mutex<BTreeMap<i32, EspHttpWsDetachedSender>>>,
+) -> Result<()> {
+    std::thread::Builder::new()
+        .name("rssi_poll".into())
+        .stack_size(POLLER_STACK_SIZE)
+        .spawn(move || loop {
+            FreeRtos::delay_ms(RSSI_POLL_INTERVAL_MS as u32);
+
+            let station = get_station_rssi();
+            let timestamp_ms = now_ms();
+            *LATEST_READING.lock().unwrap() = station.map(|(rssi, mac)| (rssi, mac, timestamp_ms));
+            let Some((rssi, mac)) = station else {
+                continue;
+            };
+            let distance = calculate_distance_from_rssi(rssi);
+            // Fed at the poll rate, not once per GET /rssi
+            filter_distance(distance);
+            smooth_distance(distance);
+            state.rssi_history().push(
+                mac,
+                RssiReading {
+                    timestamp_ms,
+                    rssi,
+                    distance_m: distance,
+                },
+            );
+            RSSI_STATS.lock().unwrap().push(rssi);
+
+            if subscribers.lock().unwrap().is_empty() {
+                continue;
+            }
+            let Some(report) = PROXIMITY_ZONE.lock().unwrap().update(rssi, distance) else {
+                continue;
+            };
+            info!(
+                "Station moved to zone {} ({} dBm, {:.2} m)",
+                report.zone.as_str(),
+                rssi,
+                distance
+            );
+
+            // Send from a snapshot, a detached send blocks until the HTTP
+            // server task delivers it and that task may need the lock
+            let mut senders: Vec<(i32, EspHttpWsDetachedSender)> = subscribers
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(session, sender)| (*session, sender.clone()))
+                .collect();
+            let failed = broadcast(&mut senders, &report.to_json());
+            let mut subscribers = subscribers.lock().unwrap();
+            for (session, _) in failed {
+                subscribers.remove(&session);
+            }
+        })?;
+
+    info!("RSSI polling every {} ms", RSSI_POLL_INTERVAL_MS);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stations_to_json() {
+        assert_eq!(stations_to_json(&[]), r#"{"count":0,"stations":[]}"#);
+        let stations = [
+            ([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff], -65, 3.24),
+            ([0, 1, 2, 3, 4, 5], -40, 0.96),
+        ];
+        assert_eq!(
+            stations_to_json(&stations),
+            r#"{"count":2,"stations":[{"mac":"AA:BB:CC:DD:EE:FF","rssi":-65,"distance_m":3.2},{"mac":"00:01:02:03:04:05","rssi":-40,"distance_m":1.0}]}"#
+        );
+    }
+
+    #[test]
+    fn test_std_dev_constant_series() {
+        let mut stats = WindowedStats::new();
+        assert_eq!(stats.std_dev(), 0.0);
+        for _ in 0..5 {
+            stats.push(-60);
+        }
+        assert_eq!(stats.mean(), -60.0);
+        assert_eq!(stats.std_dev(), 0.0);
+    }
+
+    #[test]
+    fn test_std_dev_two_readings() {
+        let mut stats = WindowedStats::new();
+        stats.push(-60);
+        stats.push(-66);
+        assert_eq!(stats.mean(), -63.0);
+        assert_eq!(stats.std_dev(), 3.0);
+    }
+
+    #[test]
+    fn test_std_dev_full_window() {
+        let mut stats = WindowedStats::new();
+        // The first readings are pushed out by the 16 that follow
+        for _ in 0..4 {
+            stats.push(-90);
+        }
+        for i in 0..STATS_WINDOW_LEN {
+            stats.push(if i % 2 == 0 { -60 } else { -70 });
+        }
+        assert_eq!(stats.mean(), -65.0);
+        assert_eq!(stats.std_dev(), 5.0);
+    }
+
+    #[test]
+    fn test_strongest_station() {
+        let a = [0xaa; 6];
+        let b = [0xbb; 6];
+        let c = [0xcc; 6];
+        assert_eq!(strongest(&[]), None);
+        assert_eq!(strongest(&[(-70, a)]), Some((-70, a)));
+        assert_eq!(strongest(&[(-70, a), (-55, b), (-60, c)]), Some((-55, b)));
+        assert_eq!(strongest(&[(-55, a), (-55, b)]), Some((-55, a)));
+    }
+
+    #[test]
+    fn test_kalman_starts_at_first_measurement() {
+        let mut filter = KalmanFilter::new(1.0, 0.05, 4.0);
+        assert_eq!(filter.update(3.0), 3.0);
+    }
+
+    #[test]
+    fn test_kalman_estimate() {
+        let mut filter = KalmanFilter::new(1.0, 0.05, 4.0);
+        assert_eq!(filter.estimate(), None);
+        let estimate = filter.update(3.0);
+        assert_eq!(filter.estimate(), Some(estimate));
+    }
+
+    #[test]
+    fn test_kalman_smooths_outlier() {
+        let mut filter = KalmanFilter::new(1.0, 0.05, 4.0);
+        for _ in 0..10 {
+            filter.update(2.0);
+        }
+        let estimate = filter.update(20.0);
+        assert!(estimate > 2.0 && estimate < 5.0, "estimate {}", estimate);
+    }
+
+    #[test]
+    fn test_kalman_converges() {
+        let mut filter = KalmanFilter::new(1.0, 0.05, 4.0);
+        filter.update(2.0);
+        let mut estimate = 0.0;
+        for _ in 0..100 {
+            estimate = filter.update(8.0);
+        }
+        assert!((estimate - 8.0).abs() < 0.1, "estimate {}", estimate);
+    }
+
+    #[test]
+    fn test_distance_filter() {
+        let mut filter = DistanceFilter::new(0.2);
+        assert_eq!(filter.update(5.0), 5.0);
+        // 0.2 * 10 + 0.8 * 5
+        assert!((filter.update(10.0) - 6.0).abs() < 1e-6);
+        // 0.2 * 10 + 0.8 * 6
+        assert!((filter.update(10.0) - 6.8).abs() < 1e-6);
+        assert_eq!(filter.update(6.8), 6.8);
+        assert_eq!(filter.last, Some(6.8));
+
+        let mut raw = DistanceFilter::new(1.0);
+        raw.update(5.0);
+        assert_eq!(raw.update(10.0), 10.0);
+        let mut frozen = DistanceFilter::new(0.0);
+        frozen.update(5.0);
+        assert_eq!(frozen.update(10.0), 5.0);
+    }
+
+    fn reading(timestamp_ms: u64) -> RssiReading {
+        RssiReading {
+            timestamp_ms,
+            rssi: -50,
+            distance_m: 1.5,
+        }
+    }
+
+    const STATION: [u8; 6] = [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff];
+
+    #[test]
+    fn test_history_oldest_first() {
+        let mut history = RssiHistory::default();
+        assert_eq!(history.iter(&STATION).count(), 0);
+        history.push(STATION, reading(1));
+        history.push(STATION, reading(2));
+        let timestamps: Vec<u64> = history.iter(&STATION).map(|r| r.timestamp_ms).collect();
+        assert_eq!(timestamps, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_history_overwrites_oldest_when_full() {
+        let mut history = RssiHistory::default();
+        for t in 0..RSSI_HISTORY_LEN as u64 + 5 {
+            history.push(STATION, reading(t));
+        }
+        let timestamps: Vec<u64> = history.iter(&STATION).map(|r| r.timestamp_ms).collect();
+        assert_eq!(timestamps.len(), RSSI_HISTORY_LEN);
+        assert_eq!(timestamps[0], 5);
+        assert_eq!(
+            timestamps[RSSI_HISTORY_LEN - 1],
+            RSSI_HISTORY_LEN as u64 + 4
+        );
+    }
+
+    #[test]
+    fn test_history_per_station() {
+        let mut history = RssiHistory::default();
+        let other = [0, 1, 2, 3, 4, 5];
+        history.push(STATION, reading(1));
+        history.push(other, reading(2));
+        history.push(STATION, reading(3));
+        assert_eq!(history.iter(&STATION).count(), 2);
+        assert_eq!(history.iter(&other).count(), 1);
+        assert_eq!(history.latest_station(), Some(STATION));
+
+        let since: Vec<(u8, u64)> = history
+            .readings_since(2)
+            .iter()
+            .map(|(mac, r)| (mac[0], r.timestamp_ms))
+            .collect();
+        assert_eq!(since, vec![(0, 2), (0xaa, 3)]);
+    }
+
+    #[test]
+    fn test_history_forgets_least_recent_station() {
+        let mut history = RssiHistory::default();
+        for i in 0..=MAX_HISTORY_STATIONS as u8 {
+            history.push([i; 6], reading(i as u64));
+        }
+        assert_eq!(history.iter(&[0; 6]).count(), 0);
+        assert_eq!(history.iter(&[1; 6]).count(), 1);
+        assert_eq!(history.readings_since(0).len(), MAX_HISTORY_STATIONS);
+    }
+
+    #[test]
+    fn test_history_csv() {
+        let mut history = RssiHistory::default();
+        assert_eq!(
+            history.to_csv(&STATION),
+            "timestamp_ms,rssi_dbm,distance_m\n"
+        );
+        history.push(STATION, reading(1000));
+        assert_eq!(
+            history.to_csv(&STATION),
+            "timestamp_ms,rssi_dbm,distance_m\n1000,-50,1.50\n"
+        );
+        assert_eq!(
+            export_csv_row(&STATION, &reading(1000)),
+            "1000,AA:BB:CC:DD:EE:FF,-50,1.50\n"
+        );
+    }
+
+    #[test]
+    fn test_calibration_back_calculates_rssi_at_1m() {
+        let state = CalibrationState::DEFAULT.calibrated(-50, 1.0);
+        assert_eq!(state.rssi_at_1m, -50.0);
+        // 10m away with exponent 3.5 loses 35 dB compared to 1m
+        let state = CalibrationState::DEFAULT.calibrated(-80, 10.0);
+        assert!((state.rssi_at_1m - -45.0).abs() < 0.001);
+        assert_eq!(state.path_loss_exponent, DEFAULT_PATH_LOSS_EXPONENT);
+    }
+
+    #[test]
+    fn test_calibration_from_json() {
+        let current = CalibrationState::DEFAULT;
+        assert_eq!(
+            current.updated_from_json(r#"{"path_loss_exponent":2.5,"rssi_at_1m":-40.0}"#),
+            Ok(CalibrationState {
+                rssi_at_1m: -40.0,
+                path_loss_exponent: 2.5,
+            })
+        );
+        assert_eq!(
+            current.updated_from_json(r#"{"rssi_at_1m": -20}"#),
+            Ok(CalibrationState {
+                rssi_at_1m: -20.0,
+                ..current
+            })
+        );
+        assert!(current
+            .updated_from_json(r#"{"path_loss_exponent":1.4}"#)
+            .is_err());
+        assert!(current
+            .updated_from_json(r#"{"path_loss_exponent":"x"}"#)
+            .is_err());
+        assert!(current.updated_from_json(r#"{"rssi_at_1m":-81}"#).is_err());
+        assert!(current.updated_from_json(r#"{"rssi_at_1m":35}"#).is_err());
+    }
+
+    #[test]
+    fn test_calibration_json_with_defaults() {
+        let state = CalibrationState::DEFAULT.calibrated(-50, 1.0);
+        assert_eq!(
+            state.to_json_with_defaults(),
+            r#"{"rssi_at_1m":-50.00,"path_loss_exponent":3.50,"defaults":{"rssi_at_1m":-35.00,"path_loss_exponent":3.50}}"#
+        );
+    }
+
+    #[test]
+    fn test_calibration_encode_decode_round_trip() {
+        let state = CalibrationState::DEFAULT.calibrated(-60, 2.0);
+        assert_eq!(CalibrationState::decode(&state.encode()), Some(state));
+        assert!(CalibrationState::decode(&[]).is_none());
+        let mut bad_version = state.encode();
+        bad_version[CRC32_LEN] = FORMAT_VERSION + 1;
+        write_crc32(&mut bad_version);
+        assert!(CalibrationState::decode(&bad_version).is_none());
+        let mut corrupt = state.encode();
+        corrupt[BLOB_LEN - 1] ^= 0x10;
+        assert!(CalibrationState::decode(&corrupt).is_none());
+    }
+
+    #[test]
+    fn test_zone_boundaries() {
+        assert_eq!(ProximityZone::from_distance(0.8), ProximityZone::Near);
+        assert_eq!(ProximityZone::from_distance(1.0), ProximityZone::Medium);
+        assert_eq!(ProximityZone::from_distance(5.0), ProximityZone::Medium);
+        assert_eq!(ProximityZone::from_distance(5.1), ProximityZone::Far);
+    }
+
+    #[test]
+    fn test_signal_quality_boundaries() {
+        assert_eq!(classify_signal(-30), SignalQuality::Excellent);
+        assert_eq!(classify_signal(-49), SignalQuality::Excellent);
+        assert_eq!(classify_signal(-50), SignalQuality::Good);
+        assert_eq!(classify_signal(-51), SignalQuality::Good);
+        assert_eq!(classify_signal(-60), SignalQuality::Good);
+        assert_eq!(classify_signal(-61), SignalQuality::Fair);
+        assert_eq!(classify_signal(-70), SignalQuality::Fair);
+        assert_eq!(classify_signal(-71), SignalQuality::Poor);
+        assert_eq!(classify_signal(-99), SignalQuality::Poor);
+        assert_eq!(classify_signal(-100), SignalQuality::NoSignal);
+        assert_eq!(classify_signal(0), SignalQuality::NoSignal);
+    }
+
+    #[test]
+    fn test_signal_quality_bars() {
+        assert_eq!(classify_signal(-49).as_bars(), 4);
+        assert_eq!(classify_signal(-50).as_bars(), 3);
+        assert_eq!(classify_signal(-65).as_bars(), 2);
+        assert_eq!(classify_signal(-80).as_bars(), 1);
+        assert_eq!(SignalQuality::NoSignal.as_bars(), 0);
+        assert_eq!(SignalQuality::Good.as_str(), "Good");
+    }
+
+    #[test]
+    fn test_to_percentage() {
+        assert_eq!(to_percentage(-30), 100);
+        assert_eq!(to_percentage(-90), 0);
+        assert_eq!(to_percentage(-60), 50);
+        assert_eq!(to_percentage(-45), 75);
+        assert_eq!(to_percentage(-100), 0);
+        assert_eq!(to_percentage(-20), 100);
+        assert_eq!(to_percentage(i8::MIN), 0);
+        assert_eq!(to_percentage(i8::MAX), 100);
+    }
+
+    #[test]
+    fn test_zone_change_needs_two_readings() {
+        let mut tracker = ZoneTracker::new();
+        assert_eq!(tracker.update(-42, 0.8), None);
+        let report = tracker.update(-42, 0.8).unwrap();
+        assert_eq!(
+            report.to_json(),
+            r#"{"zone":"Near","rssi":-42,"distance":0.8}"#
+        );
+
+        // Same zone again is not reported
+        assert_eq!(tracker.update(-40, 0.7), None);
+        assert_eq!(tracker.update(-60, 3.0), None);
+        assert_eq!(
+            tracker.update(-61, 3.2).unwrap().zone,
+            ProximityZone::Medium
+        );
+    }
+
+    #[test]
+    fn test_zone_flapping_is_ignored() {
+        let mut tracker = ZoneTracker::new();
+        tracker.update(-42, 0.8);
+        tracker.update(-42, 0.8);
+        // Alternating around the 1 m boundary never confirms Medium
+        for _ in 0..5 {
+            assert_eq!(tracker.update(-46, 1.1), None);
+            assert_eq!(tracker.update(-44, 0.9), None);
+        }
+        assert_eq!(tracker.reported().unwrap().zone, ProximityZone::Near);
+    }
+}
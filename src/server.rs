@@ -1,47 +1,477 @@
 //! HTTP server and WiFi access point setup
+//!
+//! Wi-Fi credentials are provisioned at runtime: until some are stored in
+//! NVS, the board runs an open `SETUP_SSID` access point serving
+//! POST /provision, then reboots into normal operation. Credentials set at
+//! build time skip the setup, provisioned ones take precedence over them.
 
-use crate::config::{CHANNEL, PASSWORD, SSID, STACK_SIZE};
+use crate::channel_selection::pick_least_congested_channel;
+use crate::config::{
+    BEACON_INTERVAL_MS, BUILD_CREDENTIALS, ESP_RETRY_ATTEMPTS, ESP_RETRY_DELAY_MS, HTTP_CHUNK_LEN,
+    MAC_SUFFIX_SSID, MAX_AP_STATIONS, MAX_URI_HANDLERS, MDNS_HOSTNAME, PASSWORD,
+    RATE_LIMIT_REQUESTS, RATE_LIMIT_WINDOW_MS, SETUP_SSID, SSID, STACK_SIZE, TCP_KEEPALIVE_COUNT,
+    TCP_KEEPALIVE_IDLE_S, TCP_KEEPALIVE_INTVL_S, UPSTREAM_PASS, UPSTREAM_SSID, WATCHDOG_TIMEOUT_S,
+};
+use crate::error::ServerError;
+use crate::rate_limit::RateLimiter;
+use crate::request_log;
+#[cfg(feature = "game")]
+use crate::session::WsProtocol;
+use crate::utils::{
+    check_crc32, extract_json_string, json_escape, now_us, parse_mac_address, retry, write_crc32,
+    CRC32_LEN,
+};
 use anyhow::Result;
-use embedded_svc::wifi::{self, AccessPointConfiguration, AuthMethod};
+use embedded_svc::{
+    http::server::Response,
+    io::{ErrorType, Write},
+    wifi::{self, AccessPointConfiguration, AuthMethod, ClientConfiguration},
+};
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
-    http::server::EspHttpServer,
-    nvs::EspDefaultNvsPartition,
+    handle::RawHandle,
+    http::server::{EspHttpConnection, EspHttpServer, Request},
+    io::EspIOError,
+    mdns::EspMdns,
+    netif::EspNetif,
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    sys::{self, EspError},
     wifi::{BlockingWifi, EspWifi},
 };
+#[cfg(feature = "game")]
+use esp_idf_svc::http::server::ws::EspHttpWsConnection;
 use esp_idf_svc::hal::modem::Modem;
 use log::*;
+#[cfg(feature = "game")]
+use std::ffi::{c_int, CStr};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+#[cfg(feature = "game")]
+use std::sync::Arc;
+
+/// WebSocket handler shared by `ws_handler_with_subprotocol` and the close
+/// notifications of esp-idf-svc
+#[cfg(feature = "game")]
+type WsHandler = Arc<dyn Fn(&mut EspHttpWsConnection) -> Result<(), EspError> + Send + Sync>;
+
+/// `user_ctx` of a WebSocket route registered by `ws_handler_with_subprotocol`
+#[cfg(feature = "game")]
+struct WsRoute {
+    server: sys::httpd_handle_t,
+    handler: WsHandler,
+}
+
+pub const NVS_NAMESPACE: &str = "provisioning";
+pub const NVS_KEY: &str = "credentials";
+
+// Layout: CRC-32 of the rest (u32 LE), version (1 byte), then the SSID,
+// password and game password, each as a length byte followed by the bytes
+const FORMAT_VERSION: u8 = 1;
+const BLOB_LEN: usize = CRC32_LEN + 1 + 3 + 32 + 2 * 63;
+
+/// How the Wi-Fi radio ended up being configured
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WifiMode {
+    /// Standalone access point
+    AccessPoint,
+    /// Access point plus a station connection to the provisioned network
+    Mixed,
+    /// Open `SETUP_SSID` access point waiting for credentials
+    Setup,
+}
+
+impl WifiMode {
+    /// Name used in the JSON API
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AccessPoint => "ap",
+            Self::Mixed => "mixed",
+            Self::Setup => "setup",
+        }
+    }
+}
+
+/// Wi-Fi credentials set through POST /provision
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    /// Upstream network joined while hosting the AP, empty to run AP only
+    pub ssid: String,
+    pub password: String,
+    /// WPA2 password players use to join the `SSID` access point
+    pub game_password: String,
+}
+
+impl Credentials {
+    /// Parse `{"ssid":"...","password":"...","game_password":"..."}`
+    /// `ssid` and `password` may be left out to run the access point only.
+    pub fn from_json(body: &str) -> Result<Self, &'static str> {
+        let credentials = Self {
+            ssid: extract_json_string(body, "ssid").unwrap_or_default(),
+            password: extract_json_string(body, "password").unwrap_or_default(),
+            game_password: extract_json_string(body, "game_password")
+                .ok_or("game_password is required")?,
+        };
+        if credentials.ssid.len() > 32 {
+            return Err("ssid must be at most 32 bytes");
+        }
+        if credentials.ssid.is_empty() && !credentials.password.is_empty() {
+            return Err("password given without an ssid");
+        }
+        if !credentials.password.is_empty() && !(8..=63).contains(&credentials.password.len()) {
+            return Err("password must be empty or 8-63 bytes");
+        }
+        if !(8..=63).contains(&credentials.game_password.len()) {
+            return Err("game_password must be 8-63 bytes");
+        }
+        Ok(credentials)
+    }
+
+    /// Credentials from `WIFI_PASS`, `UPSTREAM_SSID` and `UPSTREAM_PASS`
+    fn from_build() -> Self {
+        Self {
+            ssid: UPSTREAM_SSID.to_string(),
+            password: UPSTREAM_PASS.to_string(),
+            game_password: PASSWORD.to_string(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![0; CRC32_LEN];
+        data.push(FORMAT_VERSION);
+        for field in [&self.ssid, &self.password, &self.game_password] {
+            data.push(field.len() as u8);
+            data.extend_from_slice(field.as_bytes());
+        }
+        write_crc32(&mut data);
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let data = check_crc32(data)?;
+        let (&version, mut rest) = data.split_first()?;
+        if version != FORMAT_VERSION {
+            return None;
+        }
+        let mut fields = [String::new(), String::new(), String::new()];
+        for field in &mut fields {
+            let (&len, tail) = rest.split_first()?;
+            if len as usize > tail.len() {
+                return None;
+            }
+            let (bytes, tail) = tail.split_at(len as usize);
+            *field = String::from_utf8(bytes.to_vec()).ok()?;
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+        let [ssid, password, game_password] = fields;
+        Some(Self { ssid, password, game_password })
+    }
+}
+
+/// Provisioning state of the board
+/// Starts in `Setup` until POST /provision stores credentials, which take
+/// effect after a reboot, unless credentials were set at build time. A
+/// factory reset goes back to the starting state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Provisioning {
+    /// No credentials in NVS or from the build, the open setup AP is served
+    Setup,
+    /// Normal operation with credentials loaded from NVS or the build
+    Provisioned(Credentials),
+}
+
+impl Provisioning {
+    /// Load the state from the credentials stored in NVS
+    /// Missing or corrupt credentials fall back to `unprovisioned`
+    pub fn load(partition: EspDefaultNvsPartition) -> Self {
+        let nvs = match EspNvs::new(partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open provisioning NVS namespace: {:?}", e);
+                return Self::unprovisioned();
+            }
+        };
+        let mut buf = [0u8; BLOB_LEN];
+        match nvs.get_blob(NVS_KEY, &mut buf) {
+            Ok(Some(data)) => match Credentials::decode(data) {
+                Some(credentials) => Self::Provisioned(credentials),
+                None => {
+                    warn!("Provisioned credentials in NVS are corrupt, ignoring them");
+                    Self::unprovisioned()
+                }
+            },
+            Ok(None) => {
+                info!("No provisioned credentials in NVS");
+                Self::unprovisioned()
+            }
+            Err(e) => {
+                warn!("Failed to read provisioned credentials from NVS: {:?}", e);
+                Self::unprovisioned()
+            }
+        }
+    }
+
+    /// The credentials set at build time if there are any, `Setup` otherwise
+    fn unprovisioned() -> Self {
+        if BUILD_CREDENTIALS {
+            info!("Using the Wi-Fi credentials set at build time");
+            Self::Provisioned(Credentials::from_build())
+        } else {
+            Self::Setup
+        }
+    }
+
+    /// Store `credentials` for the next boot
+    pub fn provision(
+        &mut self,
+        partition: EspDefaultNvsPartition,
+        credentials: Credentials,
+    ) -> Result<(), EspError> {
+        let mut nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
+        nvs.set_blob(NVS_KEY, &credentials.encode())?;
+        info!("Stored credentials for upstream `{}`", credentials.ssid);
+        *self = Self::Provisioned(credentials);
+        Ok(())
+    }
+}
+
+/// Wipe the provisioned credentials, the next boot serves the setup AP or
+/// uses the credentials set at build time
+pub fn factory_reset(partition: EspDefaultNvsPartition) -> Result<(), EspError> {
+    let mut nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
+    nvs.remove(NVS_KEY)?;
+    warn!("Provisioned credentials erased");
+    Ok(())
+}
+
+/// Wi-Fi state determined at startup, reported by /metrics
+#[derive(Clone, Copy, Debug)]
+pub struct WifiStatus {
+    pub mode: WifiMode,
+    /// Address of the access point, 192.168.71.1 unless reconfigured
+    pub ap_ip: Ipv4Addr,
+    /// Address obtained from the upstream network in mixed mode
+    pub sta_ip: Option<Ipv4Addr>,
+    /// SSID of the access point, with the MAC suffix if one was added
+    pub ssid: &'static str,
+}
+
+/// Addresses and radio settings of the access point, for GET /network
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkInfo {
+    pub ipv4: Ipv4Addr,
+    /// Link-local address, `None` until IPv6 autoconfiguration assigned it
+    pub ipv6: Option<Ipv6Addr>,
+    pub mac: Option<[u8; 6]>,
+    pub ssid: &'static str,
+    pub channel: Option<u8>,
+    pub stations: usize,
+}
+
+impl NetworkInfo {
+    /// Read the current state of the access point
+    pub fn query(ipv4: Ipv4Addr, ssid: &'static str, stations: usize) -> Self {
+        Self {
+            ipv4,
+            ipv6: ap_ipv6_linklocal(),
+            mac: ap_mac(),
+            ssid,
+            channel: wifi_channel(),
+            stations,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let ipv6 = match self.ipv6 {
+            Some(ip) => format!(r#""{}""#, ip),
+            None => "null".to_string(),
+        };
+        let mac = match &self.mac {
+            Some(mac) => format!(r#""{}""#, parse_mac_address(mac)),
+            None => "null".to_string(),
+        };
+        let channel = match self.channel {
+            Some(channel) => channel.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            r#"{{"ipv4":"{}","ipv6_link_local":{},"mac":{},"ssid":"{}","channel":{},"stations":{}}}"#,
+            self.ipv4,
+            ipv6,
+            mac,
+            json_escape(self.ssid),
+            channel,
+            self.stations
+        )
+    }
+}
+
+/// Create the access point's IPv6 link-local address and log it
+/// The address only becomes usable once duplicate address detection is
+/// done, so right after startup it is usually logged as not assigned yet
+fn enable_ipv6_linklocal(netif: &EspNetif) {
+    // SAFETY: the handle belongs to the AP netif, which lives as long as the Wi-Fi driver
+    let created = unsafe { sys::esp_netif_create_ip6_linklocal(netif.handle()) };
+    if let Err(e) = EspError::convert(created) {
+        warn!("Failed to create IPv6 link-local address: {:?}", e);
+        return;
+    }
+    match ap_ipv6_linklocal() {
+        Some(ip) => info!("Access point IPv6 link-local address {}", ip),
+        None => info!("Access point IPv6 link-local address not assigned yet"),
+    }
+}
+
+/// IPv6 link-local address of the access point, once assigned
+pub fn ap_ipv6_linklocal() -> Option<Ipv6Addr> {
+    // SAFETY: both pointers are valid for the duration of the calls
+    unsafe {
+        let netif = sys::esp_netif_get_handle_from_ifkey(c"WIFI_AP_DEF".as_ptr());
+        if netif.is_null() {
+            return None;
+        }
+        let mut ip6 = sys::esp_ip6_addr_t::default();
+        EspError::convert(sys::esp_netif_get_ip6_linklocal(netif, &mut ip6)).ok()?;
+        Some(ipv6_from_words(ip6.addr))
+    }
+}
+
+/// lwIP keeps IPv6 addresses as four words in network byte order
+fn ipv6_from_words(words: [u32; 4]) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    for (chunk, word) in octets.chunks_exact_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_ne_bytes());
+    }
+    Ipv6Addr::from(octets)
+}
+
+/// MAC address of the access point interface
+fn ap_mac() -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    // SAFETY: `mac` has room for the 6 bytes written
+    let result =
+        unsafe { sys::esp_wifi_get_mac(sys::wifi_interface_t_WIFI_IF_AP, mac.as_mut_ptr()) };
+    EspError::convert(result).ok().map(|()| mac)
+}
+
+/// SSID of the game access point: `SSID`, with a MAC suffix if
+/// `MAC_SUFFIX_SSID` is set
+/// Needs the Wi-Fi driver to be initialized for the MAC to be readable.
+fn game_ssid() -> &'static str {
+    if !MAC_SUFFIX_SSID {
+        return SSID;
+    }
+    match ap_mac() {
+        // Built once at startup and needed for as long as the firmware runs
+        Some(mac) => ssid_with_mac_suffix(SSID, &mac).leak(),
+        None => {
+            warn!("Failed to read the AP MAC address, using `{SSID}` without suffix");
+            SSID
+        }
+    }
+}
+
+/// Append `-AABBCC` from the last 3 bytes of `mac`, truncating `base` so the
+/// result still fits the 32 bytes of an SSID
+fn ssid_with_mac_suffix(base: &str, mac: &[u8; 6]) -> String {
+    let suffix = format!("-{:02X}{:02X}{:02X}", mac[3], mac[4], mac[5]);
+    let mut end = base.len().min(32 - suffix.len());
+    while !base.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &base[..end], suffix)
+}
+
+/// Channel the radio is on, which in mixed mode is the upstream network's
+fn wifi_channel() -> Option<u8> {
+    let mut primary = 0u8;
+    let mut secondary = sys::wifi_second_chan_t::default();
+    // SAFETY: both pointers are valid for the duration of the call
+    let result = unsafe { sys::esp_wifi_get_channel(&mut primary, &mut secondary) };
+    EspError::convert(result).ok().map(|()| primary)
+}
 
 /// Create and configure the HTTP server with WiFi access point
-pub fn create_server(modem: Modem) -> Result<EspHttpServer<'static>> {
+/// Also connects to the provisioned upstream network when one is configured,
+/// or only serves the open setup AP if the board isn't provisioned yet
+pub fn create_server(
+    modem: Modem,
+    nvs: EspDefaultNvsPartition,
+    provisioning: &Provisioning,
+) -> Result<(EspHttpServer<'static>, WifiStatus)> {
     info!("Creating HTTP server...");
 
     let sys_loop = EspSystemEventLoop::take()?;
-    let nvs = EspDefaultNvsPartition::take()?;
 
     let mut wifi = BlockingWifi::wrap(
         EspWifi::new(modem, sys_loop.clone(), Some(nvs))?,
         sys_loop,
     )?;
 
-    let wifi_configuration = wifi::Configuration::AccessPoint(AccessPointConfiguration {
-        ssid: SSID.try_into().unwrap(),
-        ssid_hidden: false, // Set to false to make SSID visible in WiFi scan lists
-        auth_method: AuthMethod::WPA2Personal,
-        password: PASSWORD.try_into().unwrap(),
-        channel: CHANNEL,
-        ..Default::default()
-    });
+    let channel = pick_least_congested_channel(&mut wifi);
 
-    info!("Configuring Wi-Fi access point...");
-    wifi.set_configuration(&wifi_configuration)?;
-    wifi.start()?;
-    wifi.wait_netif_up()?;
+    let wifi_status = match provisioning {
+        Provisioning::Setup => {
+            let ap_configuration = AccessPointConfiguration {
+                ssid: SETUP_SSID.try_into().unwrap(),
+                auth_method: AuthMethod::None,
+                channel,
+                max_connections: MAX_AP_STATIONS.into(),
+                ..Default::default()
+            };
+            let status = start_access_point(&mut wifi, ap_configuration, SETUP_SSID)?;
+            info!("Created open setup Wi-Fi `{SETUP_SSID}` on channel {channel}");
+            WifiStatus { mode: WifiMode::Setup, ..status }
+        }
+        Provisioning::Provisioned(credentials) => {
+            let ssid = game_ssid();
+            let ap_configuration = AccessPointConfiguration {
+                ssid: ssid.try_into().unwrap(),
+                ssid_hidden: false, // Set to false to make SSID visible in WiFi scan lists
+                auth_method: AuthMethod::WPA2Personal,
+                password: credentials.game_password.as_str().try_into().unwrap(),
+                channel,
+                max_connections: MAX_AP_STATIONS.into(),
+                ..Default::default()
+            };
+            let status = if credentials.ssid.is_empty() {
+                start_access_point(&mut wifi, ap_configuration, ssid)?
+            } else {
+                match start_mixed(&mut wifi, ap_configuration.clone(), ssid, credentials) {
+                    Ok(status) => status,
+                    Err(e) => {
+                        warn!(
+                            "Failed to connect to upstream `{}`, falling back to AP only: {:?}",
+                            credentials.ssid, e
+                        );
+                        if let Err(e) = wifi.stop() {
+                            warn!("Failed to stop Wi-Fi: {:?}", e);
+                        }
+                        start_access_point(&mut wifi, ap_configuration, ssid)?
+                    }
+                }
+            };
+            info!(
+                "Created Wi-Fi with SSID `{ssid}` and password `{}` on channel {channel}",
+                credentials.game_password
+            );
+            status
+        }
+    };
 
-    info!("Created Wi-Fi with WIFI_SSID `{SSID}` and WIFI_PASS `{PASSWORD}`");
+    info!("Starting mDNS responder...");
+    let mut mdns = EspMdns::take()?;
+    mdns.set_hostname(MDNS_HOSTNAME)?;
+    mdns.add_service(None, "_http", "_tcp", 80, &[("path", "/")])?;
+    info!("Server reachable at http://{MDNS_HOSTNAME}.local/");
 
     let server_configuration = esp_idf_svc::http::server::Configuration {
         stack_size: STACK_SIZE,
+        max_uri_handlers: MAX_URI_HANDLERS,
+        // Needed for the catch-all OPTIONS handler on `/*`
+        uri_match_wildcard: true,
         ..Default::default()
     };
 
@@ -51,9 +481,653 @@ pub fn create_server(modem: Modem) -> Result<EspHttpServer<'static>> {
     // so it does not go out of scope.
     // https://doc.rust-lang.org/stable/core/mem/fn.forget.html
     core::mem::forget(wifi);
+    // Same for mDNS, which stops responding once dropped
+    core::mem::forget(mdns);
 
     let server = EspHttpServer::new(&server_configuration)?;
     info!("HTTP server created successfully");
-    Ok(server)
+    Ok((server, wifi_status))
+}
+
+/// Bring up the standalone access point
+fn start_access_point(
+    wifi: &mut BlockingWifi<EspWifi<'static>>,
+    ap_configuration: AccessPointConfiguration,
+    ssid: &'static str,
+) -> Result<WifiStatus> {
+    info!("Configuring Wi-Fi access point...");
+    wifi.set_configuration(&wifi::Configuration::AccessPoint(ap_configuration))?;
+    set_beacon_interval(BEACON_INTERVAL_MS)?;
+    wifi.start()?;
+    // Every attempt can wait for a while, so check in with the watchdog before each
+    retry(
+        || {
+            feed_watchdog();
+            wifi.wait_netif_up()
+        },
+        ESP_RETRY_ATTEMPTS,
+        ESP_RETRY_DELAY_MS,
+    )?;
+    enable_ipv6_linklocal(wifi.wifi().ap_netif());
+    Ok(WifiStatus {
+        mode: WifiMode::AccessPoint,
+        ap_ip: wifi.wifi().ap_netif().get_ip_info()?.ip,
+        sta_ip: None,
+        ssid,
+    })
 }
 
+/// Set the AP beacon interval, which `AccessPointConfiguration` has no field for
+/// Must run after `set_configuration`, which resets it to 100.
+fn set_beacon_interval(interval: u16) -> Result<(), EspError> {
+    let mut config = sys::wifi_config_t::default();
+    // SAFETY: `config` is valid for both calls, and the AP variant is the one in use
+    // for `WIFI_IF_AP`
+    unsafe {
+        EspError::convert(sys::esp_wifi_get_config(sys::wifi_interface_t_WIFI_IF_AP, &mut config))?;
+        config.ap.beacon_interval = interval;
+        EspError::convert(sys::esp_wifi_set_config(sys::wifi_interface_t_WIFI_IF_AP, &mut config))?;
+    }
+    debug!("Beacon interval set to {} TU", interval);
+    Ok(())
+}
+
+/// Bring up the access point and connect to the upstream network at the same time
+/// The AP moves to the upstream network's channel, as the radio can only use one
+fn start_mixed(
+    wifi: &mut BlockingWifi<EspWifi<'static>>,
+    ap_configuration: AccessPointConfiguration,
+    ssid: &'static str,
+    credentials: &Credentials,
+) -> Result<WifiStatus> {
+    info!("Configuring Wi-Fi access point with upstream `{}`...", credentials.ssid);
+    let sta_configuration = ClientConfiguration {
+        ssid: credentials.ssid.as_str().try_into().unwrap(),
+        password: credentials.password.as_str().try_into().unwrap(),
+        auth_method: if credentials.password.is_empty() {
+            AuthMethod::None
+        } else {
+            AuthMethod::WPA2Personal
+        },
+        ..Default::default()
+    };
+    wifi.set_configuration(&wifi::Configuration::Mixed(
+        sta_configuration,
+        ap_configuration,
+    ))?;
+    set_beacon_interval(BEACON_INTERVAL_MS)?;
+    wifi.start()?;
+    wifi.connect()?;
+    // Every attempt can wait for a while, so check in with the watchdog before each
+    retry(
+        || {
+            feed_watchdog();
+            wifi.wait_netif_up()
+        },
+        ESP_RETRY_ATTEMPTS,
+        ESP_RETRY_DELAY_MS,
+    )?;
+    enable_ipv6_linklocal(wifi.wifi().ap_netif());
+
+    let sta_ip = wifi.wifi().sta_netif().get_ip_info()?.ip;
+    info!("Connected to upstream `{}` with IP {sta_ip}", credentials.ssid);
+    Ok(WifiStatus {
+        mode: WifiMode::Mixed,
+        ap_ip: wifi.wifi().ap_netif().get_ip_info()?.ip,
+        sta_ip: Some(sta_ip),
+        ssid,
+    })
+}
+
+/// CORS headers allowing the API to be called from any origin
+pub fn cors_headers() -> &'static [(&'static str, &'static str)] {
+    &[
+        ("Access-Control-Allow-Origin", "*"),
+        ("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
+        ("Access-Control-Allow-Headers", "Content-Type, Authorization"),
+        ("Access-Control-Expose-Headers", "X-Request-ID, X-Response-Time"),
+    ]
+}
+
+/// Append the CORS headers to a response's own headers
+pub fn with_cors<'a>(headers: &[(&'a str, &'a str)]) -> Vec<(&'a str, &'a str)> {
+    [headers, cors_headers()].concat()
+}
+
+/// Get the IPv4 address of the client that sent a request
+pub fn client_ipv4(req: &mut Request<&mut EspHttpConnection<'_>>) -> Option<[u8; 4]> {
+    match req.connection().raw_connection().and_then(|conn| conn.source_ipv4()) {
+        Ok(ip) => Some(ip.octets()),
+        Err(e) => {
+            debug!("Could not determine client IP: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Get the IPv4 address of the peer on a socket, such as a WebSocket session ID
+#[cfg(feature = "game")]
+pub fn peer_ipv4(sockfd: i32) -> Option<Ipv4Addr> {
+    use esp_idf_svc::sys::{lwip_getpeername, sockaddr_in, socklen_t, AF_INET};
+
+    let mut addr = sockaddr_in {
+        sin_len: core::mem::size_of::<sockaddr_in>() as _,
+        sin_family: AF_INET as _,
+        ..Default::default()
+    };
+    let mut len = core::mem::size_of::<sockaddr_in>() as socklen_t;
+    let result = unsafe { lwip_getpeername(sockfd, &mut addr as *mut _ as *mut _, &mut len) };
+    if result != 0 {
+        debug!("Could not determine peer of socket {}", sockfd);
+        return None;
+    }
+    Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)))
+}
+
+/// Enable TCP keepalive on a socket, such as a WebSocket session ID
+/// ESP-IDF's HTTP server doesn't expose its listening socket, so this is
+/// applied to each WebSocket connection as it opens.
+pub fn set_tcp_keepalive(sockfd: i32) {
+    use esp_idf_svc::sys::{
+        lwip_setsockopt, socklen_t, IPPROTO_TCP, SOL_SOCKET, SO_KEEPALIVE, TCP_KEEPCNT,
+        TCP_KEEPIDLE, TCP_KEEPINTVL,
+    };
+
+    let options = [
+        (SOL_SOCKET, SO_KEEPALIVE, 1),
+        (IPPROTO_TCP, TCP_KEEPIDLE, TCP_KEEPALIVE_IDLE_S),
+        (IPPROTO_TCP, TCP_KEEPINTVL, TCP_KEEPALIVE_INTVL_S),
+        (IPPROTO_TCP, TCP_KEEPCNT, TCP_KEEPALIVE_COUNT),
+    ];
+    for (level, name, value) in options {
+        // SAFETY: `value` outlives the call, which copies it
+        let result = unsafe {
+            lwip_setsockopt(
+                sockfd,
+                level as _,
+                name as _,
+                &value as *const i32 as *const _,
+                core::mem::size_of::<i32>() as socklen_t,
+            )
+        };
+        if result != 0 {
+            warn!("Failed to set TCP keepalive option {} on socket {}", name, sockfd);
+            return;
+        }
+    }
+    debug!(
+        "TCP keepalive on socket {}: idle {} s, interval {} s, {} probes",
+        sockfd, TCP_KEEPALIVE_IDLE_S, TCP_KEEPALIVE_INTVL_S, TCP_KEEPALIVE_COUNT
+    );
+}
+
+/// Register a WebSocket handler whose handshake accepts `subprotocol`
+/// esp-idf-svc's `ws_handler` can't pass a subprotocol to ESP-IDF, so the
+/// route is registered through it first, for the close notifications, and
+/// then replaced by one that does. Clients that don't ask for the
+/// subprotocol are still accepted.
+#[cfg(feature = "game")]
+pub fn ws_handler_with_subprotocol<H>(
+    server: &mut EspHttpServer<'static>,
+    uri: &'static CStr,
+    subprotocol: &'static CStr,
+    handler: H,
+) -> Result<(), EspError>
+where
+    H: Fn(&mut EspHttpWsConnection) -> Result<(), EspError> + Send + Sync + 'static,
+{
+    let handler: WsHandler = Arc::new(handler);
+    let for_close = handler.clone();
+    server.ws_handler(uri.to_str().unwrap(), move |ws| for_close(ws))?;
+
+    let route = WsRoute {
+        server: server.handle(),
+        handler,
+    };
+    let conf = sys::httpd_uri_t {
+        uri: uri.as_ptr(),
+        method: sys::http_method_HTTP_GET,
+        // Never freed, like the server itself
+        user_ctx: Box::into_raw(Box::new(route)) as *mut _,
+        handler: Some(handle_ws_route),
+        is_websocket: true,
+        supported_subprotocol: subprotocol.as_ptr(),
+        ..Default::default()
+    };
+    let sd = server.handle();
+    // SAFETY: both strings are 'static and ESP-IDF copies the route config
+    unsafe {
+        EspError::convert(sys::httpd_unregister_uri_handler(sd, uri.as_ptr(), conf.method))?;
+        EspError::convert(sys::httpd_register_uri_handler(sd, &conf))?;
+    }
+    info!("Registered WS handler for {:?} with subprotocol {:?}", uri, subprotocol);
+    Ok(())
+}
+
+/// Request callback of routes registered by `ws_handler_with_subprotocol`
+#[cfg(feature = "game")]
+extern "C" fn handle_ws_route(raw_req: *mut sys::httpd_req_t) -> c_int {
+    // SAFETY: ESP-IDF passes a valid request whose `user_ctx` is the leaked `WsRoute`
+    let (method, route) = unsafe {
+        let req = &*raw_req;
+        (req.method, &*(req.user_ctx as *const WsRoute))
+    };
+    // Like esp-idf-svc: the GET is the handshake, anything else a frame
+    let mut ws = if method == sys::http_method_HTTP_GET as c_int {
+        EspHttpWsConnection::New(route.server, raw_req)
+    } else {
+        EspHttpWsConnection::Receiving(route.server, raw_req, None)
+    };
+    if let Err(e) = (route.handler)(&mut ws) {
+        warn!("Unhandled WebSocket handler error: {:?}", e);
+    }
+    sys::ESP_OK as _
+}
+
+/// Protocol a new WebSocket session asked for in `Sec-WebSocket-Protocol`
+/// Only known on the handshake, `WsProtocol::default()` for later frames
+#[cfg(feature = "game")]
+pub fn ws_handler_version(ws: &EspHttpWsConnection) -> WsProtocol {
+    let EspHttpWsConnection::New(_, raw_req) = ws else {
+        return WsProtocol::default();
+    };
+    let field = c"Sec-WebSocket-Protocol";
+    // SAFETY: the handshake request stays valid while the handler runs
+    let len = unsafe { sys::httpd_req_get_hdr_value_len(*raw_req, field.as_ptr()) };
+    if len == 0 {
+        return WsProtocol::default();
+    }
+    let mut buf = vec![0u8; len + 1];
+    let result = unsafe {
+        let value = buf.as_mut_ptr() as *mut _;
+        sys::httpd_req_get_hdr_value_str(*raw_req, field.as_ptr(), value, buf.len())
+    };
+    if EspError::convert(result).is_err() {
+        return WsProtocol::default();
+    }
+    let offered = CStr::from_bytes_until_nul(&buf).ok().and_then(|s| s.to_str().ok());
+    WsProtocol::negotiate(offered.unwrap_or_default())
+}
+
+/// Check the per-IP rate limit for a request
+/// Requests whose source IP cannot be determined are never limited
+pub fn rate_limited(
+    limiter: &Mutex<RateLimiter>,
+    req: &mut Request<&mut EspHttpConnection<'_>>,
+) -> bool {
+    rate_limited_to(limiter, req, RATE_LIMIT_REQUESTS)
+}
+
+/// Like `rate_limited`, with at most `limit` requests per window
+pub fn rate_limited_to(
+    limiter: &Mutex<RateLimiter>,
+    req: &mut Request<&mut EspHttpConnection<'_>>,
+    limit: u32,
+) -> bool {
+    let Some(ip) = client_ipv4(req) else {
+        return false;
+    };
+    let allowed = limiter.lock().unwrap().check(ip, limit, RATE_LIMIT_WINDOW_MS);
+    if !allowed {
+        warn!("Rate limiting {} from {:?}", req.uri(), ip);
+    }
+    !allowed
+}
+
+/// Start time of a request, for the `X-Response-Time` header
+/// `logged` starts one for every request before running its handler.
+#[derive(Clone, Copy, Debug)]
+pub struct TimedRequest {
+    start_us: u64,
+}
+
+impl TimedRequest {
+    pub fn start() -> Self {
+        Self { start_us: now_us() }
+    }
+
+    /// Microseconds since the request started
+    pub fn elapsed_us(&self) -> u64 {
+        now_us().saturating_sub(self.start_us)
+    }
+}
+
+/// Keeps the task watchdog on a handler while it runs
+///
+/// The idle task check of the watchdog only notices a starved CPU, not a
+/// handler stuck on e.g. a write to a client that stopped reading. Starting
+/// a checkpoint subscribes the calling task to the task watchdog unless it
+/// already is, dropping it unsubscribes the task again, and both reset the
+/// watchdog. A handler taking longer than `WATCHDOG_TIMEOUT_S` without
+/// calling `feed_watchdog` then resets the chip, while the server task
+/// waiting for the next request is left alone. `logged` starts one for
+/// every request, so a handler that runs for a long time, e.g. a stream
+/// or an upload, must call `feed_watchdog` as it makes progress.
+pub struct WatchdogCheckpoint {
+    /// Whether `start` subscribed the task, so `drop` has to unsubscribe it
+    subscribed: bool,
+}
+
+impl WatchdogCheckpoint {
+    pub fn start() -> Self {
+        let subscribed = !watchdog_subscribed() && {
+            // SAFETY: a null handle stands for the calling task
+            let added = EspError::convert(unsafe { sys::esp_task_wdt_add(core::ptr::null_mut()) });
+            if let Err(e) = &added {
+                warn!("Failed to subscribe to the task watchdog: {:?}", e);
+            }
+            added.is_ok()
+        };
+        feed_watchdog();
+        Self { subscribed }
+    }
+}
+
+impl Drop for WatchdogCheckpoint {
+    fn drop(&mut self) {
+        feed_watchdog();
+        if self.subscribed {
+            // SAFETY: as in `start`, only undoes its own subscription
+            let deleted = unsafe { sys::esp_task_wdt_delete(core::ptr::null_mut()) };
+            if let Err(e) = EspError::convert(deleted) {
+                warn!("Failed to unsubscribe from the task watchdog: {:?}", e);
+            }
+        }
+    }
+}
+
+fn watchdog_subscribed() -> bool {
+    // SAFETY: a null handle stands for the calling task
+    EspError::convert(unsafe { sys::esp_task_wdt_status(core::ptr::null_mut()) }).is_ok()
+}
+
+/// Reset the task watchdog if the calling task is subscribed to it, for
+/// handlers and setup steps that keep making progress for a long time
+pub fn feed_watchdog() {
+    if watchdog_subscribed() {
+        // SAFETY: only resets the entry of the calling task
+        unsafe { sys::esp_task_wdt_reset() };
+    }
+}
+
+/// Set the task watchdog timeout to `WATCHDOG_TIMEOUT_S`, resetting the chip
+/// when it expires instead of only logging the stuck tasks
+pub fn configure_watchdog() -> Result<(), EspError> {
+    let config = sys::esp_task_wdt_config_t {
+        timeout_ms: WATCHDOG_TIMEOUT_S * 1000,
+        // Keep watching the idle task of the C3's single core
+        idle_core_mask: 1,
+        trigger_panic: true,
+    };
+    // SAFETY: the config is only read during the call
+    EspError::convert(unsafe { sys::esp_task_wdt_reconfigure(&config) })?;
+    info!("Task watchdog timeout set to {} s", WATCHDOG_TIMEOUT_S);
+    Ok(())
+}
+
+/// `X-Response-Time` value for the request being served, e.g. `1423us`
+pub fn response_time(uri: &str) -> String {
+    let elapsed_us = request_log::timer().map_or(0, |timer| timer.elapsed_us());
+    debug!("{} handled in {} us", uri, elapsed_us);
+    format!("{}us", elapsed_us)
+}
+
+/// Send the status line and `headers` plus the CORS headers, the request ID
+/// and the response time, noting the status for the request log
+/// Every response is started here, so none goes out without them.
+pub fn start_response<'a, 'r>(
+    req: Request<&'a mut EspHttpConnection<'r>>,
+    status: u16,
+    reason: Option<&str>,
+    headers: &[(&str, &str)],
+) -> Result<Response<&'a mut EspHttpConnection<'r>>, EspError> {
+    let request_id = request_log::request_id();
+    let response_time = response_time(req.uri());
+    let mut all_headers = headers.to_vec();
+    all_headers.push(("X-Request-ID", &request_id));
+    all_headers.push(("X-Response-Time", &response_time));
+    request_log::set_status(status);
+    req.into_response(status, reason, &with_cors(&all_headers))
+        .map_err(|e| ServerError::from(e).into_esp_error())
+}
+
+/// Send a complete response with the content type and `headers` through
+/// `start_response`
+pub fn respond(
+    req: Request<&mut EspHttpConnection<'_>>,
+    status: u16,
+    content_type: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<(), EspError> {
+    let mut all_headers = vec![("Content-Type", content_type)];
+    all_headers.extend_from_slice(headers);
+    start_response(req, status, reason_phrase(status), &all_headers)?
+        .write_all(body)
+        .map_err(|e| ServerError::from(e).into_esp_error())
+}
+
+/// `respond` with a JSON body
+pub fn respond_json(
+    req: Request<&mut EspHttpConnection<'_>>,
+    status: u16,
+    body: &str,
+) -> Result<(), EspError> {
+    respond(req, status, "application/json", &[], body.as_bytes())
+}
+
+/// `respond` with an HTML page
+pub fn respond_html(
+    req: Request<&mut EspHttpConnection<'_>>,
+    status: u16,
+    body: &str,
+) -> Result<(), EspError> {
+    respond(req, status, "text/html; charset=utf-8", &[], body.as_bytes())
+}
+
+/// Reason phrase of the statuses handlers send with `respond`, errors go
+/// through `ServerError::respond` instead
+fn reason_phrase(status: u16) -> Option<&'static str> {
+    match status {
+        200 => Some("OK"),
+        202 => Some("Accepted"),
+        404 => Some("Not Found"),
+        _ => None,
+    }
+}
+
+/// Respond with 429 Too Many Requests and a JSON body like the other errors
+pub fn too_many_requests(req: Request<&mut EspHttpConnection<'_>>) -> Result<(), EspError> {
+    let err = ServerError::RateLimit;
+    let retry_after = RATE_LIMIT_WINDOW_MS.div_ceil(1000).to_string();
+    let body = format!(
+        r#"{{"error":"{}","request_id":"{}"}}"#,
+        json_escape(&err.to_string()),
+        request_log::request_id()
+    );
+    let mut resp = start_response(
+        req,
+        err.status(),
+        Some(err.reason()),
+        &[("Content-Type", "application/json"), ("Retry-After", retry_after.as_str())],
+    )?;
+    resp.write_all(body.as_bytes())
+        .map_err(|e| ServerError::from(e).into_esp_error())?;
+    Ok(())
+}
+
+/// Response body writer sending the body in `HTTP_CHUNK_LEN` sized chunks
+///
+/// Without a `Content-Length` header the ESP-IDF server already answers with
+/// `Transfer-Encoding: chunked`, sends each write as one chunk with its size
+/// line, and sends the terminating empty chunk once the handler returns.
+/// This batches small writes so a body built from many short pieces is not
+/// sent as just as many tiny chunks, while never holding more than one chunk.
+pub struct ChunkedWriter<'a, 'r> {
+    response: Response<&'a mut EspHttpConnection<'r>>,
+    buffer: Vec<u8>,
+}
+
+impl<'a, 'r> ChunkedWriter<'a, 'r> {
+    /// Wrap a response whose headers don't include `Content-Length`
+    pub fn new(response: Response<&'a mut EspHttpConnection<'r>>) -> Self {
+        Self {
+            response,
+            buffer: Vec::with_capacity(HTTP_CHUNK_LEN),
+        }
+    }
+
+    /// Send the buffered bytes as one chunk
+    fn send_chunk(&mut self) -> Result<(), EspIOError> {
+        if !self.buffer.is_empty() {
+            self.response.write_all(&self.buffer)?;
+            self.buffer.clear();
+            // Long responses are fine as long as every chunk gets through
+            feed_watchdog();
+        }
+        Ok(())
+    }
+}
+
+impl ErrorType for ChunkedWriter<'_, '_> {
+    type Error = EspIOError;
+}
+
+impl Write for ChunkedWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let len = buf.len().min(HTTP_CHUNK_LEN - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() >= HTTP_CHUNK_LEN {
+            self.send_chunk()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.send_chunk()?;
+        self.response.flush()
+    }
+}
+
+impl Drop for ChunkedWriter<'_, '_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.send_chunk() {
+            warn!("Failed to send last response chunk: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> Credentials {
+        Credentials {
+            ssid: "HomeNetwork".to_string(),
+            password: "secret123".to_string(),
+            game_password: "password123".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_credentials_from_json() {
+        let body = r#"{"ssid":"HomeNetwork","password":"secret123","game_password":"password123"}"#;
+        assert_eq!(Credentials::from_json(body), Ok(credentials()));
+        let ap_only = Credentials::from_json(r#"{"game_password":"password123"}"#).unwrap();
+        assert!(ap_only.ssid.is_empty() && ap_only.password.is_empty());
+
+        assert!(Credentials::from_json(r#"{"ssid":"HomeNetwork"}"#).is_err());
+        assert!(Credentials::from_json(r#"{"game_password":"short"}"#).is_err());
+        let no_ssid = r#"{"password":"secret123","game_password":"password123"}"#;
+        assert!(Credentials::from_json(no_ssid).is_err());
+        let short = r#"{"ssid":"HomeNetwork","password":"short","game_password":"password123"}"#;
+        assert!(Credentials::from_json(short).is_err());
+        let long_ssid = format!(r#"{{"ssid":"{}","game_password":"password123"}}"#, "x".repeat(33));
+        assert!(Credentials::from_json(&long_ssid).is_err());
+
+        // Keys are matched as keys, not inside other keys or values
+        let tricky = r#"{"game_password":"password","password":"secret123","ssid":"HomeNetwork"}"#;
+        assert_eq!(Credentials::from_json(tricky).unwrap().password, "secret123");
+        let escaped = r#"{"ssid":"Home \"5G\"","password":"back\\slash","game_password":"password123"}"#;
+        let escaped = Credentials::from_json(escaped).unwrap();
+        assert_eq!(escaped.ssid, "Home \"5G\"");
+        assert_eq!(escaped.password, "back\\slash");
+    }
+
+    #[test]
+    fn test_credentials_round_trip() {
+        let data = credentials().encode();
+        assert!(data.len() <= BLOB_LEN);
+        assert_eq!(Credentials::decode(&data), Some(credentials()));
+
+        let longest = Credentials {
+            ssid: "s".repeat(32),
+            password: "p".repeat(63),
+            game_password: "g".repeat(63),
+        };
+        assert_eq!(longest.encode().len(), BLOB_LEN);
+        assert_eq!(Credentials::decode(&longest.encode()), Some(longest));
+    }
+
+    #[test]
+    fn test_credentials_decode_rejects_corrupt() {
+        let data = credentials().encode();
+        assert!(Credentials::decode(&[]).is_none());
+        assert!(Credentials::decode(&data[..data.len() - 1]).is_none());
+        let mut bad_version = data.clone();
+        bad_version[CRC32_LEN] = FORMAT_VERSION + 1;
+        write_crc32(&mut bad_version);
+        assert!(Credentials::decode(&bad_version).is_none());
+        let mut bad_len = data.clone();
+        bad_len[CRC32_LEN + 1] = 200;
+        write_crc32(&mut bad_len);
+        assert!(Credentials::decode(&bad_len).is_none());
+        let mut bad_crc = data;
+        bad_crc[CRC32_LEN + 2] ^= 1;
+        assert!(Credentials::decode(&bad_crc).is_none());
+    }
+
+    #[test]
+    fn test_ipv6_from_words() {
+        // fe80::1 as lwIP stores it on a little-endian CPU
+        let words = [0x0000_80fe, 0, 0, 0x0100_0000];
+        assert_eq!(ipv6_from_words(words), "fe80::1".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[test]
+    fn test_ssid_with_mac_suffix() {
+        let mac = [0x24, 0x0a, 0xc4, 0xaa, 0xbb, 0xcc];
+        assert_eq!(ssid_with_mac_suffix("ESP32-Game", &mac), "ESP32-Game-AABBCC");
+        let long = "x".repeat(32);
+        assert_eq!(ssid_with_mac_suffix(&long, &mac), format!("{}-AABBCC", &long[..25]));
+        // Never cut a multi-byte character in half
+        let umlauts = "ä".repeat(16);
+        assert_eq!(ssid_with_mac_suffix(&umlauts, &mac), format!("{}-AABBCC", "ä".repeat(12)));
+    }
+
+    #[test]
+    fn test_network_info_json() {
+        let info = NetworkInfo {
+            ipv4: Ipv4Addr::new(192, 168, 71, 1),
+            ipv6: Some("fe80::1".parse().unwrap()),
+            mac: Some([0x24, 0x0a, 0xc4, 0x00, 0x01, 0x02]),
+            ssid: "ESP32-Game",
+            channel: Some(6),
+            stations: 2,
+        };
+        assert_eq!(
+            info.to_json(),
+            r#"{"ipv4":"192.168.71.1","ipv6_link_local":"fe80::1","mac":"24:0A:C4:00:01:02","ssid":"ESP32-Game","channel":6,"stations":2}"#
+        );
+        let info = NetworkInfo { ipv6: None, mac: None, channel: None, ..info };
+        assert!(info.to_json().contains(r#""ipv6_link_local":null,"mac":null"#));
+    }
+
+    #[test]
+    fn test_timed_request_elapsed() {
+        assert!(TimedRequest::start().elapsed_us() < 1_000_000);
+        // A clock reading from before the start must not underflow
+        let later = TimedRequest { start_us: u64::MAX };
+        assert_eq!(later.elapsed_us(), 0);
+    }
+}
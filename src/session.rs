@@ -0,0 +1,459 @@
+//! Per-connection state of a /ws/guess session
+//!
+//! Running games are also kept in NVS, so a reboot mid-game doesn't lose
+//! them. Restored games are handed to the next session from the same client
+//! IP, session IDs are socket descriptors that get reused after a reboot.
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::*;
+use std::{collections::BTreeMap, net::Ipv4Addr};
+
+use crate::config::{GameConfig, GUESS_JSON_SUBPROTOCOL, MAX_WS_SESSIONS, WS_MAX_MSG_PER_SEC};
+use crate::guessing_game::{GuessingGame, WsMessage, SERIALIZED_LEN};
+use crate::utils::{check_crc32, write_crc32, CRC32_LEN};
+
+pub const NVS_NAMESPACE: &str = "sessions";
+pub const NVS_KEY: &str = "games";
+
+// Layout: CRC-32 of the rest (u32 LE), version (1 byte), entry count
+// (1 byte), then per entry the client IPv4 address (4 bytes) followed by
+// the serialized game
+const FORMAT_VERSION: u8 = 3;
+// Version and count, following the CRC
+const HEADER_LEN: usize = 2;
+const ENTRY_LEN: usize = 4 + SERIALIZED_LEN;
+const BLOB_LEN: usize = CRC32_LEN + HEADER_LEN + MAX_WS_SESSIONS * ENTRY_LEN;
+
+/// Message format agreed on in the WebSocket handshake
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WsProtocol {
+    /// The client asked for `GUESS_JSON_SUBPROTOCOL`, so every reply is JSON
+    pub json: bool,
+}
+
+impl WsProtocol {
+    /// Pick the protocol from a `Sec-WebSocket-Protocol` header value, a
+    /// comma-separated list of the subprotocols the client offers
+    pub fn negotiate(offered: &str) -> Self {
+        let wanted = GUESS_JSON_SUBPROTOCOL.to_str().unwrap();
+        Self {
+            json: offered.split(',').any(|protocol| protocol.trim() == wanted),
+        }
+    }
+}
+
+/// Which sessions a secret set with POST /game/set-secret applies to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecretScope {
+    /// The next new session only
+    Next,
+    /// Every open session, whose games restart with the new secret
+    All,
+}
+
+impl SecretScope {
+    /// Parse the `scope` of a POST /game/set-secret body, `Next` if absent
+    pub fn from_name(name: Option<&str>) -> Result<Self, &'static str> {
+        match name {
+            None | Some("next") => Ok(Self::Next),
+            Some("all") => Ok(Self::All),
+            Some(_) => Err("scope must be \"next\" or \"all\""),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Next => "next",
+            Self::All => "all",
+        }
+    }
+}
+
+/// Secret for a new session: the operator's pending override, which is used
+/// up, or else `random` mapped onto the range
+/// An override the range was changed away from since it was set is dropped.
+pub fn new_session_secret(
+    secret_override: &mut Option<u32>,
+    config: &GameConfig,
+    random: u32,
+) -> u32 {
+    match secret_override.take() {
+        Some(secret) if config.contains(secret) => {
+            info!("Using the operator's secret for the new session");
+            secret
+        }
+        Some(secret) => {
+            warn!(
+                "Dropping operator secret {}, no longer in {}-{}",
+                secret, config.min, config.max
+            );
+            config.secret_from(random)
+        }
+        None => config.secret_from(random),
+    }
+}
+
+/// A guessing game session and what is known about its connection
+pub struct Session {
+    pub game: GuessingGame,
+    /// Milliseconds since boot when the WebSocket was opened
+    pub connected_at_ms: u64,
+    pub remote_ip: Option<[u8; 4]>,
+    /// Payload bytes of all message frames received
+    pub bytes_received: usize,
+    pub messages_received: u32,
+    /// Negotiated on connect, plain text unless the client asked for JSON
+    pub protocol: WsProtocol,
+    /// Round being played, from 1, only advances when `GameConfig::rounds` > 1
+    pub round: u8,
+    pub rounds_won: u8,
+    /// Guesses of the finished rounds
+    pub total_guesses_all_rounds: u32,
+    /// Messages counted against the rate limit in the current window
+    message_count: u32,
+    /// Milliseconds since boot when the current rate limit window started
+    window_start_ms: u64,
+}
+
+impl Session {
+    pub fn new(game: GuessingGame, connected_at_ms: u64, remote_ip: Option<[u8; 4]>) -> Self {
+        Self {
+            game,
+            connected_at_ms,
+            remote_ip,
+            bytes_received: 0,
+            messages_received: 0,
+            protocol: WsProtocol::default(),
+            round: 1,
+            rounds_won: 0,
+            total_guesses_all_rounds: 0,
+            message_count: 0,
+            window_start_ms: connected_at_ms,
+        }
+    }
+
+    /// Count a message frame with a payload of `len` bytes
+    pub fn record_message(&mut self, len: usize) {
+        self.messages_received += 1;
+        self.bytes_received += len;
+    }
+
+    /// Count a message against the rate limit at `now_ms`
+    /// Returns false once more than `WS_MAX_MSG_PER_SEC` messages arrived
+    /// within one second
+    pub fn within_rate_limit(&mut self, now_ms: u64) -> bool {
+        if now_ms.saturating_sub(self.window_start_ms) >= 1000 {
+            self.window_start_ms = now_ms;
+            self.message_count = 0;
+        }
+        self.message_count += 1;
+        self.message_count <= WS_MAX_MSG_PER_SEC
+    }
+
+    /// Record the end of the current round, won or out of `guesses`
+    /// Single-round sessions keep playing as before and get `None`. Otherwise
+    /// the game restarts with `next_secret` while rounds are left, and the
+    /// returned message announces the next round or the final score.
+    pub fn finish_round(
+        &mut self,
+        won: bool,
+        guesses: u32,
+        rounds: u8,
+        next_secret: u32,
+    ) -> Option<WsMessage> {
+        if rounds <= 1 {
+            return None;
+        }
+        self.rounds_won += u8::from(won);
+        self.total_guesses_all_rounds += guesses;
+        if self.round < rounds {
+            self.game.reset(next_secret);
+            self.round += 1;
+            Some(WsMessage::RoundComplete {
+                round: self.round - 1,
+                rounds,
+            })
+        } else {
+            Some(WsMessage::MatchOver {
+                rounds_won: self.rounds_won,
+                rounds,
+                total_guesses: self.total_guesses_all_rounds,
+            })
+        }
+    }
+
+    /// Milliseconds the session has been open at `now_ms`
+    pub fn duration_ms(&self, now_ms: u64) -> u64 {
+        now_ms.saturating_sub(self.connected_at_ms)
+    }
+
+    /// JSON view of the session for admin listings, without the secret
+    pub fn to_summary_json(&self, session_id: i32, now_ms: u64) -> String {
+        let ip = match self.remote_ip {
+            Some(ip) => format!(r#""{}""#, Ipv4Addr::from(ip)),
+            None => "null".to_string(),
+        };
+        // Splice the connection fields in front of the game's own fields
+        let game = self.game.to_summary_json();
+        format!(
+            r#"{{"session_id":{},"ip":{},"connected_at_ms":{},"duration_ms":{},"messages_received":{},"bytes_received":{},"round":{},"rounds_won":{},{}"#,
+            session_id,
+            ip,
+            self.connected_at_ms,
+            self.duration_ms(now_ms),
+            self.messages_received,
+            self.bytes_received,
+            self.round,
+            self.rounds_won,
+            &game[1..]
+        )
+    }
+}
+
+/// Games of the open sessions, persisted to NVS flash
+pub struct SessionStore {
+    nvs: Option<EspNvs<NvsDefault>>,
+}
+
+impl SessionStore {
+    /// Open the store and return the games saved before the last reboot, by
+    /// client IP
+    /// Falls back to a store that keeps nothing if NVS is unavailable
+    pub fn load(partition: EspDefaultNvsPartition) -> (Self, BTreeMap<[u8; 4], GuessingGame>) {
+        let nvs = match EspNvs::new(partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open sessions NVS namespace: {:?}", e);
+                warn!("Continuing without session persistence...");
+                return (Self { nvs: None }, BTreeMap::new());
+            }
+        };
+
+        let mut buf = [0u8; BLOB_LEN];
+        let games = match nvs.get_blob(NVS_KEY, &mut buf) {
+            Ok(Some(data)) => decode(data).unwrap_or_else(|| {
+                warn!("Session data in NVS is corrupt, ignoring it");
+                BTreeMap::new()
+            }),
+            Ok(None) => BTreeMap::new(),
+            Err(e) => {
+                warn!("Failed to read sessions from NVS: {:?}", e);
+                BTreeMap::new()
+            }
+        };
+        info!("Restored {} sessions from NVS", games.len());
+        (Self { nvs: Some(nvs) }, games)
+    }
+
+    /// Persist the games of all open sessions with a known client IP
+    /// Each call rewrites flash, so only call it when a session was added or
+    /// removed, or a game finished
+    pub fn save(&mut self, sessions: &BTreeMap<i32, Session>) {
+        let Some(nvs) = self.nvs.as_mut() else {
+            return;
+        };
+        let data = encode(
+            sessions
+                .values()
+                .filter_map(|session| Some((session.remote_ip?, &session.game))),
+        );
+        if let Err(e) = nvs.set_blob(NVS_KEY, &data) {
+            warn!("Failed to persist sessions to NVS: {:?}", e);
+        }
+    }
+}
+
+fn encode<'a>(games: impl Iterator<Item = ([u8; 4], &'a GuessingGame)>) -> Vec<u8> {
+    let mut data = vec![0; CRC32_LEN];
+    data.extend_from_slice(&[FORMAT_VERSION, 0]);
+    for (ip, game) in games.take(MAX_WS_SESSIONS) {
+        data.extend_from_slice(&ip);
+        data.extend_from_slice(&game.serialize());
+        data[CRC32_LEN + 1] += 1;
+    }
+    write_crc32(&mut data);
+    data
+}
+
+fn decode(data: &[u8]) -> Option<BTreeMap<[u8; 4], GuessingGame>> {
+    let data = check_crc32(data)?;
+    if data.len() < HEADER_LEN || data[0] != FORMAT_VERSION {
+        return None;
+    }
+    let count = data[1] as usize;
+    if count > MAX_WS_SESSIONS || data.len() != HEADER_LEN + count * ENTRY_LEN {
+        return None;
+    }
+    data[HEADER_LEN..]
+        .chunks_exact(ENTRY_LEN)
+        .map(|entry| {
+            let ip = entry[..4].try_into().unwrap();
+            let game = GuessingGame::deserialize(entry[4..].try_into().unwrap())?;
+            Some((ip, game))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_message() {
+        let mut session = Session::new(GuessingGame::new(42), 1000, None);
+        session.record_message(2);
+        session.record_message(3);
+        assert_eq!(session.messages_received, 2);
+        assert_eq!(session.bytes_received, 5);
+        assert_eq!(session.duration_ms(4500), 3500);
+        assert_eq!(session.duration_ms(0), 0);
+    }
+
+    #[test]
+    fn test_negotiate_protocol() {
+        assert!(WsProtocol::negotiate("guess-json-v1").json);
+        assert!(WsProtocol::negotiate("chat, guess-json-v1").json);
+        assert!(!WsProtocol::negotiate("").json);
+        assert!(!WsProtocol::negotiate("guess-json-v2").json);
+    }
+
+    #[test]
+    fn test_secret_scope_from_name() {
+        assert_eq!(SecretScope::from_name(None), Ok(SecretScope::Next));
+        assert_eq!(SecretScope::from_name(Some("next")), Ok(SecretScope::Next));
+        assert_eq!(SecretScope::from_name(Some("all")), Ok(SecretScope::All));
+        assert!(SecretScope::from_name(Some("some")).is_err());
+    }
+
+    #[test]
+    fn test_new_session_secret_uses_override_once() {
+        let config = GameConfig {
+            min: 1,
+            max: 100,
+            ..GameConfig::default()
+        };
+        let mut secret_override = Some(42);
+        assert_eq!(new_session_secret(&mut secret_override, &config, 6), 42);
+        assert_eq!(secret_override, None);
+        assert_eq!(new_session_secret(&mut secret_override, &config, 6), 7);
+
+        // Out of range after the range changed
+        secret_override = Some(500);
+        assert_eq!(new_session_secret(&mut secret_override, &config, 6), 7);
+        assert_eq!(secret_override, None);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut session = Session::new(GuessingGame::new(42), 1000, None);
+        for _ in 0..WS_MAX_MSG_PER_SEC {
+            assert!(session.within_rate_limit(1500));
+        }
+        assert!(!session.within_rate_limit(1999));
+        // A new window starts a second after the last one did
+        assert!(session.within_rate_limit(2000));
+        for _ in 1..WS_MAX_MSG_PER_SEC {
+            assert!(session.within_rate_limit(2999));
+        }
+        assert!(!session.within_rate_limit(2999));
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let mut session = Session::new(GuessingGame::new(42), 1000, Some([192, 168, 71, 2]));
+        session.record_message(2);
+        session.game.guess(10);
+        assert_eq!(
+            session.to_summary_json(54, 3000),
+            r#"{"session_id":54,"ip":"192.168.71.2","connected_at_ms":1000,"duration_ms":2000,"messages_received":1,"bytes_received":2,"round":1,"rounds_won":0,"guesses":1,"remaining":9,"done":false}"#
+        );
+        let session = Session::new(GuessingGame::new(42), 0, None);
+        assert!(session.to_summary_json(1, 0).contains(r#""ip":null"#));
+    }
+
+    #[test]
+    fn test_single_round_keeps_playing() {
+        let mut session = Session::new(GuessingGame::new(42), 0, None);
+        session.game.guess(42);
+        assert_eq!(session.finish_round(true, 1, 1, 7), None);
+        assert_eq!(session.round, 1);
+        assert_eq!(session.rounds_won, 0);
+    }
+
+    #[test]
+    fn test_round_transitions() {
+        let mut session = Session::new(GuessingGame::new(42), 0, None);
+        session.game.guess(10);
+        session.game.guess(42);
+        assert_eq!(
+            session.finish_round(true, 2, 3, 7),
+            Some(WsMessage::RoundComplete {
+                round: 1,
+                rounds: 3
+            })
+        );
+        assert_eq!(session.round, 2);
+        assert_eq!(session.game.secret(), 7);
+        assert!(session.game.history().is_empty());
+
+        // Running out of guesses loses the round but the match goes on
+        assert_eq!(
+            session.finish_round(false, 10, 3, 9),
+            Some(WsMessage::RoundComplete {
+                round: 2,
+                rounds: 3
+            })
+        );
+        assert_eq!(
+            session.finish_round(true, 4, 3, 11),
+            Some(WsMessage::MatchOver {
+                rounds_won: 2,
+                rounds: 3,
+                total_guesses: 16
+            })
+        );
+        assert_eq!(session.round, 3);
+        assert_eq!(
+            WsMessage::MatchOver {
+                rounds_won: 2,
+                rounds: 3,
+                total_guesses: 16
+            }
+            .to_text(),
+            "You won 2 of 3 rounds, total guesses: 16"
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let mut first = GuessingGame::new(42);
+        first.guess(10);
+        let second = GuessingGame::new(7);
+        let data = encode([([192, 168, 71, 2], &first), ([192, 168, 71, 3], &second)].into_iter());
+        assert_eq!(data.len(), CRC32_LEN + HEADER_LEN + 2 * ENTRY_LEN);
+
+        let games = decode(&data).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[&[192, 168, 71, 2]].serialize(), first.serialize());
+        assert_eq!(games[&[192, 168, 71, 3]].serialize(), second.serialize());
+        assert!(decode(&encode(std::iter::empty())).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_rejects_corrupt() {
+        let data = encode([([192, 168, 71, 2], &GuessingGame::new(42))].into_iter());
+        assert!(decode(&[]).is_none());
+        assert!(decode(&data[..data.len() - 1]).is_none());
+        let mut bad_version = data.clone();
+        bad_version[CRC32_LEN] = FORMAT_VERSION + 1;
+        write_crc32(&mut bad_version);
+        assert!(decode(&bad_version).is_none());
+        let mut bad_game = data.clone();
+        bad_game[CRC32_LEN + HEADER_LEN + 4] = 0;
+        write_crc32(&mut bad_game);
+        assert!(decode(&bad_game).is_none());
+        let mut bad_crc = data;
+        bad_crc[CRC32_LEN + HEADER_LEN] ^= 1;
+        assert!(decode(&bad_crc).is_none());
+    }
+}
@@ -0,0 +1,192 @@
+//! State shared by the HTTP handlers and the background tasks
+//!
+//! Everything lives in one `AppState` behind a single mutex, handed around
+//! as clones of a `SharedState`. The accessors lock the whole state and
+//! return a guard for one field, which holds the lock until it is dropped.
+//!
+//! Lock ordering:
+//!
+//! - Hold at most one guard of the state at a time. The mutex is not
+//!   reentrant, so calling an accessor while another guard is alive
+//!   deadlocks the task; use `lock` when several fields are needed at once.
+//! - The session store and the leaderboard may be locked while holding the
+//!   state, never the other way round. The heartbeat registry, the rate
+//!   limiters and the statics of other modules are only ever locked briefly
+//!   on their own and can be taken either way.
+//! - Release the state before sending on a WebSocket or sleeping, other
+//!   handlers and tasks wait for it in the meantime.
+
+#[cfg(feature = "game")]
+use std::collections::HashSet;
+use std::{
+    collections::BTreeMap,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+#[cfg(feature = "game")]
+use crate::config::GameConfig;
+use crate::math_quiz::MathQuiz;
+use crate::rssi::RssiHistory;
+#[cfg(feature = "game")]
+use crate::session::Session;
+use crate::word_guess::WordGuess;
+
+/// Everything the handlers and background tasks share
+#[derive(Default)]
+pub struct AppState {
+    /// Guessing game state per /ws/guess session
+    #[cfg(feature = "game")]
+    pub sessions: BTreeMap<i32, Session>,
+    /// Game range shared between the config endpoints and the game sessions
+    #[cfg(feature = "game")]
+    pub game_config: GameConfig,
+    /// Secret an operator picked for the next new /ws/guess session
+    #[cfg(feature = "game")]
+    pub secret_override: Option<u32>,
+    /// Guessing game sessions an admin asked to close, checked by /ws/guess
+    #[cfg(feature = "game")]
+    pub kick_list: HashSet<i32>,
+    /// Recent polled RSSI readings, exported as CSV on /rssi/history
+    pub rssi_history: RssiHistory,
+    /// Bytes echoed per /ws/echo session
+    pub echoed_bytes: BTreeMap<i32, usize>,
+    /// Math quiz state per /ws/quiz session
+    pub math_quizzes: BTreeMap<i32, MathQuiz>,
+    /// Word game state per /ws/wordguess session
+    pub word_games: BTreeMap<i32, WordGuess>,
+    /// Open WebSocket sockets across all endpoints, with the endpoint each
+    /// one was opened on
+    pub ws_sockets: BTreeMap<i32, &'static str>,
+}
+
+/// Handle to the `AppState`, cheap to clone into handler closures
+#[derive(Clone, Default)]
+pub struct SharedState(Arc<Mutex<AppState>>);
+
+/// Guard for one field of the locked `AppState`
+pub struct StateRef<'a, T> {
+    guard: MutexGuard<'a, AppState>,
+    field: fn(&AppState) -> &T,
+    field_mut: fn(&mut AppState) -> &mut T,
+}
+
+impl<T> Deref for StateRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        (self.field)(&self.guard)
+    }
+}
+
+impl<T> DerefMut for StateRef<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        (self.field_mut)(&mut self.guard)
+    }
+}
+
+impl SharedState {
+    /// Lock the whole state, for handlers that need several fields
+    pub fn lock(&self) -> MutexGuard<'_, AppState> {
+        self.0.lock().unwrap()
+    }
+
+    fn field<T>(
+        &self,
+        field: fn(&AppState) -> &T,
+        field_mut: fn(&mut AppState) -> &mut T,
+    ) -> StateRef<'_, T> {
+        StateRef {
+            guard: self.lock(),
+            field,
+            field_mut,
+        }
+    }
+
+    #[cfg(feature = "game")]
+    pub fn sessions(&self) -> StateRef<'_, BTreeMap<i32, Session>> {
+        self.field(|state| &state.sessions, |state| &mut state.sessions)
+    }
+
+    #[cfg(feature = "game")]
+    pub fn game_config(&self) -> StateRef<'_, GameConfig> {
+        self.field(|state| &state.game_config, |state| &mut state.game_config)
+    }
+
+    #[cfg(feature = "game")]
+    pub fn secret_override(&self) -> StateRef<'_, Option<u32>> {
+        self.field(
+            |state| &state.secret_override,
+            |state| &mut state.secret_override,
+        )
+    }
+
+    pub fn rssi_history(&self) -> StateRef<'_, RssiHistory> {
+        self.field(|state| &state.rssi_history, |state| &mut state.rssi_history)
+    }
+
+    pub fn echoed_bytes(&self) -> StateRef<'_, BTreeMap<i32, usize>> {
+        self.field(|state| &state.echoed_bytes, |state| &mut state.echoed_bytes)
+    }
+
+    pub fn math_quizzes(&self) -> StateRef<'_, BTreeMap<i32, MathQuiz>> {
+        self.field(|state| &state.math_quizzes, |state| &mut state.math_quizzes)
+    }
+
+    pub fn word_games(&self) -> StateRef<'_, BTreeMap<i32, WordGuess>> {
+        self.field(|state| &state.word_games, |state| &mut state.word_games)
+    }
+
+    pub fn open_ws_sessions(&self) -> u32 {
+        self.lock().ws_sockets.len() as u32
+    }
+
+    /// Count a WebSocket newly opened on `endpoint`, returning the new total
+    pub fn ws_session_opened(&self, session: i32, endpoint: &'static str) -> u32 {
+        let mut state = self.lock();
+        state.ws_sockets.insert(session, endpoint);
+        state.ws_sockets.len() as u32
+    }
+
+    /// Count a closed WebSocket, returning the new total
+    /// ESP-IDF runs every close handler for every socket it closes, plain
+    /// HTTP ones included, so this returns `None` unless the socket was
+    /// opened on `endpoint`
+    pub fn ws_session_closed(&self, session: i32, endpoint: &'static str) -> Option<u32> {
+        let mut state = self.lock();
+        if state.ws_sockets.get(&session) != Some(&endpoint) {
+            return None;
+        }
+        state.ws_sockets.remove(&session);
+        Some(state.ws_sockets.len() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_state() {
+        let state = SharedState::default();
+        let for_handler = state.clone();
+        for_handler.echoed_bytes().insert(3, 42);
+        assert_eq!(state.echoed_bytes().get(&3), Some(&42));
+        // The guard of the accessor above is gone, so locking again works
+        assert_eq!(state.lock().echoed_bytes.len(), 1);
+    }
+
+    #[test]
+    fn test_ws_session_count() {
+        let state = SharedState::default();
+        assert_eq!(state.ws_session_opened(54, "/ws/echo"), 1);
+        assert_eq!(state.ws_session_opened(55, "/ws/quiz"), 2);
+        // Sockets closed elsewhere are not counted
+        assert_eq!(state.ws_session_closed(56, "/ws/echo"), None);
+        assert_eq!(state.ws_session_closed(55, "/ws/echo"), None);
+        assert_eq!(state.ws_session_closed(55, "/ws/quiz"), Some(1));
+        assert_eq!(state.ws_session_closed(55, "/ws/quiz"), None);
+        assert_eq!(state.ws_session_closed(54, "/ws/echo"), Some(0));
+        assert_eq!(state.open_ws_sessions(), 0);
+    }
+}
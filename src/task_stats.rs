@@ -0,0 +1,162 @@
+//! FreeRTOS task list for GET /metrics, with the `task-stats` feature, and
+//! the stack report of GET /admin/debug/stack
+//!
+//! Needs `CONFIG_FREERTOS_USE_TRACE_FACILITY`, which sdkconfig.defaults
+//! enables.
+
+use esp_idf_svc::sys::{self, TaskStatus_t};
+use log::*;
+use std::{borrow::Cow, ffi::CStr};
+
+use crate::utils::json_escape;
+
+// Upper bound on the tasks listed, so the status array stays small enough
+// for the HTTP server task's stack
+const MAX_TASKS: usize = 16;
+// Tasks with fewer words of stack left than this are reported at risk
+const AT_RISK_HWM_WORDS: u32 = 256;
+// Extra stack suggested for a task at risk, in bytes like every stack size
+// given to ESP-IDF
+const RECOMMENDED_STACK_INCREASE: u32 = 512;
+// ESP-IDF's FreeRTOS counts stack in bytes rather than words
+const STACK_WORD_LEN: u32 = 4;
+
+/// Fill `tasks` with the status of every task
+/// `None` if there are more than `MAX_TASKS`
+fn system_state(tasks: &mut [TaskStatus_t; MAX_TASKS]) -> Option<&[TaskStatus_t]> {
+    // SAFETY: FreeRTOS fills at most `MAX_TASKS` entries and returns 0 if
+    // the array is too small for all tasks
+    let count = unsafe {
+        sys::uxTaskGetSystemState(
+            tasks.as_mut_ptr(),
+            MAX_TASKS as sys::UBaseType_t,
+            core::ptr::null_mut(),
+        )
+    } as usize;
+    if count == 0 {
+        warn!("More than {} tasks, not listing them", MAX_TASKS);
+        return None;
+    }
+    Some(&tasks[..count])
+}
+
+fn task_name(task: &TaskStatus_t) -> Cow<'_, str> {
+    // SAFETY: task names are NUL-terminated and live as long as the task
+    unsafe { CStr::from_ptr(task.pcTaskName) }.to_string_lossy()
+}
+
+#[cfg(feature = "task-stats")]
+fn state_name(state: sys::eTaskState) -> &'static str {
+    match state {
+        sys::eTaskState_eRunning => "running",
+        sys::eTaskState_eReady => "ready",
+        sys::eTaskState_eBlocked => "blocked",
+        sys::eTaskState_eSuspended => "suspended",
+        sys::eTaskState_eDeleted => "deleted",
+        _ => "invalid",
+    }
+}
+
+#[cfg(feature = "task-stats")]
+fn task_json(name: &str, state: sys::eTaskState, priority: u32, stack_hwm: u32) -> String {
+    format!(
+        r#"{{"name":"{}","state":"{}","priority":{},"stack_hwm":{}}}"#,
+        json_escape(name),
+        state_name(state),
+        priority,
+        stack_hwm
+    )
+}
+
+/// JSON array with the name, state, priority and stack high-water mark (in
+/// bytes) of every task, `null` if there are more than `MAX_TASKS`
+#[cfg(feature = "task-stats")]
+pub fn to_json() -> String {
+    let mut tasks = [TaskStatus_t::default(); MAX_TASKS];
+    let Some(tasks) = system_state(&mut tasks) else {
+        return "null".to_string();
+    };
+
+    let tasks: Vec<String> = tasks
+        .iter()
+        .map(|task| {
+            task_json(
+                &task_name(task),
+                task.eCurrentState,
+                task.uxCurrentPriority,
+                task.usStackHighWaterMark,
+            )
+        })
+        .collect();
+    format!("[{}]", tasks.join(","))
+}
+
+fn stack_json(name: &str, priority: u32, hwm_words: u32) -> String {
+    let at_risk = hwm_words < AT_RISK_HWM_WORDS;
+    let recommendation = if at_risk {
+        format!(
+            r#","recommended_stack_increase":{}"#,
+            RECOMMENDED_STACK_INCREASE
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        r#"{{"name":"{}","priority":{},"stack_hwm_words":{},"at_risk":{}{}}}"#,
+        json_escape(name),
+        priority,
+        hwm_words,
+        at_risk,
+        recommendation
+    )
+}
+
+/// JSON array with the name, priority and stack high-water mark in words of
+/// every task, flagging those close to overflowing their stack
+/// `None` if there are more than `MAX_TASKS`
+pub fn stack_report_json() -> Option<String> {
+    let mut tasks = [TaskStatus_t::default(); MAX_TASKS];
+    let tasks: Vec<String> = system_state(&mut tasks)?
+        .iter()
+        .map(|task| {
+            let hwm_words = task.usStackHighWaterMark / STACK_WORD_LEN;
+            if hwm_words < AT_RISK_HWM_WORDS {
+                warn!(
+                    "Task {} has {} words of stack left",
+                    task_name(task),
+                    hwm_words
+                );
+            }
+            stack_json(&task_name(task), task.uxCurrentPriority, hwm_words)
+        })
+        .collect();
+    Some(format!("[{}]", tasks.join(",")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "task-stats")]
+    fn test_task_json() {
+        assert_eq!(
+            task_json("httpd", sys::eTaskState_eBlocked, 5, 1234),
+            r#"{"name":"httpd","state":"blocked","priority":5,"stack_hwm":1234}"#
+        );
+        assert_eq!(state_name(sys::eTaskState_eRunning), "running");
+        assert_eq!(state_name(42), "invalid");
+    }
+
+    #[test]
+    fn test_stack_json() {
+        assert_eq!(
+            stack_json("main", 1, 1024),
+            r#"{"name":"main","priority":1,"stack_hwm_words":1024,"at_risk":false}"#
+        );
+        assert_eq!(
+            stack_json("httpd", 5, 100),
+            r#"{"name":"httpd","priority":5,"stack_hwm_words":100,"at_risk":true,"recommended_stack_increase":512}"#
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Tournament mode: several players race to guess the same secret
+//!
+//! Every player gets their own `GuessingGame` with the shared secret. The
+//! player who finds it in the fewest attempts wins; on a tie the one who
+//! finished first wins.
+
+use core::cmp::Ordering;
+use log::*;
+
+use crate::config::{GameConfig, MAX_TOURNAMENT_PLAYERS};
+use crate::guessing_game::{GuessingGame, WsMessage};
+
+/// How a player's game ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Finish {
+    /// Found the secret; `order` counts finishes across the tournament
+    Won { attempts: u32, order: u32 },
+    /// Ran out of guesses, gave up or disconnected
+    Lost,
+}
+
+struct Player {
+    session: i32,
+    game: GuessingGame,
+    finish: Option<Finish>,
+}
+
+/// A single round with up to `MAX_TOURNAMENT_PLAYERS` players
+pub struct Tournament {
+    config: GameConfig,
+    secret: u32,
+    players: Vec<Player>,
+    finishes: u32,
+}
+
+impl Tournament {
+    /// Start a tournament for the given sessions
+    /// Players beyond `MAX_TOURNAMENT_PLAYERS` are left out
+    pub fn new(player_ids: &[i32], secret: u32, config: &GameConfig) -> Self {
+        if player_ids.len() > MAX_TOURNAMENT_PLAYERS {
+            warn!(
+                "Tournament limited to {} players, leaving out {}",
+                MAX_TOURNAMENT_PLAYERS,
+                player_ids.len() - MAX_TOURNAMENT_PLAYERS
+            );
+        }
+        let players = player_ids
+            .iter()
+            .take(MAX_TOURNAMENT_PLAYERS)
+            .map(|&session| Player {
+                session,
+                game: GuessingGame::from_config(secret, config),
+                finish: None,
+            })
+            .collect();
+        info!("Starting tournament with players {:?}", player_ids);
+        Self {
+            config: *config,
+            secret,
+            players,
+            finishes: 0,
+        }
+    }
+
+    /// Game settings the tournament was started with
+    pub fn config(&self) -> &GameConfig {
+        &self.config
+    }
+
+    /// Session IDs of all players, finished or not
+    pub fn player_ids(&self) -> Vec<i32> {
+        self.players.iter().map(|p| p.session).collect()
+    }
+
+    /// Make a guess for a player and build the reply
+    /// Finding the secret or running out of guesses records the finish
+    /// Returns `None` if the session is not in the tournament or already finished
+    pub fn play(&mut self, session_id: i32, guess: u32) -> Option<WsMessage> {
+        let player = self.player_mut(session_id).filter(|p| p.finish.is_none())?;
+        let game = &mut player.game;
+        let reply = match game.guess(guess) {
+            (Ordering::Equal, n, _) => WsMessage::Win {
+                secret: game.secret(),
+                attempts: n,
+                history: game.history().to_vec(),
+            },
+            (_, _, true) => WsMessage::GameOver {
+                secret: game.secret(),
+            },
+            (ordering, n, false) => WsMessage::result(
+                game.difficulty(),
+                ordering,
+                n,
+                game.guesses_remaining(),
+                guess.abs_diff(game.secret()),
+            ),
+        };
+        match reply {
+            WsMessage::Win { attempts, .. } => self.record_win(session_id, attempts),
+            WsMessage::GameOver { .. } => self.record_loss(session_id),
+            _ => false,
+        };
+        Some(reply)
+    }
+
+    /// Record that a player found the secret
+    /// Returns false if the player is unknown or already finished
+    pub fn record_win(&mut self, session_id: i32, attempts: u32) -> bool {
+        let order = self.finishes;
+        let Some(player) = self.player_mut(session_id) else {
+            return false;
+        };
+        if player.finish.is_some() {
+            return false;
+        }
+        player.finish = Some(Finish::Won { attempts, order });
+        self.finishes += 1;
+        info!(
+            "Tournament: session {} won in {} attempts",
+            session_id, attempts
+        );
+        true
+    }
+
+    /// Record that a player is out without finding the secret
+    /// Returns false if the player is unknown or already finished
+    pub fn record_loss(&mut self, session_id: i32) -> bool {
+        let Some(player) = self.player_mut(session_id) else {
+            return false;
+        };
+        if player.finish.is_some() {
+            return false;
+        }
+        player.finish = Some(Finish::Lost);
+        self.finishes += 1;
+        info!("Tournament: session {} is out", session_id);
+        true
+    }
+
+    /// Whether every player has finished
+    pub fn is_complete(&self) -> bool {
+        self.players.iter().all(|p| p.finish.is_some())
+    }
+
+    /// Best finisher so far: fewest attempts, then earliest finish
+    pub fn winner(&self) -> Option<i32> {
+        self.players
+            .iter()
+            .filter_map(|p| match p.finish {
+                Some(Finish::Won { attempts, order }) => Some(((attempts, order), p.session)),
+                _ => None,
+            })
+            .min()
+            .map(|(_, session)| session)
+    }
+
+    /// Attempts the winner needed, if there is one
+    pub fn winning_attempts(&self) -> Option<u32> {
+        let winner = self.winner()?;
+        self.players.iter().find_map(|p| match p.finish {
+            Some(Finish::Won { attempts, .. }) if p.session == winner => Some(attempts),
+            _ => None,
+        })
+    }
+
+    /// JSON announcement sent to every player when the tournament starts
+    pub fn start_message(&self) -> String {
+        format!(
+            r#"{{"result":"tournament_start","players":{},"min":{},"max":{},"max_guesses":{}}}"#,
+            self.players.len(),
+            self.config.min,
+            self.config.max,
+            self.config.max_guesses
+        )
+    }
+
+    /// JSON announcement sent to every player once all of them have finished
+    pub fn result_message(&self) -> String {
+        match (self.winner(), self.winning_attempts()) {
+            (Some(winner), Some(attempts)) => format!(
+                r#"{{"result":"tournament_over","winner":{},"attempts":{},"secret":{}}}"#,
+                winner, attempts, self.secret
+            ),
+            _ => format!(
+                r#"{{"result":"tournament_over","winner":null,"secret":{}}}"#,
+                self.secret
+            ),
+        }
+    }
+
+    fn player_mut(&mut self, session_id: i32) -> Option<&mut Player> {
+        self.players.iter_mut().find(|p| p.session == session_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_caps_players() {
+        let ids: Vec<i32> = (0..MAX_TOURNAMENT_PLAYERS as i32 + 2).collect();
+        let tournament = Tournament::new(&ids, 42, &GameConfig::default());
+        assert_eq!(tournament.player_ids().len(), MAX_TOURNAMENT_PLAYERS);
+    }
+
+    #[test]
+    fn test_play_records_finishes() {
+        let mut tournament = Tournament::new(&[1, 2], 42, &GameConfig::default());
+        assert!(matches!(
+            tournament.play(2, 50),
+            Some(WsMessage::Result {
+                ordering: Ordering::Greater,
+                attempt: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tournament.play(1, 42),
+            Some(WsMessage::Win { attempts: 1, .. })
+        ));
+        // Finished players and strangers can't guess
+        assert_eq!(tournament.play(1, 42), None);
+        assert_eq!(tournament.play(3, 42), None);
+        assert!(matches!(
+            tournament.play(2, 42),
+            Some(WsMessage::Win { attempts: 2, .. })
+        ));
+        assert!(tournament.is_complete());
+        assert_eq!(tournament.winner(), Some(1));
+    }
+
+    #[test]
+    fn test_play_out_of_guesses() {
+        let config = GameConfig {
+            max_guesses: 1,
+            ..GameConfig::default()
+        };
+        let mut tournament = Tournament::new(&[1], 42, &config);
+        assert_eq!(
+            tournament.play(1, 50),
+            Some(WsMessage::GameOver { secret: 42 })
+        );
+        assert!(tournament.is_complete());
+        assert_eq!(tournament.winner(), None);
+    }
+
+    #[test]
+    fn test_fewest_attempts_wins() {
+        let mut tournament = Tournament::new(&[1, 2, 3], 42, &GameConfig::default());
+        assert!(tournament.record_win(1, 5));
+        assert!(!tournament.is_complete());
+        assert!(tournament.record_win(2, 3));
+        assert!(tournament.record_loss(3));
+        assert!(tournament.is_complete());
+        assert_eq!(tournament.winner(), Some(2));
+        assert_eq!(tournament.winning_attempts(), Some(3));
+        assert_eq!(
+            tournament.result_message(),
+            r#"{"result":"tournament_over","winner":2,"attempts":3,"secret":42}"#
+        );
+    }
+
+    #[test]
+    fn test_tie_goes_to_first_finisher() {
+        let mut tournament = Tournament::new(&[1, 2], 42, &GameConfig::default());
+        tournament.record_win(2, 4);
+        tournament.record_win(1, 4);
+        assert_eq!(tournament.winner(), Some(2));
+    }
+
+    #[test]
+    fn test_record_rejects_unknown_and_repeat() {
+        let mut tournament = Tournament::new(&[1], 42, &GameConfig::default());
+        assert!(!tournament.record_win(9, 1));
+        assert!(tournament.record_loss(1));
+        assert!(!tournament.record_win(1, 1));
+        assert!(tournament.is_complete());
+        assert_eq!(tournament.winner(), None);
+    }
+}
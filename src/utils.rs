@@ -1,21 +1,459 @@
 //! Utility functions
 
+use embedded_svc::http::Headers;
+use esp_idf_svc::hal::delay::FreeRtos;
+use esp_idf_svc::http::server::{EspHttpConnection, Request};
+use esp_idf_svc::sys::{EspError, ESP_ERR_INVALID_ARG, ESP_ERR_INVALID_SIZE};
 use esp_idf_svc::systime::EspSystemTime;
 use log::*;
+#[cfg(feature = "game")]
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 
-/// Generate a pseudo-random number using system time
+/// Generate a random number using the hardware RNG
 pub fn rand() -> u32 {
-    let nanos = EspSystemTime::now(&EspSystemTime {}).subsec_nanos();
-    let result = nanos / 65537;
-    debug!(
-        "Generated random number: {} (from nanos: {})",
-        result, nanos
-    );
+    // esp_random() is fed by RF noise, so it only yields true random numbers
+    // while Wi-Fi or BT is enabled (always the case once the AP is up)
+    let result = unsafe { esp_idf_svc::sys::esp_random() };
+    debug!("Generated random number: {} (from hardware RNG)", result);
     result
 }
 
+/// Random ID of an HTTP request, 8 hex digits, for correlating log lines
+pub fn generate_request_id() -> String {
+    format!("{:08x}", rand())
+}
+
+/// Milliseconds elapsed since boot
+pub fn now_ms() -> u64 {
+    EspSystemTime::now(&EspSystemTime {}).as_millis() as u64
+}
+
+/// Microseconds elapsed since boot
+pub fn now_us() -> u64 {
+    EspSystemTime::now(&EspSystemTime {}).as_micros() as u64
+}
+
+/// Call `f` up to `max_attempts` times, sleeping `delay_ms` between attempts
+/// Returns the first `Ok`, or the last `Err` once every attempt failed
+pub fn retry<T, E, F>(mut f: F, max_attempts: u32, delay_ms: u32) -> Result<T, E>
+where
+    F: FnMut() -> Result<T, E>,
+    E: core::fmt::Debug,
+{
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= max_attempts => return Err(e),
+            Err(e) => {
+                debug!(
+                    "Attempt {} of {} failed: {:?}, retrying in {} ms",
+                    attempt, max_attempts, e, delay_ms
+                );
+                FreeRtos::delay_ms(delay_ms);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Get a request header by name (case-insensitive)
+pub fn get_request_header<'a>(req: &'a impl Headers, name: &str) -> Option<&'a str> {
+    req.header(name)
+}
+
+/// Read the whole body of a POST request, at most `max_bytes` of UTF-8
+/// Fails with `ESP_ERR_INVALID_SIZE` if the body is longer and with
+/// `ESP_ERR_INVALID_ARG` if it isn't UTF-8, `ServerError::from_body_error`
+/// turns these into the matching responses
+pub fn read_json_body(
+    req: &mut Request<&mut EspHttpConnection<'_>>,
+    max_bytes: usize,
+) -> Result<String, EspError> {
+    let content_len = req.content_len().unwrap_or(0) as usize;
+    if content_len > max_bytes {
+        debug!("Body of {} bytes exceeds {} bytes", content_len, max_bytes);
+        return Err(EspError::from_infallible::<ESP_ERR_INVALID_SIZE>());
+    }
+
+    let mut buf = vec![0u8; content_len];
+    let mut len = 0;
+    while len < content_len {
+        let read = req.read(&mut buf[len..]).map_err(|e| e.0)?;
+        if read == 0 {
+            break;
+        }
+        len += read;
+    }
+    buf.truncate(len);
+    String::from_utf8(buf).map_err(|e| {
+        debug!("Body is not UTF-8: {:?}", e);
+        EspError::from_infallible::<ESP_ERR_INVALID_ARG>()
+    })
+}
+
+/// Find the raw value of `key` in a JSON object, e.g. `"text"` or `42`
+/// Scans byte by byte and skips over other values, so a key that appears
+/// inside a string or a nested object is not mistaken for the one wanted
+fn json_value<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let bytes = json.as_bytes();
+    let mut pos = skip_whitespace(bytes, 0);
+    if bytes.get(pos) != Some(&b'{') {
+        return None;
+    }
+    pos += 1;
+    loop {
+        pos = skip_whitespace(bytes, pos);
+        if bytes.get(pos) != Some(&b'"') {
+            return None;
+        }
+        let key_end = skip_json_value(bytes, pos)?;
+        let found = &json[pos + 1..key_end - 1] == key;
+        pos = skip_whitespace(bytes, key_end);
+        if bytes.get(pos) != Some(&b':') {
+            return None;
+        }
+        let value_start = skip_whitespace(bytes, pos + 1);
+        let value_end = skip_json_value(bytes, value_start)?;
+        if found {
+            return Some(&json[value_start..value_end]);
+        }
+        pos = skip_whitespace(bytes, value_end);
+        match bytes.get(pos) {
+            Some(b',') => pos += 1,
+            _ => return None,
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut pos: usize) -> usize {
+    while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+        pos += 1;
+    }
+    pos
+}
+
+/// Index just past the JSON value starting at `pos`
+fn skip_json_value(bytes: &[u8], mut pos: usize) -> Option<usize> {
+    let start = pos;
+    // Nesting depth of objects and arrays, a scalar ends at depth 0
+    let mut depth = 0usize;
+    let mut in_string = false;
+    loop {
+        let byte = *bytes.get(pos)?;
+        pos += 1;
+        if in_string {
+            match byte {
+                b'\\' => pos += 1,
+                b'"' => {
+                    in_string = false;
+                    if depth == 0 {
+                        return Some(pos);
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(pos);
+                }
+            }
+            // End of a number or literal
+            b',' | b'}' | b']' | b' ' | b'\t' | b'\r' | b'\n' if depth == 0 => {
+                return (pos - 1 > start).then_some(pos - 1);
+            }
+            _ => {}
+        }
+        if depth == 0 && !in_string && pos == bytes.len() {
+            return Some(pos);
+        }
+    }
+}
+
+/// Get the string value of `key` in a JSON object, with escapes decoded
+/// Returns `None` if the key is missing or its value is not a string
+pub fn extract_json_string(json: &str, key: &str) -> Option<String> {
+    let raw = json_value(json, key)?
+        .strip_prefix('"')?
+        .strip_suffix('"')?;
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next()? {
+            '"' => '"',
+            '\\' => '\\',
+            '/' => '/',
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let hex: String = chars.by_ref().take(4).collect();
+                // Surrogate pairs are not supported
+                char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+            }
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+/// Get the value of `key` in a JSON object as an unsigned integer
+/// Returns `None` if the key is missing and `Some(Err)` if its value is not a
+/// plain integer
+pub fn extract_json_u64(json: &str, key: &str) -> Option<Result<u64, ()>> {
+    let raw = json_value(json, key)?;
+    if !raw.bytes().all(|b| b.is_ascii_digit()) {
+        return Some(Err(()));
+    }
+    Some(raw.parse().map_err(|_| ()))
+}
+
+/// Like `extract_json_u64`, for values that must fit a u32
+pub fn extract_json_u32(json: &str, key: &str) -> Option<Result<u32, ()>> {
+    extract_json_u64(json, key).map(|n| n.and_then(|n| u32::try_from(n).map_err(|_| ())))
+}
+
+/// Get the value of `key` in a JSON object as a number, fractions allowed
+/// Returns `None` if the key is missing and `Some(Err)` if its value is not a
+/// number
+pub fn extract_json_f32(json: &str, key: &str) -> Option<Result<f32, ()>> {
+    let raw = json_value(json, key)?;
+    // Rules out the `inf` and `NaN` Rust would parse but JSON doesn't have
+    if !raw
+        .bytes()
+        .all(|b| b.is_ascii_digit() || matches!(b, b'-' | b'+' | b'.' | b'e' | b'E'))
+    {
+        return Some(Err(()));
+    }
+    Some(raw.parse().map_err(|_| ()))
+}
+
+/// Get the value of `key` in a JSON object as a bool
+/// Returns `None` if the key is missing and `Some(Err)` if its value is not
+/// `true` or `false`
+#[cfg(feature = "game")]
+pub fn extract_json_bool(json: &str, key: &str) -> Option<Result<bool, ()>> {
+    match json_value(json, key)? {
+        "true" => Some(Ok(true)),
+        "false" => Some(Ok(false)),
+        _ => Some(Err(())),
+    }
+}
+
+/// Check an `If-None-Match` header value against an ETag (including quotes)
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        // Weak comparison is enough for a GET, so ignore the `W/` prefix
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// Decode a percent-encoded URL component, with `+` standing for a space
+/// Invalid `%` sequences are kept as they are
+pub fn url_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(core::str::from_utf8(hex).ok()?, 16).ok());
+                if let Some(byte) = hex {
+                    out.push(byte);
+                    i += 2;
+                } else {
+                    out.push(b'%');
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Split a query string like `a=1&b=x%20y` into decoded keys and values
+/// A key without `=` maps to an empty value, a repeated key keeps the last value
+pub fn parse_query_string(query: &str) -> BTreeMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (url_decode(key), url_decode(value))
+        })
+        .collect()
+}
+
+/// Decoded query parameters of a request URI
+pub fn query_params(uri: &str) -> BTreeMap<String, String> {
+    uri.split_once('?')
+        .map(|(_, query)| parse_query_string(query))
+        .unwrap_or_default()
+}
+
+/// 32-bit FNV-1a hash, usable in const context
+pub const fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+/// `fnv1a` of a string's UTF-8 bytes
+pub const fn fnv1a_str(s: &str) -> u32 {
+    fnv1a(s.as_bytes())
+}
+
+/// Bytes taken by the CRC-32 in front of a checked payload
+pub const CRC32_LEN: usize = 4;
+
+/// Lookup table for the reflected CRC-32 polynomial 0xEDB88320
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32/ISO-HDLC, the checksum used by zlib, PNG and Ethernet
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Fill the first `CRC32_LEN` bytes of `data` with the CRC-32 (LE) of the rest
+pub fn write_crc32(data: &mut [u8]) {
+    let crc = crc32(&data[CRC32_LEN..]);
+    data[..CRC32_LEN].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Payload following a leading CRC-32 (LE), `None` if it doesn't match
+pub fn check_crc32(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < CRC32_LEN {
+        return None;
+    }
+    let (crc, payload) = data.split_at(CRC32_LEN);
+    (u32::from_le_bytes(crc.try_into().ok()?) == crc32(payload)).then_some(payload)
+}
+
+/// Format a MAC address as `AA:BB:CC:DD:EE:FF`
+pub fn parse_mac_address(mac: &[u8; 6]) -> String {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut out = [b':'; 17];
+    for (i, byte) in mac.iter().enumerate() {
+        out[i * 3] = HEX[(byte >> 4) as usize];
+        out[i * 3 + 1] = HEX[(byte & 0xf) as usize];
+    }
+    // Only ASCII hex digits and colons were written
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Escape a string for embedding inside a JSON string literal
+pub fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Format a duration like `2d 3h 15m 42s`, leaving out zero components
+pub fn format_duration(seconds: u64) -> String {
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+    let components = [
+        (seconds / 86_400, "d"),
+        (seconds / 3600 % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let parts: Vec<String> = components
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    parts.join(" ")
+}
+
+/// Format seconds since the epoch as an HTTP date, e.g. for `Last-Modified`
+/// Without SNTP the system time starts at the epoch on every boot.
+pub fn http_date(unix_s: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = unix_s / 86_400;
+    let seconds = unix_s % 86_400;
+    // Civil date from days since the epoch, with years starting in March so
+    // the leap day comes last (Howard Hinnant's `civil_from_days`)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = (month_index + 2) % 12;
+    let year = era * 400 + year_of_era + u64::from(month < 2);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize],
+        year,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
 /// Convert a number to its ordinal form (1st, 2nd, 3rd, etc.)
+#[cfg(feature = "game")]
 pub fn nth(n: u32) -> Cow<'static, str> {
     let result = match n {
         smaller @ (0..=13) => Cow::Borrowed(match smaller {
@@ -35,11 +473,15 @@ pub fn nth(n: u32) -> Cow<'static, str> {
             13 => "13th",
             _ => unreachable!(),
         }),
-        larger => Cow::Owned(match larger % 10 {
-            1 => format!("{larger}st"),
-            2 => format!("{larger}nd"),
-            3 => format!("{larger}rd"),
-            _ => format!("{larger}th"),
+        // 111th, 1012th, ... follow the teen rule like 11th-13th
+        larger => Cow::Owned(match larger % 100 {
+            11..=13 => format!("{larger}th"),
+            _ => match larger % 10 {
+                1 => format!("{larger}st"),
+                2 => format!("{larger}nd"),
+                3 => format!("{larger}rd"),
+                _ => format!("{larger}th"),
+            },
         }),
     };
     debug!("Converted {} to ordinal: {}", n, result);
@@ -48,9 +490,86 @@ pub fn nth(n: u32) -> Cow<'static, str> {
 
 #[cfg(test)]
 mod tests {
-   
+    use super::*;
 
     #[test]
+    fn test_generate_request_id() {
+        let id = generate_request_id();
+        assert_eq!(id.len(), 8);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(60), "1m");
+        assert_eq!(format_duration(90), "1m 30s");
+        assert_eq!(format_duration(3599), "59m 59s");
+        assert_eq!(format_duration(3600), "1h");
+        assert_eq!(format_duration(86_399), "23h 59m 59s");
+        assert_eq!(format_duration(86_400), "1d");
+        assert_eq!(
+            format_duration(2 * 86_400 + 3 * 3600 + 15 * 60 + 42),
+            "2d 3h 15m 42s"
+        );
+        assert_eq!(format_duration(45 * 86_400 + 5), "45d 5s");
+    }
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(1_700_000_000), "Tue, 14 Nov 2023 22:13:20 GMT");
+        // Leap day of a year divisible by 400
+        assert_eq!(http_date(951_782_400), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_crc32_prefix() {
+        let mut data = [0, 0, 0, 0, 1, 2, 3];
+        write_crc32(&mut data);
+        assert_eq!(check_crc32(&data), Some(&[1, 2, 3][..]));
+        data[5] ^= 1;
+        assert_eq!(check_crc32(&data), None);
+        assert_eq!(check_crc32(&[0, 0, 0]), None);
+        assert_eq!(check_crc32(&[0, 0, 0, 0]), Some(&[][..]));
+    }
+
+    #[test]
+    fn test_url_decode() {
+        assert_eq!(url_decode("AA%3ABB%3acc"), "AA:BB:cc");
+        assert_eq!(url_decode("hello+world%21"), "hello world!");
+        assert_eq!(url_decode("caf%C3%A9"), "café");
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_decode("%zz%4"), "%zz%4");
+        assert_eq!(url_decode(""), "");
+    }
+
+    #[test]
+    fn test_parse_query_string() {
+        let params = parse_query_string("duration_s=120&flag&station=AA%3ABB&x=1&x=2&");
+        assert_eq!(params.len(), 4);
+        assert_eq!(params["duration_s"], "120");
+        assert_eq!(params["flag"], "");
+        assert_eq!(params["station"], "AA:BB");
+        assert_eq!(params["x"], "2");
+        assert!(parse_query_string("").is_empty());
+
+        assert_eq!(
+            query_params("/rssi/export.csv?duration_s=5")["duration_s"],
+            "5"
+        );
+        assert!(query_params("/rssi/export.csv").is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "game")]
     fn test_nth_small_numbers() {
         assert_eq!(nth(1), "first");
         assert_eq!(nth(2), "second");
@@ -58,11 +577,149 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "game")]
     fn test_nth_larger_numbers() {
         assert_eq!(nth(21), "21st");
         assert_eq!(nth(22), "22nd");
         assert_eq!(nth(23), "23rd");
         assert_eq!(nth(24), "24th");
+        assert_eq!(nth(101), "101st");
+        assert_eq!(nth(1001), "1001st");
     }
-}
 
+    #[test]
+    #[cfg(feature = "game")]
+    fn test_nth_teens_in_hundreds_and_thousands() {
+        for n in [11, 12, 13, 111, 112, 113, 1011, 1012, 1013] {
+            assert_eq!(nth(n), format!("{n}th"));
+        }
+    }
+
+    #[test]
+    fn test_parse_mac_address() {
+        assert_eq!(parse_mac_address(&[0; 6]), "00:00:00:00:00:00");
+        assert_eq!(parse_mac_address(&[0xff; 6]), "FF:FF:FF:FF:FF:FF");
+        assert_eq!(
+            parse_mac_address(&[0x24, 0x0a, 0xc4, 0x1b, 0x9e, 0x07]),
+            "24:0A:C4:1B:9E:07"
+        );
+    }
+
+    #[test]
+    fn test_retry_returns_first_ok() {
+        let mut calls = 0;
+        let result: Result<u32, &str> = retry(
+            || {
+                calls += 1;
+                if calls < 3 {
+                    Err("timeout")
+                } else {
+                    Ok(calls)
+                }
+            },
+            5,
+            0,
+        );
+        assert_eq!(result, Ok(3));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_retry_returns_last_err() {
+        let mut calls = 0;
+        let result: Result<(), u32> = retry(
+            || {
+                calls += 1;
+                Err(calls)
+            },
+            4,
+            0,
+        );
+        assert_eq!(result, Err(4));
+        // A single attempt is still made with max_attempts of 0
+        assert_eq!(retry(|| Err::<(), _>("fail"), 0, 0), Err("fail"));
+    }
+
+    #[test]
+    fn test_fnv1a_known_values() {
+        assert_eq!(fnv1a(b""), 0x811c_9dc5);
+        assert_eq!(fnv1a(b"a"), 0xe40c_292c);
+        assert_eq!(fnv1a(b"foobar"), 0xbf9c_f968);
+        assert_eq!(fnv1a_str("foobar"), 0xbf9c_f968);
+    }
+
+    #[test]
+    fn test_extract_json_string() {
+        let json = r#"{"token": "secret", "message":"say \"hi\"\n\u00e9"}"#;
+        assert_eq!(
+            extract_json_string(json, "token").as_deref(),
+            Some("secret")
+        );
+        assert_eq!(
+            extract_json_string(json, "message").as_deref(),
+            Some("say \"hi\"\né")
+        );
+        assert_eq!(extract_json_string(json, "missing"), None);
+        assert_eq!(extract_json_string(r#"{"n":5}"#, "n"), None);
+        assert_eq!(extract_json_string(r#"{"s":"bad \q"}"#, "s"), None);
+    }
+
+    #[test]
+    fn test_extract_json_skips_nested_keys() {
+        let json =
+            r#"{"message":"\"token\":\"x\"","nested":{"token":"y"},"list":[1,"]"],"token":"z"}"#;
+        assert_eq!(extract_json_string(json, "token").as_deref(), Some("z"));
+        assert_eq!(extract_json_string("not json", "token"), None);
+        assert_eq!(extract_json_string(r#"{"token""#, "token"), None);
+        assert_eq!(extract_json_string(r#"{""#, "token"), None);
+        assert_eq!(extract_json_string(r#"{"token":""#, "token"), None);
+    }
+
+    #[test]
+    fn test_extract_json_u64() {
+        let json = r#"{ "delay_s" : 30 , "neg": -5, "frac": 1.5, "text": "7", "last":12}"#;
+        assert_eq!(extract_json_u64(json, "delay_s"), Some(Ok(30)));
+        assert_eq!(extract_json_u64(json, "last"), Some(Ok(12)));
+        assert_eq!(extract_json_u64(json, "neg"), Some(Err(())));
+        assert_eq!(extract_json_u64(json, "frac"), Some(Err(())));
+        assert_eq!(extract_json_u64(json, "text"), Some(Err(())));
+        assert_eq!(extract_json_u64(json, "missing"), None);
+        assert_eq!(
+            extract_json_u64(r#"{"big":99999999999999999999}"#, "big"),
+            Some(Err(()))
+        );
+        assert_eq!(extract_json_u32(r#"{"n":4294967295}"#, "n"), Some(Ok(u32::MAX)));
+        assert_eq!(extract_json_u32(r#"{"n":4294967296}"#, "n"), Some(Err(())));
+    }
+
+    #[test]
+    fn test_extract_json_f32() {
+        let json = r#"{"exp":3.5,"rssi":-35,"sci":1e2,"word":"2"}"#;
+        assert_eq!(extract_json_f32(json, "exp"), Some(Ok(3.5)));
+        assert_eq!(extract_json_f32(json, "rssi"), Some(Ok(-35.0)));
+        assert_eq!(extract_json_f32(json, "sci"), Some(Ok(100.0)));
+        assert_eq!(extract_json_f32(json, "word"), Some(Err(())));
+        assert_eq!(extract_json_f32(r#"{"n":NaN}"#, "n"), Some(Err(())));
+        assert_eq!(extract_json_f32(json, "missing"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "game")]
+    fn test_extract_json_bool() {
+        let json = r#"{"close":true,"open":false,"text":"true"}"#;
+        assert_eq!(extract_json_bool(json, "close"), Some(Ok(true)));
+        assert_eq!(extract_json_bool(json, "open"), Some(Ok(false)));
+        assert_eq!(extract_json_bool(json, "text"), Some(Err(())));
+        assert_eq!(extract_json_bool(json, "missing"), None);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("\"abc\"", "\"abc\""));
+        assert!(etag_matches("W/\"abc\"", "\"abc\""));
+        assert!(etag_matches("\"x\", \"abc\"", "\"abc\""));
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(!etag_matches("\"abd\"", "\"abc\""));
+        assert!(!etag_matches("abc", "\"abc\""));
+    }
+}
@@ -0,0 +1,142 @@
+//! Scan for nearby access points for GET /wifi/scan
+//!
+//! ESP-IDF only scans with a station interface, so this works in mixed mode
+//! only. The radio leaves the access point's channel while it listens on the
+//! others, so clients of the AP may miss a few packets during a scan.
+
+use core::cmp::Reverse;
+use esp_idf_svc::sys::{self, EspError};
+
+use crate::config::MAX_SCAN_RESULTS;
+use crate::utils::json_escape;
+
+/// One access point seen by a scan
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScannedAp {
+    pub ssid: String,
+    pub channel: u8,
+    pub rssi: i8,
+    pub auth: &'static str,
+}
+
+impl ScannedAp {
+    fn from_record(record: &sys::wifi_ap_record_t) -> Self {
+        let len = record
+            .ssid
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(record.ssid.len());
+        Self {
+            ssid: String::from_utf8_lossy(&record.ssid[..len]).into_owned(),
+            channel: record.primary,
+            rssi: record.rssi,
+            auth: auth_name(record.authmode),
+        }
+    }
+}
+
+/// Scan all channels, blocking until the scan is done
+pub fn scan() -> Result<Vec<ScannedAp>, EspError> {
+    let config = sys::wifi_scan_config_t {
+        show_hidden: true,
+        ..Default::default()
+    };
+    // SAFETY: the config is only read during the call, which blocks until the
+    // scan is done
+    EspError::convert(unsafe { sys::esp_wifi_scan_start(&config, true) })?;
+    let mut count: u16 = 0;
+    // SAFETY: only writes the number of records found
+    EspError::convert(unsafe { sys::esp_wifi_scan_get_ap_num(&mut count) })?;
+    let mut records = vec![sys::wifi_ap_record_t::default(); count as usize];
+    // SAFETY: `records` has room for `count` entries, and `count` is updated
+    // to the number actually written
+    EspError::convert(unsafe {
+        sys::esp_wifi_scan_get_ap_records(&mut count, records.as_mut_ptr())
+    })?;
+    records.truncate(count as usize);
+    Ok(records.iter().map(ScannedAp::from_record).collect())
+}
+
+fn auth_name(mode: sys::wifi_auth_mode_t) -> &'static str {
+    match mode {
+        sys::wifi_auth_mode_t_WIFI_AUTH_OPEN => "open",
+        sys::wifi_auth_mode_t_WIFI_AUTH_WEP => "WEP",
+        sys::wifi_auth_mode_t_WIFI_AUTH_WPA_PSK => "WPA",
+        sys::wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK => "WPA2",
+        sys::wifi_auth_mode_t_WIFI_AUTH_WPA_WPA2_PSK => "WPA/WPA2",
+        sys::wifi_auth_mode_t_WIFI_AUTH_WPA2_ENTERPRISE => "WPA2-Enterprise",
+        sys::wifi_auth_mode_t_WIFI_AUTH_WPA3_PSK => "WPA3",
+        sys::wifi_auth_mode_t_WIFI_AUTH_WPA2_WPA3_PSK => "WPA2/WPA3",
+        _ => "other",
+    }
+}
+
+/// The `MAX_SCAN_RESULTS` strongest access points as a JSON array
+pub fn to_json(mut aps: Vec<ScannedAp>) -> String {
+    aps.sort_by_key(|ap| Reverse(ap.rssi));
+    aps.truncate(MAX_SCAN_RESULTS);
+    let entries: Vec<String> = aps
+        .iter()
+        .map(|ap| {
+            format!(
+                r#"{{"ssid":"{}","channel":{},"rssi":{},"auth":"{}"}}"#,
+                json_escape(&ap.ssid),
+                ap.channel,
+                ap.rssi,
+                ap.auth
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ap(ssid: &str, rssi: i8) -> ScannedAp {
+        ScannedAp {
+            ssid: ssid.to_string(),
+            channel: 6,
+            rssi,
+            auth: "WPA2",
+        }
+    }
+
+    #[test]
+    fn test_from_record() {
+        let mut record = sys::wifi_ap_record_t::default();
+        record.ssid[..4].copy_from_slice(b"cafe");
+        record.primary = 11;
+        record.rssi = -72;
+        record.authmode = sys::wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK;
+        assert_eq!(
+            ScannedAp::from_record(&record),
+            ScannedAp {
+                ssid: "cafe".to_string(),
+                channel: 11,
+                rssi: -72,
+                auth: "WPA2",
+            }
+        );
+    }
+
+    #[test]
+    fn test_to_json_sorts_by_rssi() {
+        let json = to_json(vec![ap("far", -80), ap("near", -40), ap("q\"uote", -60)]);
+        assert_eq!(
+            json,
+            r#"[{"ssid":"near","channel":6,"rssi":-40,"auth":"WPA2"},{"ssid":"q\"uote","channel":6,"rssi":-60,"auth":"WPA2"},{"ssid":"far","channel":6,"rssi":-80,"auth":"WPA2"}]"#
+        );
+        assert_eq!(to_json(Vec::new()), "[]");
+    }
+
+    #[test]
+    fn test_to_json_keeps_strongest() {
+        let aps = (0..30).map(|i| ap("ap", -30 - i)).collect();
+        let json = to_json(aps);
+        assert_eq!(json.matches("\"ssid\"").count(), MAX_SCAN_RESULTS);
+        assert!(json.contains("\"rssi\":-30,"));
+        assert!(!json.contains("\"rssi\":-50,"));
+    }
+}
@@ -0,0 +1,124 @@
+//! Word guessing game played over the /ws/wordguess WebSocket
+//!
+//! Each session gets a secret word from `WORDS`. Every guess is answered
+//! with a Wordle-style hint: `O` for a letter in the right position, `X` for
+//! a letter that is in the word elsewhere, `_` for a letter that isn't.
+
+use log::*;
+
+use crate::config::{WORDS, WORD_LEN};
+use crate::utils::rand;
+
+const CORRECT: u8 = b'O';
+const MISPLACED: u8 = b'X';
+const ABSENT: u8 = b'_';
+
+/// Word game state for a single session
+pub struct WordGuess {
+    secret: &'static str,
+    attempts: u32,
+    solved: bool,
+}
+
+impl WordGuess {
+    /// Start a game with a random word from the list
+    pub fn new() -> Self {
+        Self::with_secret(WORDS[rand() as usize % WORDS.len()])
+    }
+
+    fn with_secret(secret: &'static str) -> Self {
+        debug!("Word game secret `{}`", secret);
+        Self {
+            secret,
+            attempts: 0,
+            solved: false,
+        }
+    }
+
+    /// Check a guess and return its hint, e.g. `XOOX_`
+    /// Returns `None` for anything but `WORD_LEN` ASCII letters, which
+    /// doesn't count as an attempt. Case is ignored.
+    pub fn check(&mut self, guess: &str) -> Option<String> {
+        let guess = guess.trim().trim_end_matches('\0').to_ascii_lowercase();
+        if guess.len() != WORD_LEN || !guess.bytes().all(|b| b.is_ascii_lowercase()) {
+            return None;
+        }
+        self.attempts += 1;
+        let hint = hint(self.secret.as_bytes(), guess.as_bytes());
+        self.solved = hint.iter().all(|&b| b == CORRECT);
+        // Only ASCII marks were written
+        Some(String::from_utf8(hint).unwrap())
+    }
+
+    pub fn is_solved(&self) -> bool {
+        self.solved
+    }
+
+    /// Message sent once the word was found
+    pub fn win_message(&self) -> String {
+        format!("You found `{}` in {} guesses!", self.secret, self.attempts)
+    }
+}
+
+/// Mark each letter of `guess` against `secret`
+/// Exact matches are marked first, so a repeated letter is only marked as
+/// misplaced as often as it is left over in the secret.
+fn hint(secret: &[u8], guess: &[u8]) -> Vec<u8> {
+    let mut marks = vec![ABSENT; guess.len()];
+    // Letters of the secret not matched in place, counted per letter
+    let mut unmatched = [0u8; 26];
+    for (i, (&s, &g)) in secret.iter().zip(guess).enumerate() {
+        if s == g {
+            marks[i] = CORRECT;
+        } else {
+            unmatched[(s - b'a') as usize] += 1;
+        }
+    }
+    for (mark, &g) in marks.iter_mut().zip(guess) {
+        let left = &mut unmatched[(g - b'a') as usize];
+        if *mark == ABSENT && *left > 0 {
+            *mark = MISPLACED;
+            *left -= 1;
+        }
+    }
+    marks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hint() {
+        assert_eq!(hint(b"apple", b"apple"), b"OOOOO");
+        assert_eq!(hint(b"apple", b"plead"), b"XXXX_");
+        assert_eq!(hint(b"tiger", b"stone"), b"_X__X");
+        assert_eq!(hint(b"water", b"ocean"), b"__XX_");
+    }
+
+    #[test]
+    fn test_hint_repeated_letters() {
+        // Only one `p` is left over after the exact match
+        assert_eq!(hint(b"apple", b"puppy"), b"X_O__");
+        assert_eq!(hint(b"eagle", b"eerie"), b"O___O");
+    }
+
+    #[test]
+    fn test_check() {
+        let mut game = WordGuess::with_secret("tiger");
+        assert_eq!(game.check("toy"), None);
+        assert_eq!(game.check("ti9er"), None);
+        assert_eq!(game.check("Timer\n").as_deref(), Some("OO_OO"));
+        assert!(!game.is_solved());
+        assert_eq!(game.check("tiger").as_deref(), Some("OOOOO"));
+        assert!(game.is_solved());
+        assert_eq!(game.win_message(), "You found `tiger` in 2 guesses!");
+    }
+
+    #[test]
+    fn test_new_picks_listed_word() {
+        let game = WordGuess::new();
+        assert!(WORDS.contains(&game.secret));
+        assert_eq!(WORDS.len(), 30);
+    }
+}
@@ -0,0 +1,172 @@
+//! Helpers for pushing frames to several WebSocket sessions at once
+
+use embedded_svc::ws::{FrameType, Sender};
+use log::*;
+
+#[cfg(feature = "game")]
+use crate::config::BROADCAST_STACK_SIZE;
+
+/// Send a text message to every session
+/// A failing session does not stop delivery to the others; the errors are
+/// returned per session instead
+///
+/// NOTE: detached senders block until the HTTP server task has sent the
+/// frame, so this must not be called from an HTTP or WebSocket handler.
+pub fn broadcast<S: Sender>(senders: &mut [(i32, S)], message: &str) -> Vec<(i32, S::Error)> {
+    let mut errors = Vec::new();
+    for (session, sender) in senders.iter_mut() {
+        match sender.send(FrameType::Text(false), message.as_bytes()) {
+            Ok(()) => debug!("Broadcast delivered to session {}", session),
+            Err(e) => {
+                warn!("Broadcast to session {} failed: {:?}", session, e);
+                errors.push((*session, e));
+            }
+        }
+    }
+    info!(
+        "Broadcast sent to {} of {} sessions",
+        senders.len() - errors.len(),
+        senders.len()
+    );
+    errors
+}
+
+/// Run `broadcast` on a short-lived thread
+/// Detached senders block until the HTTP server task has sent the frame, so
+/// handlers (which run on that task) must deliver through this instead
+#[cfg(feature = "game")]
+pub fn spawn_broadcast<S>(mut senders: Vec<(i32, S)>, message: String) -> std::io::Result<()>
+where
+    S: Sender + Send + 'static,
+{
+    std::thread::Builder::new()
+        .name("ws_broadcast".into())
+        .stack_size(BROADCAST_STACK_SIZE)
+        .spawn(move || {
+            broadcast(&mut senders, &message);
+        })
+        .map(|_| ())
+}
+
+/// Run `broadcast` on a short-lived thread like `spawn_broadcast`, then send
+/// a Close frame to every session the message reached
+#[cfg(feature = "game")]
+pub fn spawn_broadcast_and_close<S>(
+    mut senders: Vec<(i32, S)>,
+    message: String,
+) -> std::io::Result<()>
+where
+    S: Sender + Send + 'static,
+{
+    std::thread::Builder::new()
+        .name("ws_broadcast".into())
+        .stack_size(BROADCAST_STACK_SIZE)
+        .spawn(move || {
+            broadcast_and_close(&mut senders, &message);
+        })
+        .map(|_| ())
+}
+
+/// Send `message` to every session, then close the ones it reached
+/// Sessions whose send failed are already gone and are skipped
+#[cfg(feature = "game")]
+fn broadcast_and_close<S: Sender>(senders: &mut [(i32, S)], message: &str) {
+    let failed: Vec<i32> = broadcast(senders, message)
+        .into_iter()
+        .map(|(session, _)| session)
+        .collect();
+    for (session, sender) in senders.iter_mut() {
+        if failed.contains(session) {
+            continue;
+        }
+        if let Err(e) = sender.send(FrameType::Close, &[]) {
+            warn!("Failed to close session {}: {:?}", session, e);
+        }
+    }
+}
+
+/// Tell a session why it is being closed and send a Close frame, on a
+/// short-lived thread like `spawn_broadcast`
+#[cfg(feature = "game")]
+pub fn spawn_close<S>(session: i32, mut sender: S, reason: String) -> std::io::Result<()>
+where
+    S: Sender + Send + 'static,
+{
+    std::thread::Builder::new()
+        .name("ws_close".into())
+        .stack_size(BROADCAST_STACK_SIZE)
+        .spawn(move || {
+            let sent = sender
+                .send(FrameType::Text(false), reason.as_bytes())
+                .and_then(|()| sender.send(FrameType::Close, &[]));
+            if let Err(e) = sent {
+                warn!("Failed to close session {}: {:?}", session, e);
+            }
+        })
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_svc::ws::ErrorType;
+
+    struct MockSender {
+        fail: bool,
+        sent: Vec<Vec<u8>>,
+        closed: bool,
+    }
+
+    impl ErrorType for MockSender {
+        type Error = &'static str;
+    }
+
+    impl Sender for MockSender {
+        fn send(&mut self, frame_type: FrameType, frame_data: &[u8]) -> Result<(), Self::Error> {
+            if self.fail {
+                return Err("closed");
+            }
+            if let FrameType::Close = frame_type {
+                self.closed = true;
+                return Ok(());
+            }
+            self.sent.push(frame_data.to_vec());
+            Ok(())
+        }
+    }
+
+    fn mock(fail: bool) -> MockSender {
+        MockSender {
+            fail,
+            sent: Vec::new(),
+            closed: false,
+        }
+    }
+
+    #[test]
+    fn test_broadcast_reaches_every_session() {
+        let mut senders = [(1, mock(false)), (2, mock(false))];
+        assert!(broadcast(&mut senders, "hello").is_empty());
+        for (_, sender) in &senders {
+            assert_eq!(sender.sent, vec![b"hello".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn test_broadcast_continues_past_failures() {
+        let mut senders = [(1, mock(true)), (2, mock(false)), (3, mock(true))];
+        let errors = broadcast(&mut senders, "bye");
+        assert_eq!(errors, vec![(1, "closed"), (3, "closed")]);
+        assert_eq!(senders[1].1.sent.len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "game")]
+    fn test_broadcast_and_close() {
+        let mut senders = [(1, mock(false)), (2, mock(true))];
+        broadcast_and_close(&mut senders, "maintenance");
+        assert_eq!(senders[0].1.sent, vec![b"maintenance".to_vec()]);
+        assert!(senders[0].1.closed);
+        assert!(!senders[1].1.closed);
+    }
+}